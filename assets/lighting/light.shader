@@ -0,0 +1,70 @@
+// One light drawn additively into the light buffer. The geometry is the
+// part of the light's circle (or cone) it can see, in world space; the
+// falloff, cone edges and normal mapping happen here.
+#uniform sampler2D normals = 0
+#uniform vec2 resolution = 800.0 600.0
+#uniform vec2 light_pos = 0.0 0.0
+#uniform vec3 light_color = 1.0 1.0 1.0
+#uniform float radius = 100.0
+#uniform float falloff = 1.0
+#uniform float height = 32.0
+#uniform vec2 cone_direction = 1.0 0.0
+#uniform float cone_cos = -2.0
+#uniform float cone_edge = 0.0
+#uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+
+#stage vertex
+#version 330 core
+
+layout (location = 0) in vec2 pos;
+layout (location = 1) in vec2 uv;
+layout (location = 2) in vec4 vertexColor;
+
+out vec2 WorldPos;
+
+uniform mat4 model;
+uniform mat4 projection;
+
+void main() {
+    WorldPos = pos;
+    gl_Position = projection * model * vec4(pos, 0.0, 1.0);
+}
+
+#stage fragment
+#version 330 core
+
+in vec2 WorldPos;
+out vec4 color;
+
+uniform sampler2D normals;
+uniform vec2 resolution;
+uniform vec2 light_pos;
+uniform vec3 light_color;
+uniform float radius;
+uniform float falloff;
+uniform float height;
+uniform vec2 cone_direction;
+// Cosine of the cone's half angle (below -1 for point lights), and how far
+// inside of it the edge fades out
+uniform float cone_cos;
+uniform float cone_edge;
+uniform vec4 spriteColor;
+
+void main() {
+    vec2 to_pixel = WorldPos - light_pos;
+    float dist = length(to_pixel);
+    float attenuation = pow(clamp(1.0 - dist / radius, 0.0, 1.0), falloff);
+
+    if (cone_cos >= -1.0) {
+        float c = dot(to_pixel / max(dist, 0.0001), cone_direction);
+        attenuation *= smoothstep(cone_cos, cone_cos + cone_edge, c);
+    }
+
+    // Normal maps point +y up, the world's y axis points down
+    vec3 normal = texture(normals, gl_FragCoord.xy / resolution).xyz * 2.0 - 1.0;
+    normal.y = -normal.y;
+    vec3 to_light = normalize(vec3(-to_pixel, height));
+    float diffuse = max(dot(normalize(normal), to_light), 0.0);
+
+    color = spriteColor * vec4(light_color * attenuation * diffuse, 1.0);
+}
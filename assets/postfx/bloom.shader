@@ -0,0 +1,49 @@
+// Bloom in three kinds of passes chosen by `mode`: 0 keeps what's brighter
+// than `threshold`, 1 is one direction of a 9-tap gaussian blur and 2 adds
+// the blurred highlights (in `bloom`) back onto the scene
+#uniform sampler2D image = 0
+#uniform sampler2D bloom = 1
+#uniform int mode = 0
+#uniform float threshold = 0.7
+#uniform float intensity = 1.0
+#uniform vec2 direction = 0.0 0.0
+#uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+
+#stage vertex
+#version 330 core
+#include "postfx/fullscreen.glsl"
+
+#stage fragment
+#version 330 core
+
+in vec2 TexCoords;
+out vec4 color;
+
+uniform sampler2D image;
+uniform sampler2D bloom;
+uniform int mode;
+uniform float threshold;
+uniform float intensity;
+uniform vec2 direction;
+uniform vec4 spriteColor;
+
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main() {
+    if (mode == 0) {
+        vec3 scene = texture(image, TexCoords).rgb;
+        float brightness = max(scene.r, max(scene.g, scene.b));
+        float keep = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+        color = vec4(scene * keep, 1.0);
+    } else if (mode == 1) {
+        vec3 sum = texture(image, TexCoords).rgb * weights[0];
+        for (int i = 1; i < 5; i++) {
+            sum += texture(image, TexCoords + direction * float(i)).rgb * weights[i];
+            sum += texture(image, TexCoords - direction * float(i)).rgb * weights[i];
+        }
+        color = vec4(sum, 1.0);
+    } else {
+        vec4 scene = texture(image, TexCoords);
+        color = spriteColor * vec4(scene.rgb + intensity * texture(bloom, TexCoords).rgb, scene.a);
+    }
+}
@@ -0,0 +1,39 @@
+// Colour grading through a lookup table: an N*N x N strip of N slices,
+// blue selecting the slice, red across and green down each slice
+#uniform sampler2D image = 0
+#uniform sampler2D lut = 1
+#uniform float strength = 1.0
+#uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+
+#stage vertex
+#version 330 core
+#include "postfx/fullscreen.glsl"
+
+#stage fragment
+#version 330 core
+
+in vec2 TexCoords;
+out vec4 color;
+
+uniform sampler2D image;
+uniform sampler2D lut;
+uniform float strength;
+uniform vec4 spriteColor;
+
+vec3 lookup(vec3 c) {
+    float n = float(textureSize(lut, 0).y);
+    float slice = c.b * (n - 1.0);
+    float slice0 = floor(slice);
+    float slice1 = min(slice0 + 1.0, n - 1.0);
+    float u = c.r * (n - 1.0) + 0.5;
+    float v = (c.g * (n - 1.0) + 0.5) / n;
+    vec3 a = texture(lut, vec2((slice0 * n + u) / (n * n), v)).rgb;
+    vec3 b = texture(lut, vec2((slice1 * n + u) / (n * n), v)).rgb;
+    return mix(a, b, slice - slice0);
+}
+
+void main() {
+    vec4 scene = texture(image, TexCoords);
+    vec3 graded = lookup(clamp(scene.rgb, 0.0, 1.0));
+    color = spriteColor * vec4(mix(scene.rgb, graded, strength), scene.a);
+}
@@ -0,0 +1,45 @@
+// Old monitor look: barrel distortion, scanlines and a slight colour
+// fringe. `resolution` is the target size in pixels.
+#uniform sampler2D image = 0
+#uniform float curvature = 0.1
+#uniform float scanlines = 0.3
+#uniform float line_count = 240.0
+#uniform float aberration = 1.0
+#uniform vec2 resolution = 800.0 600.0
+#uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+
+#stage vertex
+#version 330 core
+#include "postfx/fullscreen.glsl"
+
+#stage fragment
+#version 330 core
+
+in vec2 TexCoords;
+out vec4 color;
+
+uniform sampler2D image;
+uniform float curvature;
+uniform float scanlines;
+uniform float line_count;
+uniform float aberration;
+uniform vec2 resolution;
+uniform vec4 spriteColor;
+
+void main() {
+    vec2 centered = TexCoords * 2.0 - 1.0;
+    centered *= 1.0 + curvature * dot(centered.yx, centered.yx);
+    vec2 uv = centered * 0.5 + 0.5;
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec2 offset = vec2(aberration / resolution.x, 0.0);
+    vec3 scene = vec3(texture(image, uv + offset).r,
+                      texture(image, uv).g,
+                      texture(image, uv - offset).b);
+    float line = 0.5 + 0.5 * sin(uv.y * line_count * 6.2831853);
+    scene *= 1.0 - scanlines * (1.0 - line);
+    color = spriteColor * vec4(scene, 1.0);
+}
@@ -0,0 +1,25 @@
+// Blends the scene towards a flat colour, `amount` 1 being fully faded
+#uniform sampler2D image = 0
+#uniform vec3 fade_color = 0.0 0.0 0.0
+#uniform float amount = 0.0
+#uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+
+#stage vertex
+#version 330 core
+#include "postfx/fullscreen.glsl"
+
+#stage fragment
+#version 330 core
+
+in vec2 TexCoords;
+out vec4 color;
+
+uniform sampler2D image;
+uniform vec3 fade_color;
+uniform float amount;
+uniform vec4 spriteColor;
+
+void main() {
+    vec4 scene = texture(image, TexCoords);
+    color = spriteColor * vec4(mix(scene.rgb, fade_color, amount), scene.a);
+}
@@ -1,7 +1,8 @@
-#version 330 core
-
+// Vertex stage shared by the post-process passes: a unit quad covering the
+// target, drawn with an orthographic 0..1 projection
 layout (location = 0) in vec2 pos;
 layout (location = 1) in vec2 uv;
+layout (location = 2) in vec4 vertexColor;
 
 out vec2 TexCoords;
 
@@ -0,0 +1,30 @@
+// Darkens the corners, starting `radius` from the center (0.5 reaches the
+// middle of the edges) and fading in over `softness`
+#uniform sampler2D image = 0
+#uniform float intensity = 0.5
+#uniform float radius = 0.75
+#uniform float softness = 0.45
+#uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+
+#stage vertex
+#version 330 core
+#include "postfx/fullscreen.glsl"
+
+#stage fragment
+#version 330 core
+
+in vec2 TexCoords;
+out vec4 color;
+
+uniform sampler2D image;
+uniform float intensity;
+uniform float radius;
+uniform float softness;
+uniform vec4 spriteColor;
+
+void main() {
+    vec4 scene = texture(image, TexCoords);
+    float dist = length(TexCoords - vec2(0.5));
+    float shade = smoothstep(radius, radius - softness, dist);
+    color = spriteColor * vec4(scene.rgb * mix(1.0, shade, intensity), scene.a);
+}
@@ -0,0 +1,38 @@
+// Default shader for sprites and tilemaps
+#uniform sampler2D image = 0
+#uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+#uniform mat4 model
+#uniform mat4 projection
+
+#stage vertex
+#version 330 core
+
+layout (location = 0) in vec2 pos;
+layout (location = 1) in vec2 uv;
+layout (location = 2) in vec4 vertexColor;
+
+out vec2 TexCoords;
+out vec4 VertexColor;
+
+uniform mat4 model;
+uniform mat4 projection;
+
+void main() {
+    TexCoords = uv;
+    VertexColor = vertexColor;
+    gl_Position = projection * model * vec4(pos, 0.0, 1.0);
+}
+
+#stage fragment
+#version 330 core
+
+in vec2 TexCoords;
+in vec4 VertexColor;
+out vec4 color;
+
+uniform sampler2D image;
+uniform vec4 spriteColor;
+
+void main() {
+    color = spriteColor * VertexColor * texture(image, TexCoords);
+}
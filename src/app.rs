@@ -0,0 +1,454 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sdl2::{self, Sdl, EventPump};
+use sdl2::event::Event;
+
+use window::{Window, WindowConfig};
+use game_data::GameData;
+use input_manager::InputManager;
+use sprite_renderer::SpriteRenderer;
+use render_backend::{RenderBackend, GlBackend, NullBackend};
+use time::Time;
+
+// What a game can reach while running a frame. GameData is read-only here,
+// as the sprite renderer borrows it, see Game::resize for changing it.
+pub struct Context<'a> {
+    pub window: &'a mut Window,
+    pub input: &'a InputManager,
+    pub data: &'a GameData,
+    // Already advanced for this frame, set time_scale here for slow motion
+    pub time: &'a mut Time,
+    quit: bool,
+    load: bool,
+}
+
+impl<'a> Context<'a> {
+    // Stop after the current frame
+    pub fn quit(&mut self) {
+        self.quit = true;
+    }
+
+    // Call Game::load after the current frame
+    pub fn request_load(&mut self) {
+        self.load = true;
+    }
+}
+
+pub trait Game {
+    // Once before the first frame, e.g. to create render targets. Anything
+    // created through `backend` has to be drawn with the renderer passed to
+    // render(), which uses the same backend.
+    fn init(&mut self, _window: &mut Window, _data: &mut GameData, _backend: &mut dyn RenderBackend) {}
+
+    fn event(&mut self, _ctx: &mut Context, _event: &Event) {}
+
+    // Zero or more times per frame, always with the same `dt`. Game logic
+    // and physics go here so they don't depend on the frame rate. Follows
+    // the scaled time, so it stops running while paused.
+    fn fixed_update(&mut self, _ctx: &mut Context, _dt: f32) {}
+
+    // Once per frame with the scaled frame time, ctx.time.delta()
+    fn update(&mut self, _ctx: &mut Context, _dt: f32) {}
+
+    // `alpha` is how far (0 to 1) the frame is between the last fixed update
+    // and the next, for interpolating what moves in fixed_update. Whatever
+    // is still queued in the renderer afterwards is flushed to the window.
+    fn render(&mut self, ctx: &mut Context, renderer: &mut SpriteRenderer, alpha: f32);
+
+    // Between frames after Context::request_load, with write access to
+    // GameData, e.g. to create render targets for something new
+    fn load(&mut self, _window: &Window, _data: &mut GameData, _backend: &mut dyn RenderBackend) {}
+
+    // The render size changed from `old_size` to window.render_size(). Render
+    // targets should be resized here and cameras updated to match.
+    fn resize(&mut self, _window: &Window, _data: &mut GameData, _backend: &mut dyn RenderBackend,
+              _old_size: (u32, u32)) {}
+
+    // Once after the last frame
+    fn shutdown(&mut self, _data: &mut GameData, _backend: &mut dyn RenderBackend) {}
+}
+
+// Turns variable frame times into a whole number of fixed updates, carrying
+// the remainder over to the next frame
+#[derive(Copy, Clone, Debug)]
+pub struct FixedTimestep {
+    // Seconds per fixed update
+    pub step: f32,
+    // Longest frame time accounted for, so one slow frame (a breakpoint,
+    // dragging the window) doesn't need a long catch-up afterwards
+    pub max_frame_time: f32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32) -> Self {
+        FixedTimestep { step, max_frame_time: 0.25, accumulator: 0.0 }
+    }
+
+    // Add a frame's time, returns the number of fixed updates to run
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.accumulator += frame_time.min(self.max_frame_time).max(0.0);
+        // With some slack for rounding, so a frame of exactly n steps runs n
+        let steps = (self.accumulator / self.step + 1e-4).floor();
+        self.accumulator = (self.accumulator - steps * self.step).max(0.0);
+        steps as u32
+    }
+
+    // Time left over after the last fixed update, as a fraction of the step
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).min(1.0)
+    }
+}
+
+// Where frame times come from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Clock {
+    // Wall clock time
+    Real,
+    // Every frame takes exactly this many seconds, however long it really
+    // took. Makes runs reproducible.
+    Manual(f32),
+    // Wall clock time sped up by a factor
+    Accelerated(f32),
+}
+
+struct FrameClock {
+    clock: Clock,
+    last_frame: Instant,
+}
+
+impl FrameClock {
+    fn new(clock: Clock) -> Self {
+        FrameClock { clock, last_frame: Instant::now() }
+    }
+
+    // Seconds since the last call
+    fn tick(&mut self) -> f32 {
+        let now = Instant::now();
+        let elapsed = now - self.last_frame;
+        self.last_frame = now;
+        let real = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+        match self.clock {
+            Clock::Real => real,
+            Clock::Manual(frame_time) => frame_time,
+            Clock::Accelerated(factor) => real * factor,
+        }
+    }
+
+    // Sleep out the rest of a frame lasting 1/fps seconds from the last tick
+    fn wait(&self, fps: f32) {
+        let frame = Duration::from_nanos((1e9 / fps as f64) as u64);
+        let elapsed = self.last_frame.elapsed();
+        if elapsed < frame {
+            thread::sleep(frame - elapsed);
+        }
+    }
+}
+
+pub struct App {
+    // GL resources go first so they are dropped while the context is alive
+    data: GameData,
+    // Shared by every renderer the App creates, so render targets and such
+    // made in Game::init/load/resize can be drawn with
+    backend: Box<dyn RenderBackend>,
+    input: InputManager,
+    // SDL isn't initialised at all when headless
+    event_pump: Option<EventPump>,
+    // From push_event, handled after SDL's on the next frame
+    pending_events: Vec<Event>,
+    window: Window,
+    sdl: Option<Sdl>,
+    pub timestep: FixedTimestep,
+    // Frame rate to hold when vsync is off, one frame per fixed step if None
+    pub target_fps: Option<f32>,
+    clock: FrameClock,
+    time: Time,
+    started: bool,
+    render_size: (u32, u32),
+    load_requested: bool,
+}
+
+impl App {
+    // Open the window and load GameData
+    pub fn new(config: WindowConfig) -> Self {
+        let sdl = sdl2::init().unwrap();
+        let video = sdl.video().unwrap();
+        let window = Window::new(&video, config);
+        let event_pump = sdl.event_pump().unwrap();
+
+        App::with_window(window, Some(sdl), Some(event_pump), GameData::from_file(), Box::new(GlBackend::new()),
+                         Clock::Real)
+    }
+
+    // Run without SDL, a window or GL: nothing is drawn, the renderer gets a
+    // NullBackend and games must not call into GL themselves (see
+    // Window::is_headless). Frames take a fixed 1/60s unless set_clock is
+    // used, and input only changes through input_mut().
+    pub fn headless(config: WindowConfig) -> Self {
+        App::headless_with(config, Box::new(NullBackend::new()))
+    }
+
+    // Headless, but drawing with `backend`, e.g. a SoftwareBackend to look
+    // at the frames
+    pub fn headless_with(config: WindowConfig, mut backend: Box<dyn RenderBackend>) -> Self {
+        let data = GameData::from_file_with(&mut *backend);
+        App::with_window(Window::headless(config), None, None, data, backend, Clock::Manual(1.0 / 60.0))
+    }
+
+    fn with_window(window: Window, sdl: Option<Sdl>, event_pump: Option<EventPump>,
+                   data: GameData, backend: Box<dyn RenderBackend>, clock: Clock) -> Self {
+        let render_size = window.render_size();
+        App {
+            data,
+            backend,
+            input: InputManager::new(),
+            event_pump,
+            pending_events: Vec::new(),
+            window,
+            sdl,
+            timestep: FixedTimestep::new(1.0 / 60.0),
+            target_fps: None,
+            clock: FrameClock::new(clock),
+            time: Time::new(),
+            started: false,
+            render_size,
+            load_requested: false,
+        }
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock.clock
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock.clock = clock;
+    }
+
+    pub fn time(&self) -> &Time {
+        &self.time
+    }
+
+    pub fn time_mut(&mut self) -> &mut Time {
+        &mut self.time
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    pub fn window_mut(&mut self) -> &mut Window {
+        &mut self.window
+    }
+
+    pub fn data(&self) -> &GameData {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut GameData {
+        &mut self.data
+    }
+
+    pub fn backend_mut(&mut self) -> &mut dyn RenderBackend {
+        &mut *self.backend
+    }
+
+    pub fn input(&self) -> &InputManager {
+        &self.input
+    }
+
+    // For scripting input in headless runs
+    pub fn input_mut(&mut self) -> &mut InputManager {
+        &mut self.input
+    }
+
+    // Hand the game an event on the next frame, e.g. a key press in a
+    // headless run
+    pub fn push_event(&mut self, event: Event) {
+        self.pending_events.push(event);
+    }
+
+    // Run the game until it quits or the window is closed
+    pub fn run<G: Game>(&mut self, game: &mut G) {
+        self.start(game);
+        self.run_loop(game, None);
+        game.shutdown(&mut self.data, &mut *self.backend);
+    }
+
+    // Run up to `frames` frames, calling Game::init first if this is the
+    // first call. Returns false once the game has quit and shut down. In
+    // headless mode with a manual clock, the same inputs give the same
+    // results every time.
+    pub fn run_frames<G: Game>(&mut self, game: &mut G, frames: u32) -> bool {
+        self.start(game);
+        let running = self.run_loop(game, Some(frames));
+        if !running {
+            game.shutdown(&mut self.data, &mut *self.backend);
+        }
+        running
+    }
+
+    fn start<G: Game>(&mut self, game: &mut G) {
+        if !self.started {
+            self.started = true;
+            game.init(&mut self.window, &mut self.data, &mut *self.backend);
+            self.render_size = self.window.render_size();
+            self.clock.last_frame = Instant::now();
+        }
+    }
+
+    fn run_loop<G: Game>(&mut self, game: &mut G, mut frames: Option<u32>) -> bool {
+        let App { ref mut data, ref mut backend, ref mut input, ref mut event_pump, ref mut pending_events,
+                  ref mut window, ref mut timestep, target_fps, ref mut clock, ref mut time, ref mut render_size,
+                  ref mut load_requested, .. } = *self;
+        loop {
+            if *load_requested {
+                *load_requested = false;
+                game.load(window, data, &mut **backend);
+            }
+            if window.render_size() != *render_size {
+                game.resize(window, data, &mut **backend, *render_size);
+                *render_size = window.render_size();
+            }
+
+            // Rebuilt after loading or resizing, as the renderer borrows GameData
+            let mut renderer = SpriteRenderer::with_backend(Box::new(&mut **backend),
+                                                            &data.shaders, &data.textures, &data.sprites);
+            loop {
+                if frames == Some(0) {
+                    return true;
+                }
+                let mut events: Vec<Event> = match *event_pump {
+                    Some(ref mut event_pump) => {
+                        let events = event_pump.poll_iter().collect();
+                        input.update(event_pump);
+                        events
+                    }
+                    None => {
+                        input.update_without_events();
+                        Vec::new()
+                    }
+                };
+                events.extend(pending_events.drain(..));
+                time.advance(clock.tick());
+
+                let mut resized = false;
+                let mut ctx = Context { window, input, data, time, quit: false, load: false };
+                for event in &events {
+                    resized |= ctx.window.handle_event(event);
+                    if let Event::Quit { .. } = *event {
+                        ctx.quit();
+                    }
+                    game.event(&mut ctx, event);
+                }
+
+                for _ in 0..timestep.advance(ctx.time.delta()) {
+                    game.fixed_update(&mut ctx, timestep.step);
+                }
+                let delta = ctx.time.delta();
+                game.update(&mut ctx, delta);
+                game.render(&mut ctx, &mut renderer, timestep.alpha());
+                renderer.end_frame();
+                ctx.window.swap();
+
+                // Swapping waits for the display with vsync on
+                if !ctx.window.is_headless() && !ctx.window.config().vsync {
+                    clock.wait(target_fps.unwrap_or(1.0 / timestep.step));
+                }
+
+                if let Some(ref mut frames) = frames {
+                    *frames -= 1;
+                }
+                if ctx.quit {
+                    return false;
+                }
+                if resized || ctx.load {
+                    *load_requested = ctx.load;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use app::*;
+    use cgmath::Vector2;
+    use input_manager::Key;
+    use sprite_renderer::SpriteParams;
+
+    #[derive(Default)]
+    struct Walker {
+        pos: f32,
+        fixed_updates: u32,
+        frames: u32,
+        shut_down: bool,
+    }
+
+    impl Game for Walker {
+        fn fixed_update(&mut self, ctx: &mut Context, dt: f32) {
+            self.fixed_updates += 1;
+            if ctx.input.is_key_pressed(Key::Right) {
+                self.pos += 60.0 * dt;
+            }
+        }
+
+        fn render(&mut self, ctx: &mut Context, renderer: &mut SpriteRenderer, _alpha: f32) {
+            let (_, sprite) = ctx.data.sprites.get_by_name("smiley_face.sprite").unwrap();
+            renderer.submit_sprite_params(sprite, &SpriteParams::new(Vector2::new(self.pos, 0.0)));
+            self.frames += 1;
+            if self.frames == 120 {
+                ctx.quit();
+            }
+        }
+
+        fn shutdown(&mut self, _data: &mut GameData, _backend: &mut dyn RenderBackend) {
+            self.shut_down = true;
+        }
+    }
+
+    #[test]
+    fn test_headless_run() {
+        let mut app = App::headless(WindowConfig::default());
+        let mut game = Walker::default();
+        assert!(app.run_frames(&mut game, 30));
+        assert_eq!((game.frames, game.fixed_updates, game.pos), (30, 30, 0.0));
+
+        app.input_mut().set_key(Key::Right, true);
+        assert!(app.run_frames(&mut game, 60));
+        assert!((game.pos - 60.0).abs() < 1e-3);
+
+        // Two fixed updates per frame at 30fps
+        app.input_mut().set_key(Key::Right, false);
+        app.set_clock(Clock::Manual(1.0 / 30.0));
+        assert!(app.run_frames(&mut game, 5));
+        assert_eq!(game.fixed_updates, 100);
+
+        // Paused, frames still run but fixed updates don't
+        app.time_mut().time_scale = 0.0;
+        assert!(app.run_frames(&mut game, 5));
+        assert_eq!((game.frames, game.fixed_updates), (100, 100));
+        assert_eq!(app.time().frame_count(), 100);
+        app.time_mut().time_scale = 1.0;
+
+        // The game quits on its 120th frame
+        assert!(!app.run_frames(&mut game, 100));
+        assert_eq!(game.frames, 120);
+        assert!(game.shut_down);
+    }
+
+    #[test]
+    fn test_fixed_timestep() {
+        let mut timestep = FixedTimestep::new(0.1);
+        assert_eq!(timestep.advance(0.25), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+        // The remainder carries over
+        assert_eq!(timestep.advance(0.06), 1);
+        assert!((timestep.alpha() - 0.1).abs() < 1e-4);
+        assert_eq!(timestep.advance(0.0), 0);
+
+        // A long stall only counts as max_frame_time
+        assert_eq!(timestep.advance(10.0), 2);
+    }
+}
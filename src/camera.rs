@@ -0,0 +1,302 @@
+use rand::{Rng, SeedableRng, XorShiftRng};
+use cgmath;
+use cgmath::{Vector2, Vector3, Matrix2, Matrix4, Rad};
+
+// Area of the frame a camera renders to, in render pixels with the origin at
+// the top left. Without a virtual resolution that's the same space as
+// InputManager::get_mouse_pos, otherwise see Window::to_render_coords.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Viewport { x, y, width, height }
+    }
+
+    pub fn center(&self) -> Vector2<f32> {
+        Vector2::new(self.x + 0.5 * self.width, self.y + 0.5 * self.height)
+    }
+}
+
+pub struct Camera2D {
+    // World position shown at the centre of the viewport
+    pub position: Vector2<f32>,
+    pub zoom: f32,
+    // In degrees, rotates the view around its centre
+    pub rotation: f32,
+    pub viewport: Viewport,
+
+    target: Option<Vector2<f32>>,
+    // Fraction of the remaining distance to the target covered per 1/60s,
+    // 1.0 snaps to the target immediately
+    pub follow_lerp: f32,
+    // Half extents (in world units) of the area around the centre the
+    // target can move in without the camera following
+    pub deadzone: Option<Vector2<f32>>,
+    // World rectangle (min, max) the view is kept inside of
+    pub bounds: Option<(Vector2<f32>, Vector2<f32>)>,
+
+    shake_intensity: f32,
+    shake_duration: f32,
+    shake_remaining: f32,
+    shake_offset: Vector2<f32>,
+    // Seeded, so a replayed or headless run shakes the same way every time
+    shake_rng: XorShiftRng,
+}
+
+const SHAKE_SEED: [u32; 4] = [0x193a6754, 0xa8a7d469, 0x97830e05, 0x113ba7bb];
+
+fn rotate(v: Vector2<f32>, degrees: f32) -> Vector2<f32> {
+    Matrix2::from_angle(Rad(degrees.to_radians())) * v
+}
+
+impl Camera2D {
+    // Camera showing the world rectangle (0, 0) - (width, height) in a
+    // viewport covering the whole window
+    pub fn new(width: f32, height: f32) -> Self {
+        Camera2D {
+            position: Vector2::new(0.5 * width, 0.5 * height),
+            zoom: 1.0,
+            rotation: 0.0,
+            viewport: Viewport::new(0.0, 0.0, width, height),
+            target: None,
+            follow_lerp: 1.0,
+            deadzone: None,
+            bounds: None,
+            shake_intensity: 0.0,
+            shake_duration: 0.0,
+            shake_remaining: 0.0,
+            shake_offset: Vector2::new(0.0, 0.0),
+            shake_rng: XorShiftRng::from_seed(SHAKE_SEED),
+        }
+    }
+
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+    }
+
+    // Scale the viewport along with the frame it's in, so a camera covering
+    // the left half of the window still does after it's resized
+    pub fn resize(&mut self, old_size: (u32, u32), new_size: (u32, u32)) {
+        let sx = new_size.0 as f32 / old_size.0.max(1) as f32;
+        let sy = new_size.1 as f32 / old_size.1.max(1) as f32;
+        let vp = self.viewport;
+        self.viewport = Viewport::new(vp.x * sx, vp.y * sy, vp.width * sx, vp.height * sy);
+        self.clamp_to_bounds();
+    }
+
+    pub fn follow(&mut self, target: Vector2<f32>) {
+        self.target = Some(target);
+    }
+
+    pub fn stop_following(&mut self) {
+        self.target = None;
+    }
+
+    // Shake with a random offset of up to `intensity` world units, fading
+    // out over `duration` seconds
+    pub fn shake(&mut self, intensity: f32, duration: f32) {
+        self.shake_intensity = intensity;
+        self.shake_duration = duration;
+        self.shake_remaining = duration;
+    }
+
+    // Restart the shake offsets from a different sequence
+    pub fn seed_shake(&mut self, seed: u32) {
+        let mut state = SHAKE_SEED;
+        state[0] ^= seed;
+        self.shake_rng = XorShiftRng::from_seed(state);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if let Some(target) = self.target {
+            let mut desired = self.position;
+            match self.deadzone {
+                Some(half) => {
+                    let offset = target - self.position;
+                    if offset.x > half.x { desired.x = target.x - half.x; }
+                    if offset.x < -half.x { desired.x = target.x + half.x; }
+                    if offset.y > half.y { desired.y = target.y - half.y; }
+                    if offset.y < -half.y { desired.y = target.y + half.y; }
+                }
+                None => desired = target,
+            }
+            let t = 1.0 - (1.0 - self.follow_lerp.min(1.0).max(0.0)).powf(dt * 60.0);
+            self.position += (desired - self.position) * t;
+        }
+
+        self.clamp_to_bounds();
+
+        if self.shake_remaining > 0.0 {
+            self.shake_remaining = (self.shake_remaining - dt).max(0.0);
+            let strength = self.shake_intensity * self.shake_remaining / self.shake_duration;
+            let x = self.shake_rng.gen::<f32>() * 2.0 - 1.0;
+            let y = self.shake_rng.gen::<f32>() * 2.0 - 1.0;
+            self.shake_offset = Vector2::new(x, y) * strength;
+        } else {
+            self.shake_offset = Vector2::new(0.0, 0.0);
+        }
+    }
+
+    fn clamp_to_bounds(&mut self) {
+        if let Some((min, max)) = self.bounds {
+            let half = self.visible_half_extents();
+            for i in 0..2 {
+                if max[i] - min[i] < 2.0 * half[i] {
+                    self.position[i] = 0.5 * (min[i] + max[i]);
+                } else {
+                    self.position[i] = self.position[i].max(min[i] + half[i]).min(max[i] - half[i]);
+                }
+            }
+        }
+    }
+
+    // Half the size of the visible world area, ignoring rotation
+    pub fn visible_half_extents(&self) -> Vector2<f32> {
+        Vector2::new(self.viewport.width, self.viewport.height) * (0.5 / self.zoom)
+    }
+
+    // Axis-aligned world rectangle (min, max) containing everything visible
+    pub fn visible_bounds(&self) -> (Vector2<f32>, Vector2<f32>) {
+        let vp = self.viewport;
+        let corners = [self.screen_to_world(Vector2::new(vp.x, vp.y)),
+                       self.screen_to_world(Vector2::new(vp.x + vp.width, vp.y)),
+                       self.screen_to_world(Vector2::new(vp.x, vp.y + vp.height)),
+                       self.screen_to_world(Vector2::new(vp.x + vp.width, vp.y + vp.height))];
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for c in &corners[1..] {
+            min = Vector2::new(min.x.min(c.x), min.y.min(c.y));
+            max = Vector2::new(max.x.max(c.x), max.y.max(c.y));
+        }
+        (min, max)
+    }
+
+    // Position the view is actually centred on, including screen shake
+    pub fn eye(&self) -> Vector2<f32> {
+        self.position + self.shake_offset
+    }
+
+    // World space to viewport pixels
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        let eye = self.eye();
+        Matrix4::from_translation(Vector3::new(0.5 * self.viewport.width, 0.5 * self.viewport.height, 0.0))
+            * Matrix4::from_scale(self.zoom)
+            * Matrix4::from_angle_z(cgmath::Deg(-self.rotation))
+            * Matrix4::from_translation(Vector3::new(-eye.x, -eye.y, 0.0))
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        cgmath::ortho(0.0, self.viewport.width, self.viewport.height, 0.0, -1.0, 1.0)
+    }
+
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.projection_matrix() * self.view_matrix()
+    }
+
+    pub fn world_to_screen(&self, world: Vector2<f32>) -> Vector2<f32> {
+        self.viewport.center() + rotate(world - self.eye(), -self.rotation) * self.zoom
+    }
+
+    pub fn screen_to_world(&self, screen: Vector2<f32>) -> Vector2<f32> {
+        self.eye() + rotate((screen - self.viewport.center()) / self.zoom, self.rotation)
+    }
+
+    // Convenience for InputManager::get_mouse_pos
+    pub fn mouse_to_world(&self, mouse_pos: (i32, i32)) -> Vector2<f32> {
+        self.screen_to_world(Vector2::new(mouse_pos.0 as f32, mouse_pos.1 as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camera::*;
+    use cgmath::Vector4;
+
+    fn assert_near(a: Vector2<f32>, b: Vector2<f32>) {
+        assert!((a.x - b.x).abs() < 1e-3 && (a.y - b.y).abs() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_camera_default_matches_window() {
+        let camera = Camera2D::new(800.0, 600.0);
+        assert_near(camera.world_to_screen(Vector2::new(100.0, 50.0)), Vector2::new(100.0, 50.0));
+        assert_near(camera.screen_to_world(Vector2::new(799.0, 0.0)), Vector2::new(799.0, 0.0));
+
+        let expected = cgmath::ortho(0.0, 800.0, 600.0, 0.0, -1.0, 1.0) * Vector4::new(100.0, 50.0, 0.0, 1.0);
+        let actual = camera.view_projection() * Vector4::new(100.0, 50.0, 0.0, 1.0);
+        assert_near(Vector2::new(actual.x, actual.y), Vector2::new(expected.x, expected.y));
+    }
+
+    #[test]
+    fn test_camera_screen_world_roundtrip() {
+        let mut camera = Camera2D::new(800.0, 600.0);
+        camera.position = Vector2::new(1000.0, -200.0);
+        camera.zoom = 2.5;
+        camera.rotation = 30.0;
+        camera.set_viewport(Viewport::new(400.0, 0.0, 400.0, 300.0));
+
+        let world = Vector2::new(1020.0, -180.0);
+        let screen = camera.world_to_screen(world);
+        assert_near(camera.screen_to_world(screen), world);
+
+        // The matrices agree with the direct conversion
+        let clip = camera.view_projection() * Vector4::new(world.x, world.y, 0.0, 1.0);
+        let from_matrix = Vector2::new(400.0 + (clip.x + 1.0) * 200.0, (1.0 - clip.y) * 150.0);
+        assert_near(from_matrix, screen);
+    }
+
+    #[test]
+    fn test_camera_deadzone_and_bounds() {
+        let mut camera = Camera2D::new(800.0, 600.0);
+        camera.deadzone = Some(Vector2::new(50.0, 50.0));
+
+        camera.follow(Vector2::new(430.0, 300.0));
+        camera.update(1.0 / 60.0);
+        assert_near(camera.position, Vector2::new(400.0, 300.0));
+
+        camera.follow(Vector2::new(500.0, 300.0));
+        camera.update(1.0 / 60.0);
+        assert_near(camera.position, Vector2::new(450.0, 300.0));
+
+        camera.bounds = Some((Vector2::new(0.0, 0.0), Vector2::new(1000.0, 1000.0)));
+        camera.follow(Vector2::new(2000.0, -500.0));
+        camera.update(1.0 / 60.0);
+        assert_near(camera.position, Vector2::new(600.0, 300.0));
+    }
+
+    #[test]
+    fn test_camera_resize() {
+        let mut camera = Camera2D::new(800.0, 600.0);
+        camera.set_viewport(Viewport::new(400.0, 0.0, 400.0, 600.0));
+        camera.resize((800, 600), (1200, 300));
+        assert_eq!(camera.viewport, Viewport::new(600.0, 0.0, 600.0, 300.0));
+
+        // A bigger view has to move further from the edge of the bounds
+        camera.bounds = Some((Vector2::new(0.0, 0.0), Vector2::new(1000.0, 1000.0)));
+        camera.position = Vector2::new(250.0, 150.0);
+        camera.resize((1200, 300), (1200, 600));
+        assert_near(camera.position, Vector2::new(300.0, 300.0));
+    }
+
+    #[test]
+    fn test_camera_shake_is_repeatable() {
+        let offsets = |seed: Option<u32>| {
+            let mut camera = Camera2D::new(800.0, 600.0);
+            if let Some(seed) = seed {
+                camera.seed_shake(seed);
+            }
+            camera.shake(10.0, 1.0);
+            (0..10).map(|_| { camera.update(0.05); camera.eye() }).collect::<Vec<_>>()
+        };
+        assert_eq!(offsets(None), offsets(None));
+        assert_eq!(offsets(Some(7)), offsets(Some(7)));
+        assert!(offsets(Some(7)) != offsets(None));
+        assert!(offsets(None).iter().all(|eye| (eye - Vector2::new(400.0, 300.0)).x.abs() <= 10.0));
+    }
+}
@@ -1,6 +1,4 @@
 use std;
-use std::os::raw::c_void;
-use std::mem;
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
@@ -13,9 +11,6 @@ use big_array::BigArray;
 use toml;
 use serde_json;
 use find_folder;
-use cgmath::{Vector3, Matrix4, One};
-use gl;
-use gl::types::*;
 use serde::ser::{Serialize, Serializer, SerializeTuple, SerializeSeq};
 use serde::de::{Deserialize, Deserializer, Visitor, SeqAccess, Error};
 
@@ -23,6 +18,9 @@ use storage::{Storage, ResourceID};
 use shader::Shader;
 use texture::Texture;
 use sprite::{SpriteBounds, SpriteData};
+use sprite_renderer::{SpriteRenderer, SpriteVertex, Quad, BlendMode};
+use camera::Camera2D;
+use lighting::{Segment, tile_edges};
 
 use path::*;
 
@@ -33,6 +31,14 @@ pub const SCALE: f32 = 64.0;
 
 big_array! { 4096, }
 
+// Where a tile layer goes in the SpriteRenderer's draw order (see SpriteParams)
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct LayerOrder {
+    pub layer: i32,
+    #[serde(default)]
+    pub y_sort: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 struct CanvasData {
     name: String,
@@ -44,9 +50,15 @@ struct CanvasData {
     textures: ArrayVec<[ResourceID<Texture>; MAX_LAYERS]>,
     // Note: We use Vec instead of ArrayVec because of array serializing issue
     data: Vec<ArrayVec<[ResourceID<SpriteData>; MAX_WIDTH * MAX_HEIGHT]>>,
+    // Defaults to drawing layer i on sprite layer i
+    #[serde(default)]
+    layer_order: Vec<LayerOrder>,
+    // Layers whose (non-empty) tiles block light
+    #[serde(default)]
+    shadow_layers: Vec<usize>,
 }
 
-pub struct Canvas<'a> {
+pub struct Canvas {
     num_tiles_x: u32,
     num_tiles_y: u32,
     tile_width: u32,
@@ -57,26 +69,17 @@ pub struct Canvas<'a> {
 
     layer_index_to_texture: ArrayVec<[ResourceID<Texture>; MAX_LAYERS]>,
     tiles: Vec<ArrayVec<[ResourceID<SpriteData>; MAX_WIDTH * MAX_HEIGHT]>>,
+    layer_order: Vec<LayerOrder>,
+    shadow_layers: Vec<usize>,
     vertices: [f32; 8*MAX_WIDTH*MAX_HEIGHT],
     uvs: [[f32; 8*MAX_WIDTH*MAX_HEIGHT]; MAX_LAYERS],
-    indices: [u32; 6*MAX_WIDTH*MAX_HEIGHT],
-
-    sprites: &'a Storage<SpriteData>,
-    textures: &'a Storage<Texture>,
-    shaders: &'a Storage<Shader>,
 
     default_shader: ResourceID<Shader>,
-
-    pos_vbos: ArrayVec<[GLuint; MAX_LAYERS]>,
-    uv_vbos: ArrayVec<[GLuint; MAX_LAYERS]>,
-    vaos: ArrayVec<[GLuint; MAX_LAYERS]>,
-    ebo: GLuint
 }
 
-impl<'a> Canvas<'a> {
-    pub fn from_file(sprites: &'a Storage<SpriteData>,
-                     textures: &'a Storage<Texture>,
-                     shaders: &'a Storage<Shader>,
+impl Canvas {
+    pub fn from_file(sprites: &Storage<SpriteData>,
+                     textures: &Storage<Texture>,
                      default_shader: ResourceID<Shader>,
                      filename: &str) -> Self {
 
@@ -96,6 +99,11 @@ impl<'a> Canvas<'a> {
         assert!(num_tiles_x <= MAX_WIDTH as u32);
         assert!(num_tiles_y <= MAX_HEIGHT as u32);
 
+        let mut layer_order = canvas_data.layer_order;
+        for i in layer_order.len()..num_layers {
+            layer_order.push(LayerOrder { layer: i as i32, y_sort: false });
+        }
+
         // Create vertices array
         let mut vertices: [f32; 8*MAX_WIDTH*MAX_HEIGHT] =
             unsafe { std::mem::uninitialized() };
@@ -119,6 +127,13 @@ impl<'a> Canvas<'a> {
             let texture = textures.get(texture_id);
             for i in 0..MAX_WIDTH*MAX_HEIGHT{
                 let sprite_id = canvas_data.data[layer_idx as usize][i];
+                // Null tiles are empty
+                if sprite_id.is_null() {
+                    for uv in &mut uvs[layer_idx][8*i..8*i + 8] {
+                        *uv = 0.0;
+                    }
+                    continue;
+                }
                 let sprite = sprites.get(sprite_id);
                 let sprite_uvs = sprite.get_uvs(texture.width as u32, texture.height as u32);
 
@@ -133,70 +148,6 @@ impl<'a> Canvas<'a> {
             }
         }
 
-        let mut indices: [GLuint; 6 * MAX_WIDTH*MAX_HEIGHT] = unsafe { std::mem::uninitialized() };
-        for i in 0..MAX_WIDTH * MAX_HEIGHT {
-            indices[6*i] = (4*i) as u32;
-            indices[6*i+1] = (4*i + 1) as u32;
-            indices[6*i+2] = (4*i + 2) as u32;
-            indices[6*i+3] = (4*i) as u32;
-            indices[6*i+4] = (4*i + 2) as u32;
-            indices[6*i+5] = (4*i + 3) as u32;
-        }
-
-        let mut pos_vbos = ArrayVec::<[GLuint; MAX_LAYERS]>::new();
-        let mut uv_vbos = ArrayVec::<[GLuint; MAX_LAYERS]>::new();
-        let mut vaos = ArrayVec::<[GLuint; MAX_LAYERS]>::new();
-        let mut ebo = 0;
-
-        unsafe {
-            // Create buffers
-            gl::GenBuffers(1, &mut ebo);
-            for i in 0..num_layers {
-                let mut vao = 0;
-                let mut pos_vbo = 0;
-                let mut uv_vbo = 0;
-                gl::GenVertexArrays(1, &mut vao);
-                gl::GenBuffers(1, &mut pos_vbo);
-                gl::GenBuffers(1, &mut uv_vbo);
-                vaos.push(vao);
-                pos_vbos.push(pos_vbo);
-                uv_vbos.push(uv_vbo);
-            }
-
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
-                           (6*MAX_WIDTH*MAX_HEIGHT*mem::size_of::<f32>()) as GLsizeiptr,
-                           indices.as_ptr() as *const c_void,
-                           gl::STATIC_DRAW);
-
-            // Bind vbos and ebo to vaos
-            for i in 0..num_layers {
-                gl::BindVertexArray(vaos[i]);
-
-                gl::BindBuffer(gl::ARRAY_BUFFER, pos_vbos[i]);
-                gl::BufferData(gl::ARRAY_BUFFER,
-                               (8*MAX_WIDTH*MAX_HEIGHT*mem::size_of::<f32>()) as GLsizeiptr,
-                               vertices.as_ptr() as *const c_void,
-                               gl::STATIC_DRAW);
-                gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE,
-                                        0 as GLint, 0 as *const c_void);
-                gl::EnableVertexAttribArray(0);
-
-                gl::BindBuffer(gl::ARRAY_BUFFER, uv_vbos[i]);
-                gl::BufferData(gl::ARRAY_BUFFER,
-                               (8*MAX_WIDTH*MAX_HEIGHT*mem::size_of::<f32>()) as GLsizeiptr,
-                               uvs.as_ptr() as *const c_void,
-                               gl::STATIC_DRAW);
-
-                gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE,
-                                        0 as GLint, 0 as *const c_void);
-                gl::EnableVertexAttribArray(1);
-
-                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
-            }
-
-        }
-
         Canvas {
             num_tiles_x,
             num_tiles_y,
@@ -208,54 +159,79 @@ impl<'a> Canvas<'a> {
 
             layer_index_to_texture: canvas_data.textures,
             tiles: canvas_data.data,
+            layer_order,
+            shadow_layers: canvas_data.shadow_layers,
 
             vertices,
-            indices,
             uvs,
 
-            sprites,
-            textures,
-            shaders,
             default_shader,
-
-            pos_vbos,
-            uv_vbos,
-            vaos,
-            ebo
         }
     }
 
-    pub fn draw(&self) {
-        let shader = self.shaders.get(self.default_shader);
-        shader.use_shader();
-        shader.set_mat4("model", Matrix4::<f32>::one());
-        shader.set_vec3("spriteColor", Vector3::<f32>::new(1.0, 1.0, 1.0));
+    pub fn layer_order(&self, layer_idx: usize) -> LayerOrder {
+        self.layer_order[layer_idx]
+    }
 
-        for layer_idx in 0..self.num_layers {
-            let texture_id = self.layer_index_to_texture[layer_idx];
-            let texture = self.textures.get(texture_id);
+    pub fn set_layer_order(&mut self, layer_idx: usize, order: LayerOrder) {
+        self.layer_order[layer_idx] = order;
+    }
 
-            unsafe {
-                gl::ActiveTexture(gl::TEXTURE0);
-                texture.bind();
+    // Queue the visible tiles into the sprite renderer, so tile layers are
+    // sorted together with sprites (e.g. characters walking behind tree tops)
+    pub fn submit(&self, renderer: &mut SpriteRenderer, camera: &Camera2D) {
+        let (min, max) = camera.visible_bounds();
+        // Only walk the tiles the camera can see
+        let range = |min: f32, max: f32, num_tiles: u32| {
+            let first = (min / SCALE).floor().max(0.0).min(num_tiles as f32) as usize;
+            let last = ((max / SCALE).floor() + 1.0).max(0.0).min(num_tiles as f32) as usize;
+            first..last.max(first)
+        };
+        let columns = range(min.x, max.x, self.num_tiles_x);
+        let rows = range(min.y, max.y, self.num_tiles_y);
 
-                gl::BindVertexArray(self.vaos[layer_idx]);
-                gl::DrawElements(gl::TRIANGLES, (6 * MAX_WIDTH * MAX_HEIGHT) as GLint, gl::UNSIGNED_INT, 0 as *const c_void);
-                gl::BindVertexArray(0);
+        for layer_idx in 0..self.num_layers {
+            let order = self.layer_order[layer_idx];
+            let texture = self.layer_index_to_texture[layer_idx];
+            let uvs = &self.uvs[layer_idx];
+
+            for i in rows.clone().flat_map(|y| columns.clone().map(move |x| y * MAX_WIDTH + x)) {
+                if self.tiles[layer_idx][i].is_null() {
+                    continue;
+                }
+                let pos = &self.vertices[8*i..8*i + 8];
+
+                let mut vertices = [SpriteVertex { pos: [0.0; 2], uv: [0.0; 2], color: [1.0; 4] }; 4];
+                for (c, vertex) in vertices.iter_mut().enumerate() {
+                    vertex.pos = [pos[2*c], pos[2*c + 1]];
+                    vertex.uv = [uvs[8*i + 2*c], uvs[8*i + 2*c + 1]];
+                }
+
+                renderer.submit_quad(Quad {
+                    shader: self.default_shader,
+                    blend: BlendMode::Alpha,
+                    texture,
+                    layer: order.layer,
+                    // Tiles sort by their bottom edge
+                    depth: if order.y_sort { pos[5] } else { std::f32::NEG_INFINITY },
+                    vertices
+                });
             }
         }
     }
-}
 
-impl<'a> Drop for Canvas<'a> {
-    fn drop(&mut self) {
-        unsafe {
-            for i in 0..self.num_layers {
-                gl::DeleteVertexArrays(1, &self.vaos[i]);
-                gl::DeleteBuffers(1, &self.pos_vbos[i]);
-                gl::DeleteBuffers(1, &self.uv_vbos[i]);
-            }
-            gl::DeleteBuffers(1, &self.ebo);
-        }
+    // Outline of the tiles on the shadow layers, for Lighting::set_occluders
+    pub fn shadow_edges(&self) -> Vec<Segment> {
+        let solid = |x: i32, y: i32| {
+            let i = y as usize * MAX_WIDTH + x as usize;
+            self.shadow_layers.iter().any(|&layer| !self.tiles[layer][i].is_null())
+        };
+        tile_edges(self.num_tiles_x as usize, self.num_tiles_y as usize, SCALE, solid)
     }
-}
\ No newline at end of file
+
+    // Draw the map on its own, without sorting against other sprites
+    pub fn draw(&self, renderer: &mut SpriteRenderer, camera: &Camera2D) {
+        self.submit(renderer, camera);
+        renderer.flush();
+    }
+}
@@ -0,0 +1,203 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use png;
+use png::HasParameters;
+use stb_image::image::Image;
+
+use render_backend::RenderBackend;
+use render_target::RenderTarget;
+use path::asset_path;
+
+// Read back the whole back buffer (or whatever framebuffer is bound)
+pub fn capture_screen(backend: &mut dyn RenderBackend, width: u32, height: u32) -> Image<u8> {
+    backend.read_pixels(0, 0, width, height)
+}
+
+// Read back an offscreen target made by the same backend. Leaves the
+// target bound.
+pub fn capture_target(backend: &mut dyn RenderBackend, target: &RenderTarget) -> Image<u8> {
+    target.bind(backend);
+    backend.read_pixels(0, 0, target.width(), target.height())
+}
+
+fn to_io_error<E: ::std::fmt::Debug>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+}
+
+// Write an 8-bit RGBA image as PNG
+pub fn save_png<P: AsRef<Path>>(image: &Image<u8>, path: P) -> io::Result<()> {
+    assert_eq!(image.depth, 4, "Only RGBA images can be saved");
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width as u32, image.height as u32);
+    encoder.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(to_io_error)?;
+    writer.write_image_data(&image.data).map_err(to_io_error)
+}
+
+// Read a PNG as 8-bit RGBA, whatever its colour type
+pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Image<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(to_io_error)?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(to_io_error)?;
+
+    let pixels = (info.width * info.height) as usize;
+    let channels = buf.len() / pixels;
+    let mut data = Vec::with_capacity(4 * pixels);
+    for p in buf.chunks(channels) {
+        match channels {
+            1 => data.extend_from_slice(&[p[0], p[0], p[0], 255]),
+            2 => data.extend_from_slice(&[p[0], p[0], p[0], p[1]]),
+            3 => data.extend_from_slice(&[p[0], p[1], p[2], 255]),
+            _ => data.extend_from_slice(&p[..4]),
+        }
+    }
+    Ok(Image { width: info.width as usize, height: info.height as usize, depth: 4, data })
+}
+
+pub struct ImageDiff {
+    // Pixels where some channel differs by more than the tolerance
+    pub mismatched: usize,
+    pub max_difference: u8,
+    // Mismatched pixels in red, everything else as a faded copy of the expected image
+    pub diff: Image<u8>,
+}
+
+pub fn compare_images(actual: &Image<u8>, expected: &Image<u8>, tolerance: u8) -> ImageDiff {
+    assert_eq!((actual.width, actual.height), (expected.width, expected.height), "Image sizes differ");
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    let mut data = Vec::with_capacity(expected.data.len());
+    for (a, e) in actual.data.chunks(4).zip(expected.data.chunks(4)) {
+        let difference = a.iter().zip(e.iter())
+            .map(|(&a, &e)| (a as i16 - e as i16).abs() as u8)
+            .max().unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        if difference > tolerance {
+            mismatched += 1;
+            data.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let grey = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 12) as u8;
+            data.extend_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+    ImageDiff {
+        mismatched,
+        max_difference,
+        diff: Image { width: expected.width, height: expected.height, depth: 4, data },
+    }
+}
+
+// Compare a rendered frame against assets/golden/<name>.png. Set
+// GENGINE_BLESS=1 to (re)write the golden image instead. On failure the
+// frame and a diff image are written next to the golden one.
+pub fn assert_golden(actual: &Image<u8>, name: &str, tolerance: u8) {
+    let golden_path = asset_path(&format!("golden/{}.png", name));
+    if env::var("GENGINE_BLESS").is_ok() {
+        save_png(actual, &golden_path).expect("Couldn't write golden image");
+        return;
+    }
+
+    let expected = load_png(&golden_path).unwrap_or_else(|e| {
+        panic!("Couldn't load golden image {} ({}), run with GENGINE_BLESS=1 to create it", golden_path, e)
+    });
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        panic!("Frame is {}x{} but golden image {} is {}x{}",
+               actual.width, actual.height, golden_path, expected.width, expected.height);
+    }
+
+    let result = compare_images(actual, &expected, tolerance);
+    if result.mismatched > 0 {
+        let actual_path = asset_path(&format!("golden/{}.actual.png", name));
+        let diff_path = asset_path(&format!("golden/{}.diff.png", name));
+        save_png(actual, &actual_path).expect("Couldn't write frame");
+        save_png(&result.diff, &diff_path).expect("Couldn't write diff image");
+        panic!("{} pixels differ from {} by more than {} (max {}), see {} and {}",
+               result.mismatched, golden_path, tolerance, result.max_difference, actual_path, diff_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use capture::*;
+    use cgmath::{Vector2, Vector4};
+    use render_backend::SoftwareBackend;
+    use game_data::GameData;
+    use canvas::Canvas;
+    use camera::Camera2D;
+    use sprite_renderer::{SpriteRenderer, SpriteParams, software_sprite_shader};
+    use render_target::RenderTargetBuilder;
+    use storage::Storage;
+
+    #[test]
+    fn test_compare_images() {
+        let expected = Image { width: 2, height: 1, depth: 4, data: vec![10, 20, 30, 255, 0, 0, 0, 255] };
+        let actual = Image { width: 2, height: 1, depth: 4, data: vec![12, 20, 30, 255, 0, 90, 0, 255] };
+
+        let result = compare_images(&actual, &expected, 2);
+        assert_eq!(result.mismatched, 1);
+        assert_eq!(result.max_difference, 90);
+        assert_eq!(&result.diff.data[4..], &[255, 0, 0, 255]);
+
+        assert_eq!(compare_images(&actual, &expected, 90).mismatched, 0);
+    }
+
+    #[test]
+    fn test_capture_target() {
+        let mut backend = SoftwareBackend::new(8, 8);
+        let mut textures = Storage::new(1);
+        let target = RenderTargetBuilder::new(4, 2).build(&mut backend, &mut textures, "target");
+        target.clear(&mut backend, 1.0, 0.0, 0.0, 1.0);
+        RenderTarget::bind_default(&mut backend, 8, 8);
+
+        let image = capture_target(&mut backend, &target);
+        assert_eq!((image.width, image.height), (4, 2));
+        assert!(image.data.chunks(4).all(|p| p == [255, 0, 0, 255]));
+        // The window wasn't touched
+        assert_eq!(backend.pixel(0, 0), [0, 0, 0, 0]);
+        target.release(&mut backend, &mut textures);
+    }
+
+    fn render_map_test() -> Image<u8> {
+        let (width, height) = (320, 240);
+        let mut game_data = GameData::from_file_with(&mut SoftwareBackend::new(1, 1));
+        let (_, shader_id) = game_data.shaders.get_by_name("sprite.shader").unwrap();
+        game_data.shaders.get_mut(shader_id).set_software(software_sprite_shader);
+        let (_, sprite_id) = game_data.sprites.get_by_name("grass_with_dirt_1").unwrap();
+        let canvas = Canvas::from_file(&game_data.sprites, &game_data.textures, shader_id, "map_test.json");
+
+        let mut renderer = SpriteRenderer::with_backend(Box::new(SoftwareBackend::new(width, height)),
+                                                        &game_data.shaders, &game_data.textures, &game_data.sprites);
+        let camera = Camera2D::new(width as f32, height as f32);
+        renderer.set_camera(&camera);
+        renderer.backend_mut().clear(Vector4::new(0.2, 0.3, 0.3, 1.0));
+
+        canvas.submit(&mut renderer, &camera);
+        let params = SpriteParams::new(Vector2::new(150.0, 100.0))
+            .scale(Vector2::new(0.5, 0.5))
+            .rotate(30.0)
+            .color(Vector4::new(1.0, 0.5, 0.5, 0.8))
+            .layer(1);
+        renderer.submit_sprite_params(sprite_id, &params);
+        renderer.end_frame();
+
+        capture_screen(renderer.backend_mut(), width, height)
+    }
+
+    #[test]
+    fn test_golden_map_test() {
+        // Canvas keeps its tile arrays inline, more than a test thread's default stack
+        let frame = ::std::thread::Builder::new()
+            .stack_size(32 * 1024 * 1024)
+            .spawn(render_map_test)
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_golden(&frame, "map_test", 2);
+    }
+}
@@ -0,0 +1,326 @@
+use std::cell::RefCell;
+
+use cgmath::{Vector2, Vector4};
+use cgmath::prelude::*;
+
+use render_backend::{SpriteVertex, BlendMode};
+use sprite_renderer::SpriteRenderer;
+use camera::Camera2D;
+use shape_renderer::{tessellate_polyline, circle_points};
+
+// How far circles may stray from the true curve, in screen pixels
+const CURVE_TOLERANCE: f32 = 0.25;
+
+pub const WHITE: Vector4<f32> = Vector4 { x: 1.0, y: 1.0, z: 1.0, w: 1.0 };
+pub const BLACK: Vector4<f32> = Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 };
+pub const RED: Vector4<f32> = Vector4 { x: 1.0, y: 0.0, z: 0.0, w: 1.0 };
+pub const GREEN: Vector4<f32> = Vector4 { x: 0.0, y: 1.0, z: 0.0, w: 1.0 };
+pub const BLUE: Vector4<f32> = Vector4 { x: 0.0, y: 0.0, z: 1.0, w: 1.0 };
+pub const YELLOW: Vector4<f32> = Vector4 { x: 1.0, y: 1.0, z: 0.0, w: 1.0 };
+pub const CYAN: Vector4<f32> = Vector4 { x: 0.0, y: 1.0, z: 1.0, w: 1.0 };
+pub const MAGENTA: Vector4<f32> = Vector4 { x: 1.0, y: 0.0, z: 1.0, w: 1.0 };
+
+// 5x7 font for ASCII 32..127, one byte per column with the top row in bit 0
+const FONT_FIRST: u8 = 32;
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00], [0x14, 0x08, 0x3E, 0x08, 0x14], [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00], [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31], [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7F, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], [0x38, 0x44, 0x44, 0x48, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18], [0x08, 0x7E, 0x09, 0x01, 0x02], [0x0C, 0x52, 0x52, 0x52, 0x3E],
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], [0x20, 0x40, 0x44, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00], [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x18, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0x7C, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7C], [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3F, 0x44, 0x40, 0x20], [0x3C, 0x40, 0x40, 0x20, 0x7C], [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], [0x44, 0x28, 0x10, 0x28, 0x44], [0x0C, 0x50, 0x50, 0x50, 0x3C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x7F, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00], [0x08, 0x04, 0x08, 0x10, 0x08],
+];
+
+// Width of a character including spacing, in font pixels
+pub const CHAR_ADVANCE: f32 = 6.0;
+pub const LINE_HEIGHT: f32 = 9.0;
+
+#[derive(Clone, Debug)]
+enum Primitive {
+    Line(Vector2<f32>, Vector2<f32>),
+    Rect(Vector2<f32>, Vector2<f32>),
+    Circle(Vector2<f32>, f32),
+    Arrow(Vector2<f32>, Vector2<f32>),
+    Text(Vector2<f32>, String),
+}
+
+#[derive(Clone, Debug)]
+pub struct DebugShape {
+    primitive: Primitive,
+    color: Vector4<f32>,
+    // Seconds left to show the shape, shapes with none are drawn for one frame
+    lifetime: f32,
+}
+
+impl DebugShape {
+    // Keep drawing the shape for the given number of seconds
+    pub fn lifetime(&mut self, seconds: f32) -> &mut Self {
+        self.lifetime = seconds;
+        self
+    }
+}
+
+// Shapes for visualising things like collision boxes or camera bounds, in
+// world coordinates. Everything queued during a frame is drawn on top of
+// the scene with a single draw call in render().
+pub struct DebugDraw {
+    pub enabled: bool,
+    // In screen pixels, regardless of camera zoom
+    pub line_width: f32,
+    // Size of a font pixel in screen pixels
+    pub text_scale: f32,
+    shapes: Vec<DebugShape>,
+    vertices: Vec<SpriteVertex>,
+}
+
+thread_local! {
+    static DEBUG_DRAW: RefCell<DebugDraw> = RefCell::new(DebugDraw::new());
+}
+
+// Access the shared DebugDraw from anywhere on the render thread, e.g.
+// `debug_draw::with(|d| { d.rect(min, max, debug_draw::RED); })`
+pub fn with<F, R>(f: F) -> R where F: FnOnce(&mut DebugDraw) -> R {
+    DEBUG_DRAW.with(|d| f(&mut d.borrow_mut()))
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        DebugDraw {
+            enabled: true,
+            line_width: 1.0,
+            text_scale: 1.0,
+            shapes: Vec::new(),
+            vertices: Vec::new(),
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    fn push(&mut self, primitive: Primitive, color: Vector4<f32>) -> &mut DebugShape {
+        self.shapes.push(DebugShape { primitive, color, lifetime: 0.0 });
+        self.shapes.last_mut().unwrap()
+    }
+
+    pub fn line(&mut self, from: Vector2<f32>, to: Vector2<f32>, color: Vector4<f32>) -> &mut DebugShape {
+        self.push(Primitive::Line(from, to), color)
+    }
+
+    // Outline of an axis-aligned rectangle
+    pub fn rect(&mut self, min: Vector2<f32>, max: Vector2<f32>, color: Vector4<f32>) -> &mut DebugShape {
+        self.push(Primitive::Rect(min, max), color)
+    }
+
+    pub fn circle(&mut self, center: Vector2<f32>, radius: f32, color: Vector4<f32>) -> &mut DebugShape {
+        self.push(Primitive::Circle(center, radius), color)
+    }
+
+    pub fn arrow(&mut self, from: Vector2<f32>, to: Vector2<f32>, color: Vector4<f32>) -> &mut DebugShape {
+        self.push(Primitive::Arrow(from, to), color)
+    }
+
+    // ASCII text with its top left corner at `pos`, '\n' starts a new line
+    pub fn text(&mut self, pos: Vector2<f32>, text: &str, color: Vector4<f32>) -> &mut DebugShape {
+        self.push(Primitive::Text(pos, text.to_string()), color)
+    }
+
+    pub fn shape_count(&self) -> usize {
+        self.shapes.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+    }
+
+    fn add_quad(&mut self, corners: [Vector2<f32>; 4], color: Vector4<f32>) {
+        let color = [color.x, color.y, color.z, color.w];
+        for &i in &[0, 1, 2, 0, 2, 3] {
+            self.vertices.push(SpriteVertex { pos: [corners[i].x, corners[i].y], uv: [0.0, 0.0], color });
+        }
+    }
+
+    fn tessellate(&mut self, shape: &DebugShape, pixel: f32) {
+        let width = self.line_width * pixel;
+        let color = shape.color;
+        match shape.primitive {
+            Primitive::Line(from, to) => {
+                tessellate_polyline(&mut self.vertices, &[from, to], false, width, color);
+            }
+            Primitive::Rect(min, max) => {
+                let corners = [min, Vector2::new(max.x, min.y), max, Vector2::new(min.x, max.y)];
+                tessellate_polyline(&mut self.vertices, &corners, true, width, color);
+            }
+            Primitive::Circle(center, radius) => {
+                let points = circle_points(center, radius, CURVE_TOLERANCE * pixel);
+                tessellate_polyline(&mut self.vertices, &points, true, width, color);
+            }
+            Primitive::Arrow(from, to) => {
+                tessellate_polyline(&mut self.vertices, &[from, to], false, width, color);
+                let dir = to - from;
+                if dir.magnitude() > 0.0 {
+                    let head = dir.normalize() * (8.0 * pixel).min(dir.magnitude() * 0.5);
+                    let side = Vector2::new(-head.y, head.x) * 0.5;
+                    tessellate_polyline(&mut self.vertices, &[to - head + side, to, to - head - side], false, width, color);
+                }
+            }
+            Primitive::Text(pos, ref text) => {
+                let size = self.text_scale * pixel;
+                let mut cursor = pos;
+                for c in text.chars() {
+                    if c == '\n' {
+                        cursor = Vector2::new(pos.x, cursor.y + LINE_HEIGHT * size);
+                        continue;
+                    }
+                    let code = c as u32;
+                    let glyph = if code >= FONT_FIRST as u32 && code < FONT_FIRST as u32 + FONT.len() as u32 {
+                        FONT[(code - FONT_FIRST as u32) as usize]
+                    } else {
+                        FONT[('?' as u8 - FONT_FIRST) as usize]
+                    };
+                    for (col, bits) in glyph.iter().enumerate() {
+                        for row in 0..7 {
+                            if bits & (1 << row) != 0 {
+                                let min = cursor + Vector2::new(col as f32, row as f32) * size;
+                                let max = min + Vector2::new(size, size);
+                                self.add_quad([min, Vector2::new(max.x, min.y), max, Vector2::new(min.x, max.y)], color);
+                            }
+                        }
+                    }
+                    cursor.x += CHAR_ADVANCE * size;
+                }
+            }
+        }
+    }
+
+    // Draw all queued shapes on top of everything submitted so far, then
+    // age them by `dt` and drop the expired ones. Shapes still expire while
+    // drawing is disabled.
+    pub fn render(&mut self, renderer: &mut SpriteRenderer, camera: &Camera2D, dt: f32) {
+        if self.enabled && !self.shapes.is_empty() {
+            renderer.flush();
+
+            // Size of a screen pixel in world units
+            let pixel = 1.0 / camera.zoom;
+            let shapes = ::std::mem::replace(&mut self.shapes, Vec::new());
+            self.vertices.clear();
+            for shape in &shapes {
+                self.tessellate(shape, pixel);
+            }
+            self.shapes = shapes;
+
+            renderer.set_camera(camera);
+            renderer.draw_triangles(None, BlendMode::Alpha, &self.vertices);
+        }
+
+        self.expire(dt);
+    }
+
+    fn expire(&mut self, dt: f32) {
+        for shape in &mut self.shapes {
+            shape.lifetime -= dt;
+        }
+        self.shapes.retain(|s| s.lifetime > 0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use debug_draw::*;
+    use sprite_renderer::{test_renderer, test_shaders};
+    use storage::Storage;
+    use capture::{capture_screen, assert_golden};
+
+    #[test]
+    fn test_debug_draw_lifetimes() {
+        let mut debug = DebugDraw::new();
+        debug.line(Vector2::new(0.0, 0.0), Vector2::new(10.0, 0.0), RED);
+        debug.circle(Vector2::new(0.0, 0.0), 5.0, GREEN).lifetime(1.0);
+        assert_eq!(debug.shape_count(), 2);
+
+        // Shapes without a lifetime are gone after one frame
+        debug.expire(0.5);
+        assert_eq!(debug.shape_count(), 1);
+        debug.expire(0.5);
+        assert_eq!(debug.shape_count(), 0);
+    }
+
+    #[test]
+    fn test_debug_draw_tessellate() {
+        let mut debug = DebugDraw::new();
+        debug.line_width = 2.0;
+        debug.rect(Vector2::new(0.0, 0.0), Vector2::new(10.0, 10.0), WHITE);
+        let shape = debug.shapes[0].clone();
+        debug.tessellate(&shape, 1.0);
+        // Four sides, two triangles each
+        assert_eq!(debug.vertices.len(), 4 * 6);
+        assert!(debug.vertices.iter().all(|v| v.pos[0] >= -1.0 && v.pos[0] <= 11.0));
+
+        debug.vertices.clear();
+        let text = DebugShape { primitive: Primitive::Text(Vector2::new(0.0, 0.0), "-".to_string()), color: WHITE, lifetime: 0.0 };
+        debug.tessellate(&text, 1.0);
+        // '-' is a single row of five pixels
+        assert_eq!(debug.vertices.len(), 5 * 6);
+
+        // Circles stay smooth on screen when zoomed in
+        let circle = DebugShape { primitive: Primitive::Circle(Vector2::new(0.0, 0.0), 10.0), color: WHITE, lifetime: 0.0 };
+        debug.vertices.clear();
+        debug.tessellate(&circle, 1.0);
+        let unzoomed = debug.vertices.len();
+        debug.vertices.clear();
+        debug.tessellate(&circle, 0.25);
+        assert!(debug.vertices.len() > unzoomed);
+    }
+
+    #[test]
+    fn test_golden_debug_draw() {
+        let (textures, sprites) = (Storage::new(1), Storage::new(1));
+        let shaders = test_shaders();
+        let mut renderer = test_renderer(160, 120, &shaders, &textures, &sprites);
+        renderer.backend_mut().clear(Vector4::new(0.1, 0.1, 0.1, 1.0));
+
+        let mut camera = Camera2D::new(160.0, 120.0);
+        camera.zoom = 2.0;
+        camera.position = Vector2::new(40.0, 30.0);
+
+        let mut debug = DebugDraw::new();
+        debug.rect(Vector2::new(5.0, 5.0), Vector2::new(35.0, 25.0), YELLOW);
+        debug.circle(Vector2::new(60.0, 15.0), 10.0, RED);
+        debug.line(Vector2::new(5.0, 55.0), Vector2::new(35.0, 35.0), CYAN);
+        debug.arrow(Vector2::new(45.0, 50.0), Vector2::new(75.0, 35.0), GREEN);
+        debug.text(Vector2::new(2.0, 28.0), "Hi! 42", WHITE);
+        debug.render(&mut renderer, &camera, 1.0 / 60.0);
+        assert_eq!(renderer.stats().draw_calls, 0);
+        renderer.end_frame();
+        // Everything went out in one draw call
+        assert_eq!(renderer.stats().draw_calls, 1);
+
+        assert_golden(&capture_screen(renderer.backend_mut(), 160, 120), "debug_draw", 2);
+    }
+}
@@ -0,0 +1,632 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use storage::{Storage, ResourceID};
+
+// Entities are just ids from a Storage, everything about them lives in
+// their components
+pub struct EntityData;
+
+pub type Entity = ResourceID<EntityData>;
+
+// All components of one type, indexed by entity index
+pub struct Components<T> {
+    items: Vec<Option<T>>,
+    len: usize,
+}
+
+impl<T> Components<T> {
+    fn new() -> Self {
+        Components { items: Vec::new(), len: 0 }
+    }
+
+    fn insert(&mut self, index: usize, item: T) -> Option<T> {
+        if index >= self.items.len() {
+            let new_len = index + 1;
+            self.items.reserve(new_len - self.items.len());
+            while self.items.len() < new_len {
+                self.items.push(None);
+            }
+        }
+        let old = self.items[index].take();
+        if old.is_none() {
+            self.len += 1;
+        }
+        self.items[index] = Some(item);
+        old
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T> {
+        let old = self.items.get_mut(index).and_then(|item| item.take());
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index).and_then(|item| item.as_ref())
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.items.get_mut(index).and_then(|item| item.as_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+// Components<T> with the type erased, so despawning can clear an entity
+// from every storage. Only used by World.
+pub trait AnyComponents {
+    fn remove_index(&mut self, index: usize);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> AnyComponents for Components<T> {
+    fn remove_index(&mut self, index: usize) {
+        self.remove(index);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+type ComponentMap = HashMap<TypeId, Box<dyn AnyComponents>>;
+
+fn components<T: 'static>(map: &ComponentMap) -> Option<&Components<T>> {
+    map.get(&TypeId::of::<T>()).map(|c| c.as_any().downcast_ref::<Components<T>>().unwrap())
+}
+
+fn components_mut<T: 'static>(map: &mut ComponentMap) -> Option<&mut Components<T>> {
+    map.get_mut(&TypeId::of::<T>()).map(|c| c.as_any_mut().downcast_mut::<Components<T>>().unwrap())
+}
+
+// A set of components added together, any tuple of up to 6 components
+pub trait Bundle: 'static {
+    fn insert_into(self, world: &mut World, entity: Entity);
+}
+
+macro_rules! tuple_bundle {
+    ($($name:ident),*) => {
+        impl<$($name: 'static),*> Bundle for ($($name,)*) {
+            #[allow(non_snake_case, unused_variables)]
+            fn insert_into(self, world: &mut World, entity: Entity) {
+                let ($($name,)*) = self;
+                $(world.insert(entity, $name);)*
+            }
+        }
+    }
+}
+
+tuple_bundle!();
+tuple_bundle!(A);
+tuple_bundle!(A, B);
+tuple_bundle!(A, B, C);
+tuple_bundle!(A, B, C, D);
+tuple_bundle!(A, B, C, D, E);
+tuple_bundle!(A, B, C, D, E, F);
+
+// What World::query can iterate over: &T, &mut T or tuples of them. Only
+// entities that have all of the components are visited.
+pub trait Query<'w> {
+    type Item;
+    // Pointers to the storages, valid for as long as the world is borrowed
+    type Fetch: Copy;
+
+    // None if there are no components of a type at all. `types` collects the
+    // component types, as no type may be borrowed twice.
+    fn fetch(components: &mut ComponentMap, types: &mut Vec<TypeId>) -> Option<Self::Fetch>;
+
+    // Entity indices past this have none of the components
+    fn len(fetch: Self::Fetch) -> usize;
+
+    // Must not be called twice for the same index while the items are alive
+    unsafe fn get(fetch: Self::Fetch, index: usize) -> Option<Self::Item>;
+}
+
+impl<'w, T: 'static> Query<'w> for &'w T {
+    type Item = &'w T;
+    type Fetch = *const Components<T>;
+
+    fn fetch(components: &mut ComponentMap, types: &mut Vec<TypeId>) -> Option<Self::Fetch> {
+        types.push(TypeId::of::<T>());
+        components_mut::<T>(components).map(|c| c as *const _)
+    }
+
+    fn len(fetch: Self::Fetch) -> usize {
+        unsafe { (*fetch).items.len() }
+    }
+
+    unsafe fn get(fetch: Self::Fetch, index: usize) -> Option<Self::Item> {
+        (*fetch).get(index)
+    }
+}
+
+impl<'w, T: 'static> Query<'w> for &'w mut T {
+    type Item = &'w mut T;
+    type Fetch = *mut Components<T>;
+
+    fn fetch(components: &mut ComponentMap, types: &mut Vec<TypeId>) -> Option<Self::Fetch> {
+        types.push(TypeId::of::<T>());
+        components_mut::<T>(components).map(|c| c as *mut _)
+    }
+
+    fn len(fetch: Self::Fetch) -> usize {
+        unsafe { (*fetch).items.len() }
+    }
+
+    unsafe fn get(fetch: Self::Fetch, index: usize) -> Option<Self::Item> {
+        (*fetch).get_mut(index)
+    }
+}
+
+macro_rules! tuple_query {
+    ($($name:ident),*) => {
+        impl<'w, $($name: Query<'w>),*> Query<'w> for ($($name,)*) {
+            type Item = ($($name::Item,)*);
+            type Fetch = ($($name::Fetch,)*);
+
+            fn fetch(components: &mut ComponentMap, types: &mut Vec<TypeId>) -> Option<Self::Fetch> {
+                Some(($($name::fetch(components, types)?,)*))
+            }
+
+            #[allow(non_snake_case)]
+            fn len(fetch: Self::Fetch) -> usize {
+                let ($($name,)*) = fetch;
+                usize::max_value()$(.min($name::len($name)))*
+            }
+
+            #[allow(non_snake_case)]
+            unsafe fn get(fetch: Self::Fetch, index: usize) -> Option<Self::Item> {
+                let ($($name,)*) = fetch;
+                Some(($($name::get($name, index)?,)*))
+            }
+        }
+    }
+}
+
+tuple_query!(A);
+tuple_query!(A, B);
+tuple_query!(A, B, C);
+tuple_query!(A, B, C, D);
+tuple_query!(A, B, C, D, E);
+tuple_query!(A, B, C, D, E, F);
+
+pub struct QueryIter<'w, Q: Query<'w>> {
+    entities: &'w Storage<EntityData>,
+    fetch: Option<Q::Fetch>,
+    index: usize,
+    end: usize,
+    // Keeps the world mutably borrowed while items are around
+    phantom: PhantomData<&'w mut World>,
+}
+
+impl<'w, Q: Query<'w>> Iterator for QueryIter<'w, Q> {
+    type Item = (Entity, Q::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fetch = match self.fetch {
+            Some(fetch) => fetch,
+            None => return None,
+        };
+        while self.index < self.end {
+            let index = self.index;
+            self.index += 1;
+            // Each index is only visited once, so no item is handed out twice
+            if let Some(item) = unsafe { Q::get(fetch, index) } {
+                return Some((self.entities.id_at(index as u32).unwrap(), item));
+            }
+        }
+        None
+    }
+}
+
+pub struct World {
+    entities: Storage<EntityData>,
+    components: ComponentMap,
+    // Kept here rather than in the Storage, as names needn't be unique
+    names: HashMap<Entity, String>,
+    // Entities with each name, oldest first
+    by_name: HashMap<String, Vec<Entity>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World {
+            entities: Storage::new(64),
+            components: HashMap::new(),
+            names: HashMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity {
+        let entity = self.entities.insert("", EntityData);
+        bundle.insert_into(self, entity);
+        entity
+    }
+
+    // Named entities can be looked up with find(). Several entities can
+    // share a name.
+    pub fn spawn_named<B: Bundle>(&mut self, name: &str, bundle: B) -> Entity {
+        let entity = self.spawn(bundle);
+        if !name.is_empty() {
+            self.names.insert(entity, name.to_string());
+            self.by_name.entry(name.to_string()).or_insert_with(Vec::new).push(entity);
+        }
+        entity
+    }
+
+    // Remove the entity and all its components, returns false if it was
+    // already gone
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+        for components in self.components.values_mut() {
+            components.remove_index(entity.index() as usize);
+        }
+        if let Some(name) = self.names.remove(&entity) {
+            let empty = {
+                let entities = self.by_name.get_mut(&name).unwrap();
+                entities.retain(|&e| e != entity);
+                entities.is_empty()
+            };
+            if empty {
+                self.by_name.remove(&name);
+            }
+        }
+        self.entities.release(entity);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.entities.id_at(entity.index()) == Some(entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.size() as usize
+    }
+
+    // The oldest living entity with that name
+    pub fn find(&self, name: &str) -> Option<Entity> {
+        self.by_name.get(name).and_then(|entities| entities.first().cloned())
+    }
+
+    // Every living entity with that name, oldest first
+    pub fn find_all(&self, name: &str) -> &[Entity] {
+        self.by_name.get(name).map_or(&[], |entities| &entities[..])
+    }
+
+    // Empty for unnamed entities
+    pub fn name(&self, entity: Entity) -> &str {
+        self.names.get(&entity).map_or("", |name| &name[..])
+    }
+
+    pub fn entities(&self) -> Vec<Entity> {
+        (0..self.entities.capacity()).filter_map(|i| self.entities.id_at(i)).collect()
+    }
+
+    // Add a component, replacing and returning any of the same type
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) -> Option<T> {
+        assert!(self.is_alive(entity), "Inserting a component into a despawned entity");
+        self.components.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Components::<T>::new()));
+        components_mut::<T>(&mut self.components).unwrap().insert(entity.index() as usize, component)
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        components_mut::<T>(&mut self.components).and_then(|c| c.remove(entity.index() as usize))
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        components::<T>(&self.components).and_then(|c| c.get(entity.index() as usize))
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        if !self.is_alive(entity) {
+            return None;
+        }
+        components_mut::<T>(&mut self.components).and_then(|c| c.get_mut(entity.index() as usize))
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    // Number of entities with a T
+    pub fn count<T: 'static>(&self) -> usize {
+        components::<T>(&self.components).map(|c| c.len()).unwrap_or(0)
+    }
+
+    // Iterate over the entities that have all the components in Q, e.g.
+    // world.query::<(&mut Position, &Velocity)>()
+    pub fn query<'w, Q: Query<'w>>(&'w mut self) -> QueryIter<'w, Q> {
+        let World { ref entities, ref mut components, .. } = *self;
+        let mut types = Vec::new();
+        let fetch = Q::fetch(components, &mut types);
+        for (i, t) in types.iter().enumerate() {
+            assert!(!types[..i].contains(t), "A query can't borrow the same component type twice");
+        }
+        let end = fetch.map(|fetch| Q::len(fetch)).unwrap_or(0);
+        QueryIter { entities, fetch, index: 0, end, phantom: PhantomData }
+    }
+}
+
+// Changes to a World queued up while it's being iterated over, applied
+// with apply(). Spawned entities don't exist until then.
+pub struct Commands {
+    queue: Vec<Box<dyn FnOnce(&mut World)>>,
+}
+
+impl Commands {
+    pub fn new() -> Self {
+        Commands { queue: Vec::new() }
+    }
+
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) {
+        self.push(move |world| { world.spawn(bundle); });
+    }
+
+    pub fn spawn_named<B: Bundle>(&mut self, name: &str, bundle: B) {
+        let name = name.to_string();
+        self.push(move |world| { world.spawn_named(&name, bundle); });
+    }
+
+    // Despawning an entity twice is fine, the second one does nothing
+    pub fn despawn(&mut self, entity: Entity) {
+        self.push(move |world| { world.despawn(entity); });
+    }
+
+    // Skipped if the entity is gone by then
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) {
+        self.push(move |world| {
+            if world.is_alive(entity) {
+                world.insert(entity, component);
+            }
+        });
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) {
+        self.push(move |world| { world.remove::<T>(entity); });
+    }
+
+    // Any other change
+    pub fn push<F: FnOnce(&mut World) + 'static>(&mut self, command: F) {
+        self.queue.push(Box::new(command));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // Run the queued commands in the order they were added
+    pub fn apply(&mut self, world: &mut World) {
+        for command in self.queue.drain(..) {
+            command(world);
+        }
+    }
+}
+
+pub trait System {
+    fn run(&mut self, world: &mut World, commands: &mut Commands, dt: f32);
+}
+
+impl<F> System for F where F: FnMut(&mut World, &mut Commands, f32) {
+    fn run(&mut self, world: &mut World, commands: &mut Commands, dt: f32) {
+        self(world, commands, dt)
+    }
+}
+
+struct ScheduledSystem {
+    name: String,
+    order: i32,
+    enabled: bool,
+    system: Box<dyn System>,
+}
+
+// Systems run one after another, by ascending order and then in the order
+// they were added. The commands a system queues are applied as soon as it
+// finishes, so later systems see what it spawned.
+pub struct Schedule {
+    systems: Vec<ScheduledSystem>,
+    commands: Commands,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Schedule { systems: Vec::new(), commands: Commands::new() }
+    }
+
+    pub fn add<S: System + 'static>(&mut self, name: &str, order: i32, system: S) -> &mut Self {
+        assert!(self.systems.iter().all(|s| s.name != name), "Duplicate system {}", name);
+        // After the last system with the same order
+        let position = self.systems.iter().position(|s| s.order > order).unwrap_or(self.systems.len());
+        self.systems.insert(position, ScheduledSystem {
+            name: name.to_string(),
+            order,
+            enabled: true,
+            system: Box::new(system),
+        });
+        self
+    }
+
+    // Returns false if there's no system with that name
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.systems.len();
+        self.systems.retain(|s| s.name != name);
+        self.systems.len() != len
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.systems.iter_mut().find(|s| s.name == name) {
+            Some(system) => { system.enabled = enabled; true }
+            None => false,
+        }
+    }
+
+    // Names in the order the systems run
+    pub fn names(&self) -> Vec<&str> {
+        self.systems.iter().map(|s| &s.name[..]).collect()
+    }
+
+    pub fn run(&mut self, world: &mut World, dt: f32) {
+        let Schedule { ref mut systems, ref mut commands } = *self;
+        for scheduled in systems.iter_mut().filter(|s| s.enabled) {
+            scheduled.system.run(world, commands, dt);
+            commands.apply(world);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ecs::*;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(f32, f32);
+    #[derive(Debug, PartialEq)]
+    struct Velocity(f32, f32);
+    #[derive(Debug, PartialEq)]
+    struct Health(i32);
+
+    #[test]
+    fn test_world_entities() {
+        let mut world = World::new();
+        let player = world.spawn_named("player", (Position(0.0, 0.0), Health(3)));
+        let rock = world.spawn((Position(5.0, 5.0),));
+        assert_eq!(world.len(), 2);
+        assert_eq!(world.find("player"), Some(player));
+        assert_eq!(world.get::<Health>(player), Some(&Health(3)));
+        assert!(!world.has::<Health>(rock));
+
+        world.insert(rock, Velocity(1.0, 0.0));
+        assert_eq!(world.remove::<Health>(player), Some(Health(3)));
+        assert_eq!(world.count::<Health>(), 0);
+
+        // The slot is reused, but the old id stays dead
+        assert!(world.despawn(rock));
+        assert!(!world.despawn(rock));
+        let tree = world.spawn((Position(1.0, 1.0),));
+        assert_eq!(tree.index(), rock.index());
+        assert!(!world.is_alive(rock));
+        assert_eq!(world.get::<Position>(rock), None);
+        assert!(!world.has::<Velocity>(tree));
+        assert_eq!(world.entities(), [player, tree]);
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let mut world = World::new();
+        let first = world.spawn_named("enemy", (Health(1),));
+        let second = world.spawn_named("enemy", (Health(2),));
+        world.spawn((Health(3),));
+        assert_eq!(world.find("enemy"), Some(first));
+        assert_eq!(world.find_all("enemy"), [first, second]);
+        assert_eq!(world.find(""), None);
+
+        // Despawning one leaves the other findable
+        world.despawn(first);
+        assert_eq!(world.find("enemy"), Some(second));
+        assert_eq!(world.name(second), "enemy");
+        world.despawn(second);
+        assert_eq!(world.find("enemy"), None);
+        assert_eq!(world.name(second), "");
+    }
+
+    #[test]
+    fn test_query() {
+        let mut world = World::new();
+        let a = world.spawn((Position(0.0, 0.0), Velocity(1.0, 2.0)));
+        world.spawn((Position(10.0, 0.0),));
+        let c = world.spawn((Velocity(-1.0, 0.0), Position(0.0, 10.0), Health(1)));
+
+        for (_, (pos, vel)) in world.query::<(&mut Position, &Velocity)>() {
+            pos.0 += vel.0;
+            pos.1 += vel.1;
+        }
+        assert_eq!(world.get::<Position>(a), Some(&Position(1.0, 2.0)));
+        assert_eq!(world.get::<Position>(c), Some(&Position(-1.0, 10.0)));
+
+        let found: Vec<Entity> = world.query::<(&Health, &Position)>().map(|(e, _)| e).collect();
+        assert_eq!(found, [c]);
+        assert_eq!(world.query::<&Position>().count(), 3);
+        // Nothing has ever had a String
+        assert_eq!(world.query::<(&Position, &String)>().count(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_query_aliasing() {
+        let mut world = World::new();
+        world.spawn((Position(0.0, 0.0),));
+        let _ = world.query::<(&mut Position, &Position)>();
+    }
+
+    #[test]
+    fn test_schedule() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut world = World::new();
+        let mut schedule = Schedule::new();
+
+        let movement_log = log.clone();
+        schedule.add("movement", 10, move |world: &mut World, _: &mut Commands, dt: f32| {
+            movement_log.borrow_mut().push("movement");
+            for (_, (pos, vel)) in world.query::<(&mut Position, &Velocity)>() {
+                pos.0 += vel.0 * dt;
+            }
+        });
+        // Spawns are deferred, but applied before the next system runs
+        let spawn_log = log.clone();
+        schedule.add("spawn", 0, move |world: &mut World, commands: &mut Commands, _: f32| {
+            spawn_log.borrow_mut().push("spawn");
+            if world.len() == 0 {
+                commands.spawn((Position(0.0, 0.0), Velocity(2.0, 0.0)));
+                assert_eq!(world.len(), 0);
+            }
+        });
+        // Despawns whatever moved past 3
+        schedule.add("cleanup", 10, |world: &mut World, commands: &mut Commands, _: f32| {
+            for (entity, pos) in world.query::<&Position>() {
+                if pos.0 > 3.0 {
+                    commands.despawn(entity);
+                }
+            }
+        });
+        assert_eq!(schedule.names(), ["spawn", "movement", "cleanup"]);
+
+        schedule.run(&mut world, 1.0);
+        assert_eq!(*log.borrow(), ["spawn", "movement"]);
+        assert_eq!(world.query::<&Position>().map(|(_, p)| p.0).collect::<Vec<_>>(), [2.0]);
+
+        schedule.run(&mut world, 1.0);
+        assert_eq!(world.len(), 0);
+
+        assert!(schedule.set_enabled("spawn", false));
+        schedule.run(&mut world, 1.0);
+        assert_eq!(world.len(), 0);
+        assert!(schedule.remove("cleanup"));
+        assert!(!schedule.remove("cleanup"));
+    }
+}
@@ -0,0 +1,658 @@
+use std::fs::File;
+use std::mem;
+use std::io::Read;
+use std::path::Path;
+use std::collections::HashMap;
+
+use gl;
+use rusttype;
+use cgmath::{Vector2, Vector4};
+use stb_image::image::{self, Image};
+
+use storage::{Storage, ResourceID};
+use texture::{Texture, TextureBuilder};
+use render_backend::{RenderBackend, GlBackend, SpriteVertex, BlendMode};
+use sprite_renderer::{SpriteRenderer, DrawState};
+use path::*;
+
+// Where a character is in the atlas and how to place it, in pixels
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Glyph {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    // From the pen position (top of the line) to the glyph's top left
+    pub xoffset: f32,
+    pub yoffset: f32,
+    pub xadvance: f32,
+}
+
+// A bitmap font loaded from a BMFont (AngelCode) text .fnt file, or a
+// TrueType font rasterised into an atlas at `size` pixels high.
+#[derive(Serialize, Deserialize)]
+pub struct Font {
+    path: String,
+    #[serde(default)]
+    size: f32,
+    // Characters to rasterise from a TrueType font besides ASCII and Latin-1
+    #[serde(default)]
+    extra_chars: String,
+
+    #[serde(skip)]
+    texture: ResourceID<Texture>,
+    #[serde(skip)]
+    atlas_size: (f32, f32),
+    #[serde(skip)]
+    line_height: f32,
+    #[serde(skip)]
+    base: f32,
+    #[serde(skip)]
+    glyphs: HashMap<char, Glyph>,
+    #[serde(skip)]
+    kerning: HashMap<(char, char), f32>,
+    // Kept around for its kerning tables
+    #[serde(skip)]
+    ttf: Option<rusttype::Font<'static>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TextParams {
+    // Top of the first line; its left edge, center or right edge depending on `align`
+    pub pos: Vector2<f32>,
+    pub color: Vector4<f32>,
+    pub scale: f32,
+    pub align: TextAlign,
+    // Wrap lines at word boundaries so they fit this width (after scaling)
+    pub max_width: Option<f32>,
+    pub blend: BlendMode,
+    pub layer: i32,
+}
+
+impl TextParams {
+    pub fn new(pos: Vector2<f32>) -> Self {
+        TextParams {
+            pos,
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            scale: 1.0,
+            align: TextAlign::Left,
+            max_width: None,
+            blend: BlendMode::Alpha,
+            layer: 0,
+        }
+    }
+
+    pub fn color(mut self, color: Vector4<f32>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn align(mut self, align: TextAlign) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn max_width(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+// A laid out line: characters with their pen x positions, and the width
+// up to the end of the last visible character
+struct Line {
+    chars: Vec<(char, f32)>,
+    width: f32,
+    // Where the next character goes
+    pen: f32,
+}
+
+impl Line {
+    fn new() -> Self {
+        Line { chars: Vec::new(), width: 0.0, pen: 0.0 }
+    }
+
+    // Enough to undo appends with restore()
+    fn checkpoint(&self) -> (usize, f32, f32) {
+        (self.chars.len(), self.width, self.pen)
+    }
+
+    fn restore(&mut self, (len, width, pen): (usize, f32, f32)) {
+        self.chars.truncate(len);
+        self.width = width;
+        self.pen = pen;
+    }
+
+    fn has_visible(&self) -> bool {
+        self.chars.iter().any(|&(c, _)| !c.is_whitespace())
+    }
+}
+
+// Values of `key=value` pairs on a BMFont line, quotes removed
+fn bmfont_values(line: &str) -> (String, HashMap<String, String>) {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(current.clone());
+                    current.clear();
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    let tag = if tokens.is_empty() { String::new() } else { tokens.remove(0) };
+    let values = tokens.iter().filter_map(|t| {
+        let mut parts = t.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) => Some((k.to_string(), v.to_string())),
+            _ => None,
+        }
+    }).collect();
+    (tag, values)
+}
+
+// Convert any decoded image to RGBA, single-channel images becoming white
+// with the channel as alpha (how bitmap fonts are usually stored)
+fn font_image_to_rgba(image: Image<u8>) -> Image<u8> {
+    let data = match image.depth {
+        1 => image.data.iter().flat_map(|&a| vec![255, 255, 255, a]).collect(),
+        2 => image.data.chunks(2).flat_map(|p| vec![p[0], p[0], p[0], p[1]]).collect(),
+        3 => image.data.chunks(3).flat_map(|p| vec![p[0], p[1], p[2], 255]).collect(),
+        _ => image.data,
+    };
+    Image { width: image.width, height: image.height, depth: 4, data }
+}
+
+impl Font {
+    pub fn new(path: &str, size: f32) -> Self {
+        Font {
+            path: path.to_string(),
+            size,
+            extra_chars: String::new(),
+            texture: ResourceID::null(),
+            atlas_size: (0.0, 0.0),
+            line_height: 0.0,
+            base: 0.0,
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+            ttf: None,
+        }
+    }
+
+    pub fn extra_chars(mut self, chars: &str) -> Self {
+        self.extra_chars = chars.to_string();
+        self
+    }
+
+    pub fn texture(&self) -> ResourceID<Texture> {
+        self.texture
+    }
+
+    // Name of the atlas texture in the texture storage
+    pub fn atlas_name(&self) -> String {
+        format!("{}.atlas", self.path)
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    // Distance from the top of a line to the baseline
+    pub fn base(&self) -> f32 {
+        self.base
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c)
+    }
+
+    pub fn load(&mut self, textures: &mut Storage<Texture>) {
+        self.load_with(&mut GlBackend::new(), textures);
+    }
+
+    // Load the font and add its atlas to `textures`
+    pub fn load_with(&mut self, backend: &mut dyn RenderBackend, textures: &mut Storage<Texture>) {
+        let path = asset_path(&self.path);
+        let is_ttf = self.path.ends_with(".ttf") || self.path.ends_with(".otf");
+        let image = if is_ttf {
+            let mut data = Vec::new();
+            File::open(&path).and_then(|mut f| f.read_to_end(&mut data))
+                .unwrap_or_else(|e| panic!("Couldn't read font {}: {}", path, e));
+            self.rasterize_ttf(data)
+        } else {
+            let mut source = String::new();
+            File::open(&path).and_then(|mut f| f.read_to_string(&mut source))
+                .unwrap_or_else(|e| panic!("Couldn't read font {}: {}", path, e));
+            let page = self.parse_bmfont(&source).unwrap_or_else(|e| panic!("{}: {}", path, e));
+
+            // Pages are relative to the .fnt file
+            let page_path = Path::new(&path).with_file_name(&page);
+            match image::load(&page_path) {
+                image::LoadResult::ImageU8(image) => font_image_to_rgba(image),
+                image::LoadResult::ImageF32(_) => { panic!("Image loaded as f32"); }
+                image::LoadResult::Error(s) => { panic!("Error while loading font page {}: {}", page, s); }
+            }
+        };
+
+        self.atlas_size = (image.width as f32, image.height as f32);
+        let texture = TextureBuilder::new()
+            .image(image)
+            .internal_format(gl::RGBA)
+            .image_format(gl::RGBA)
+            .wrap(gl::CLAMP_TO_EDGE, gl::CLAMP_TO_EDGE)
+            .build_with(backend);
+        let name = self.atlas_name();
+        textures.release_by_name(&name);
+        self.texture = textures.insert(&name, texture);
+    }
+
+    // Read the metrics from a BMFont text file, returning the page image's
+    // file name. Only single page fonts are supported.
+    pub fn parse_bmfont(&mut self, source: &str) -> Result<String, String> {
+        let mut page = None;
+        for (i, line) in source.lines().enumerate() {
+            let (tag, values) = bmfont_values(line);
+            let get = |key: &str| -> Result<f32, String> {
+                values.get(key)
+                    .ok_or_else(|| format!("line {}: missing {}", i + 1, key))?
+                    .parse::<f32>()
+                    .map_err(|_| format!("line {}: {} isn't a number", i + 1, key))
+            };
+            match tag.as_str() {
+                "common" => {
+                    if get("pages").unwrap_or(1.0) > 1.0 {
+                        return Err("fonts with several pages aren't supported".to_string());
+                    }
+                    self.line_height = get("lineHeight")?;
+                    self.base = get("base")?;
+                    self.atlas_size = (get("scaleW")?, get("scaleH")?);
+                }
+                "page" => {
+                    page = values.get("file").cloned();
+                }
+                "char" => {
+                    let id = get("id")? as u32;
+                    let c = ::std::char::from_u32(id)
+                        .ok_or_else(|| format!("line {}: invalid character {}", i + 1, id))?;
+                    self.glyphs.insert(c, Glyph {
+                        x: get("x")?,
+                        y: get("y")?,
+                        width: get("width")?,
+                        height: get("height")?,
+                        xoffset: get("xoffset")?,
+                        yoffset: get("yoffset")?,
+                        xadvance: get("xadvance")?,
+                    });
+                }
+                "kerning" => {
+                    let first = ::std::char::from_u32(get("first")? as u32);
+                    let second = ::std::char::from_u32(get("second")? as u32);
+                    if let (Some(first), Some(second)) = (first, second) {
+                        self.kerning.insert((first, second), get("amount")?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        page.ok_or_else(|| "no page file".to_string())
+    }
+
+    // Rasterise the font's characters into an RGBA atlas (white, with
+    // coverage in alpha) and fill in the glyph metrics
+    fn rasterize_ttf(&mut self, data: Vec<u8>) -> Image<u8> {
+        let font = rusttype::Font::from_bytes(data)
+            .unwrap_or_else(|e| panic!("Couldn't parse font {}: {}", self.path, e));
+        let scale = rusttype::Scale::uniform(self.size);
+        let v_metrics = font.v_metrics(scale);
+        self.line_height = (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap).ceil();
+        self.base = v_metrics.ascent;
+
+        let mut chars: Vec<char> = (32u8..127).chain(160u8..=255).map(|c| c as char).collect();
+        chars.extend(self.extra_chars.chars());
+        chars.sort();
+        chars.dedup();
+
+        // Pack the glyphs in rows, with a pixel of padding so linear
+        // filtering doesn't bleed between them
+        const ATLAS_WIDTH: i32 = 512;
+        let (mut x, mut y, mut row_height) = (1, 1, 0);
+        let mut placed = Vec::new();
+        for c in chars {
+            let glyph = font.glyph(c);
+            if glyph.id() == rusttype::GlyphId(0) {
+                continue;
+            }
+            let glyph = glyph.scaled(scale);
+            let xadvance = glyph.h_metrics().advance_width;
+            let glyph = glyph.positioned(rusttype::point(0.0, 0.0));
+            let bounds = match glyph.pixel_bounding_box() {
+                Some(bounds) => bounds,
+                None => {
+                    // Whitespace
+                    self.glyphs.insert(c, Glyph { xadvance, ..Glyph::default() });
+                    continue;
+                }
+            };
+            let (w, h) = (bounds.width(), bounds.height());
+            if x + w + 1 > ATLAS_WIDTH {
+                x = 1;
+                y += row_height + 1;
+                row_height = 0;
+            }
+            self.glyphs.insert(c, Glyph {
+                x: x as f32,
+                y: y as f32,
+                width: w as f32,
+                height: h as f32,
+                xoffset: bounds.min.x as f32,
+                yoffset: bounds.min.y as f32 + v_metrics.ascent,
+                xadvance,
+            });
+            placed.push((x, y, glyph));
+            x += w + 1;
+            row_height = row_height.max(h);
+        }
+
+        let height = ((y + row_height + 1) as u32).next_power_of_two() as usize;
+        let width = ATLAS_WIDTH as usize;
+        let mut pixels = vec![255u8; width * height * 4];
+        for alpha in pixels.chunks_mut(4) {
+            alpha[3] = 0;
+        }
+        for (x, y, glyph) in placed {
+            glyph.draw(|gx, gy, coverage| {
+                let i = 4 * ((y as usize + gy as usize) * width + x as usize + gx as usize);
+                pixels[i + 3] = (coverage * 255.0).round() as u8;
+            });
+        }
+
+        self.ttf = Some(font);
+        Image { width, height, depth: 4, data: pixels }
+    }
+
+    pub fn kerning(&self, first: char, second: char) -> f32 {
+        match self.ttf {
+            Some(ref font) => font.pair_kerning(rusttype::Scale::uniform(self.size), first, second),
+            None => self.kerning.get(&(first, second)).cloned().unwrap_or(0.0),
+        }
+    }
+
+    fn advance(&self, c: char) -> f32 {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?')).map_or(0.0, |g| g.xadvance)
+    }
+
+    // Place a character after the line's last one
+    fn append(&self, line: &mut Line, c: char) {
+        if let Some(&(prev, _)) = line.chars.last() {
+            line.pen += self.kerning(prev, c);
+        }
+        line.chars.push((c, line.pen));
+        line.pen += self.advance(c);
+        if !c.is_whitespace() {
+            line.width = line.pen;
+        }
+    }
+
+    // Split text into lines at '\n' and, given a width (in font pixels),
+    // wherever a word would stick out. Words that don't fit on a line of
+    // their own are broken between characters.
+    fn layout_lines(&self, text: &str, max_width: Option<f32>) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let max_width = match max_width {
+                Some(max_width) => max_width,
+                None => {
+                    let mut line = Line::new();
+                    for c in paragraph.chars() {
+                        self.append(&mut line, c);
+                    }
+                    lines.push(line);
+                    continue;
+                }
+            };
+
+            // Words and single spaces
+            let mut tokens: Vec<Vec<char>> = Vec::new();
+            for c in paragraph.chars() {
+                let new_token = match tokens.last() {
+                    Some(last) => c.is_whitespace() || last[0].is_whitespace(),
+                    None => true,
+                };
+                if new_token {
+                    tokens.push(vec![c]);
+                } else {
+                    tokens.last_mut().unwrap().push(c);
+                }
+            }
+
+            // Each token is placed once on the running line, and taken off
+            // again if it doesn't fit
+            let mut line = Line::new();
+            for token in tokens {
+                let checkpoint = line.checkpoint();
+                for &c in &token {
+                    self.append(&mut line, c);
+                }
+                if line.width <= max_width {
+                    continue;
+                }
+                line.restore(checkpoint);
+                if token[0].is_whitespace() {
+                    // Spaces at a line break are dropped
+                    continue;
+                }
+                if line.has_visible() {
+                    lines.push(mem::replace(&mut line, Line::new()));
+                }
+                for c in token {
+                    let checkpoint = line.checkpoint();
+                    self.append(&mut line, c);
+                    if checkpoint.0 > 0 && line.width > max_width {
+                        line.restore(checkpoint);
+                        lines.push(mem::replace(&mut line, Line::new()));
+                        self.append(&mut line, c);
+                    }
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    // Size of the text's bounding box when drawn with these parameters
+    pub fn measure_text(&self, text: &str, params: &TextParams) -> Vector2<f32> {
+        let lines = self.layout_lines(text, params.max_width.map(|w| w / params.scale));
+        let width = lines.iter().fold(0.0f32, |w, line| w.max(line.width));
+        Vector2::new(width, lines.len() as f32 * self.line_height) * params.scale
+    }
+
+    // Append two triangles per visible glyph
+    pub fn build_text(&self, text: &str, params: &TextParams, out: &mut Vec<SpriteVertex>) {
+        let scale = params.scale;
+        let color = [params.color.x, params.color.y, params.color.z, params.color.w];
+        let (atlas_w, atlas_h) = self.atlas_size;
+
+        let lines = self.layout_lines(text, params.max_width.map(|w| w / scale));
+        for (i, line) in lines.iter().enumerate() {
+            let left = match params.align {
+                TextAlign::Left => params.pos.x,
+                TextAlign::Center => params.pos.x - 0.5 * line.width * scale,
+                TextAlign::Right => params.pos.x - line.width * scale,
+            };
+            let top = params.pos.y + i as f32 * self.line_height * scale;
+
+            for &(c, pen) in &line.chars {
+                let glyph = match self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?')) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                if glyph.width == 0.0 || glyph.height == 0.0 {
+                    continue;
+                }
+                let x0 = left + (pen + glyph.xoffset) * scale;
+                let y0 = top + glyph.yoffset * scale;
+                let (x1, y1) = (x0 + glyph.width * scale, y0 + glyph.height * scale);
+                let (u0, v0) = (glyph.x / atlas_w, glyph.y / atlas_h);
+                let (u1, v1) = ((glyph.x + glyph.width) / atlas_w, (glyph.y + glyph.height) / atlas_h);
+
+                let corners = [([x0, y0], [u0, v0]), ([x1, y0], [u1, v0]), ([x1, y1], [u1, v1]), ([x0, y1], [u0, v1])];
+                for &k in &[0, 1, 2, 0, 2, 3] {
+                    out.push(SpriteVertex { pos: corners[k].0, uv: corners[k].1, color });
+                }
+            }
+        }
+    }
+}
+
+impl<'a> SpriteRenderer<'a> {
+    // Queue text, sorted and batched together with sprites
+    pub fn submit_text(&mut self, font: &Font, text: &str, params: &TextParams) {
+        let mut vertices = Vec::new();
+        font.build_text(text, params, &mut vertices);
+        let state = DrawState {
+            shader: self.sprite_shader(),
+            blend: params.blend,
+            texture: font.texture(),
+            layer: params.layer,
+            depth: ::std::f32::NEG_INFINITY,
+        };
+        self.submit_triangles(state, &vertices);
+    }
+
+    // Draws immediately, prefer submit_text when drawing along with sprites
+    pub fn draw_text(&mut self, font: &Font, text: &str, params: &TextParams) {
+        let mut vertices = Vec::new();
+        font.build_text(text, params, &mut vertices);
+        let texture = self.textures().get(font.texture());
+        self.draw_triangles(Some(texture), params.blend, &vertices);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use font::*;
+    use sprite_renderer::{test_renderer, test_shaders};
+    use render_backend::SoftwareBackend;
+    use capture::{capture_screen, assert_golden};
+
+    const TEST_FNT: &str = r#"info face="Test" size=8 unicode=1
+common lineHeight=10 base=8 scaleW=64 scaleH=64 pages=1
+page id=0 file="test font.png"
+chars count=4
+char id=32 x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=4 page=0 chnl=15
+char id=65 x=0 y=0 width=6 height=8 xoffset=0 yoffset=0 xadvance=6 page=0 chnl=15
+char id=86 x=6 y=0 width=6 height=8 xoffset=0 yoffset=0 xadvance=6 page=0 chnl=15
+char id=233 x=12 y=0 width=5 height=8 xoffset=0 yoffset=1 xadvance=5 page=0 chnl=15
+kernings count=1
+kerning first=65 second=86 amount=-2
+"#;
+
+    fn test_font() -> Font {
+        let mut font = Font::new("test.fnt", 0.0);
+        assert_eq!(font.parse_bmfont(TEST_FNT), Ok("test font.png".to_string()));
+        font
+    }
+
+    #[test]
+    fn test_parse_bmfont() {
+        let font = test_font();
+        assert_eq!(font.line_height(), 10.0);
+        assert_eq!(font.base(), 8.0);
+        assert_eq!(font.glyph('é').map(|g| g.yoffset), Some(1.0));
+        assert_eq!(font.kerning('A', 'V'), -2.0);
+        assert_eq!(font.kerning('V', 'A'), 0.0);
+
+        let mut font = Font::new("broken.fnt", 0.0);
+        assert!(font.parse_bmfont("common lineHeight=10 base=x scaleW=1 scaleH=1\n").unwrap_err().contains("line 1"));
+    }
+
+    #[test]
+    fn test_measure_text() {
+        let font = test_font();
+        let params = TextParams::new(Vector2::new(0.0, 0.0));
+        // Kerning pulls the V towards the A
+        assert_eq!(font.measure_text("AV", &params), Vector2::new(10.0, 10.0));
+        assert_eq!(font.measure_text("AAé\nA", &params), Vector2::new(17.0, 20.0));
+        assert_eq!(font.measure_text("AV", &params.scale(2.0)), Vector2::new(20.0, 20.0));
+
+        // "AAA AA A" wraps into "AAA" and "AA A", dropping the space at the break
+        let wrapped = params.max_width(24.0);
+        assert_eq!(font.measure_text("AAA AA A", &wrapped), Vector2::new(22.0, 20.0));
+        assert_eq!(font.layout_lines("AAA AA A", Some(20.0)).len(), 3);
+        // Too long for a line of its own, so split between characters
+        assert_eq!(font.layout_lines("AAAAAAAA", Some(20.0)).len(), 3);
+        // Wrapping places text the same as not wrapping, kerning included
+        assert_eq!(font.measure_text("AV AV", &params.max_width(100.0)), font.measure_text("AV AV", &params));
+    }
+
+    #[test]
+    fn test_build_text_alignment() {
+        let font = test_font();
+        let mut vertices = Vec::new();
+        font.build_text("A A", &TextParams::new(Vector2::new(100.0, 0.0)).align(TextAlign::Right), &mut vertices);
+        // Spaces have no quad
+        assert_eq!(vertices.len(), 12);
+        let right = vertices.iter().fold(0.0f32, |m, v| m.max(v.pos[0]));
+        assert_eq!(right, 100.0);
+
+        vertices.clear();
+        font.build_text("A", &TextParams::new(Vector2::new(100.0, 0.0)).align(TextAlign::Center), &mut vertices);
+        assert_eq!(vertices[0].pos, [97.0, 0.0]);
+        assert_eq!(vertices[0].uv, [0.0, 0.0]);
+        assert_eq!(vertices[2].uv, [6.0 / 64.0, 8.0 / 64.0]);
+    }
+
+    #[test]
+    fn test_golden_ttf_text() {
+        let mut textures = Storage::new(1);
+        let mut font = Font::new("fonts/DejaVuSans.ttf", 16.0);
+        font.load_with(&mut SoftwareBackend::new(1, 1), &mut textures);
+        assert!(font.glyph('ü').is_some());
+
+        let sprites = Storage::new(1);
+        let shaders = test_shaders();
+        let mut renderer = test_renderer(200, 100, &shaders, &textures, &sprites);
+        renderer.backend_mut().clear(Vector4::new(0.1, 0.1, 0.2, 1.0));
+
+        renderer.submit_text(&font, "AVATAR Wärme", &TextParams::new(Vector2::new(4.0, 4.0)));
+        renderer.submit_text(&font, "centered and wrapped to fit",
+                             &TextParams::new(Vector2::new(100.0, 30.0))
+                                 .align(TextAlign::Center)
+                                 .max_width(120.0)
+                                 .color(Vector4::new(1.0, 0.8, 0.2, 1.0)));
+        renderer.end_frame();
+
+        assert_golden(&capture_screen(renderer.backend_mut(), 200, 100), "ttf_text", 2);
+    }
+}
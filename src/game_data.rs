@@ -7,6 +7,8 @@ use storage::Storage;
 use sprite::SpriteData;
 use texture::Texture;
 use shader::Shader;
+use font::Font;
+use render_backend::{RenderBackend, GlBackend};
 
 fn load_file(filename: &str) -> String {
     use std::io::Read;
@@ -31,6 +33,7 @@ pub struct GameData {
     pub sprites: Storage<SpriteData>,
     pub textures: Storage<Texture>,
     pub shaders: Storage<Shader>,
+    pub fonts: Storage<Font>,
 }
 
 impl GameData {
@@ -38,9 +41,10 @@ impl GameData {
         let sprites = Storage::new(16);
         let textures = Storage::new(16);
         let shaders = Storage::new(16);
+        let fonts = Storage::new(16);
 
         let game_data = GameData {
-            sprites, textures, shaders
+            sprites, textures, shaders, fonts
         };
 
         game_data.save();
@@ -54,22 +58,39 @@ impl GameData {
                   serde_json::to_string_pretty(&self.textures).unwrap().as_bytes());
         save_file(&storage_path("shaders.json"),
                   serde_json::to_string_pretty(&self.shaders).unwrap().as_bytes());
+        save_file(&storage_path("fonts.json"),
+                  serde_json::to_string_pretty(&self.fonts).unwrap().as_bytes());
     }
 
     pub fn from_file() -> Self {
+        GameData::from_file_with(&mut GlBackend::new())
+    }
+
+    // Load everything, creating shaders and textures through the given backend
+    pub fn from_file_with(backend: &mut dyn RenderBackend) -> Self {
         let sprite_data = load_file(&storage_path("sprites.json"));
         let texture_data = load_file(&storage_path("textures.json"));
         let shaders_data = load_file(&storage_path("shaders.json"));
+        let fonts_data = load_file(&storage_path("fonts.json"));
 
         let mut shaders: Storage<Shader> = serde_json::from_str(&shaders_data).unwrap();
         let mut textures: Storage<Texture> = serde_json::from_str(&texture_data).unwrap();
         let sprites: Storage<SpriteData> = serde_json::from_str(&sprite_data).unwrap();
+        let mut fonts: Storage<Font> = serde_json::from_str(&fonts_data).unwrap();
+
+        // Font atlases are generated on load, drop any that were saved
+        fonts.iterate(|f| textures.release_by_name(&f.atlas_name()));
 
-        shaders.iterate_mut(|s| { s.compile(); });
-        textures.iterate_mut(|t| { t.load(); });
+        shaders.iterate_mut(|s| {
+            if let Err(e) = backend.compile_shader(s) {
+                panic!("{}", e);
+            }
+        });
+        textures.iterate_mut(|t| { t.load_with(backend); });
+        fonts.iterate_mut(|f| f.load_with(backend, &mut textures));
 
         GameData {
-            sprites, textures, shaders
+            sprites, textures, shaders, fonts
         }
     }
 }
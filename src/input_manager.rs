@@ -67,6 +67,24 @@ impl InputManager {
         };
     }
 
+    // Start a new frame without an event pump, keeping what's held down.
+    // For headless runs, where input is scripted with set_key.
+    pub fn update_without_events(&mut self) {
+        self.prev_input_state = self.cur_input_state.clone();
+    }
+
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        if pressed {
+            self.cur_input_state.keys_down.insert(key);
+        } else {
+            self.cur_input_state.keys_down.remove(&key);
+        }
+    }
+
+    pub fn set_mouse_pos(&mut self, pos: (i32, i32)) {
+        self.cur_input_state.mouse_position = pos;
+    }
+
     pub fn is_key_pressed(&self, key: Key) -> bool {
         self.cur_input_state.keys_down.contains(&key)
     }
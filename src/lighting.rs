@@ -0,0 +1,524 @@
+use std::f32::consts::PI;
+
+use cgmath::{self, Vector2, Vector3, InnerSpace};
+
+use storage::Storage;
+use texture::Texture;
+use shader::{Shader, UniformValue};
+use camera::Camera2D;
+use render_target::{RenderTarget, RenderTargetBuilder};
+use render_backend::{RenderBackend, BufferKind, BufferId, SpriteVertex, BlendMode, Fragment, smoothstep};
+use sprite_renderer::{SpriteRenderer, software_sprite_shader};
+
+// A line blocking light, in world space
+pub type Segment = [Vector2<f32>; 2];
+
+// Rays cast around a full circle, besides the ones towards occluder corners
+const CIRCLE_RAYS: usize = 48;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Cone {
+    // Degrees, clockwise from +x like sprite rotations
+    pub direction: f32,
+    // Half the opening angle, in degrees
+    pub angle: f32,
+    // Degrees inside the edge over which the light fades out
+    pub softness: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub pos: Vector2<f32>,
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub radius: f32,
+    // Exponent applied to (1 - distance / radius)
+    pub falloff: f32,
+    // Height above the scene in pixels, low lights graze normal maps more
+    pub height: f32,
+    pub cone: Option<Cone>,
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn point(pos: Vector2<f32>, radius: f32) -> Self {
+        Light {
+            pos,
+            color: Vector3::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            radius,
+            falloff: 1.0,
+            height: 32.0,
+            cone: None,
+            cast_shadows: true,
+        }
+    }
+
+    pub fn cone(pos: Vector2<f32>, radius: f32, direction: f32, angle: f32) -> Self {
+        let mut light = Light::point(pos, radius);
+        light.cone = Some(Cone { direction, angle, softness: 10.0 });
+        light
+    }
+
+    pub fn color(mut self, color: Vector3<f32>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn falloff(mut self, falloff: f32) -> Self {
+        self.falloff = falloff;
+        self
+    }
+
+    pub fn height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn cast_shadows(mut self, cast_shadows: bool) -> Self {
+        self.cast_shadows = cast_shadows;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LightId(usize);
+
+// Outline of the solid cells of a grid: the sides of solid tiles that
+// don't touch another solid tile. `solid` is only asked about cells inside
+// the grid, everything outside counts as empty.
+pub fn tile_edges<F>(num_x: usize, num_y: usize, tile_size: f32, solid: F) -> Vec<Segment>
+    where F: Fn(i32, i32) -> bool {
+    let is_solid = |x: i32, y: i32| x >= 0 && y >= 0 && x < num_x as i32 && y < num_y as i32 && solid(x, y);
+    let corner = |x: i32, y: i32| Vector2::new(x as f32 * tile_size, y as f32 * tile_size);
+
+    let mut edges = Vec::new();
+    for y in 0..num_y as i32 {
+        for x in 0..num_x as i32 {
+            if !is_solid(x, y) {
+                continue;
+            }
+            if !is_solid(x, y - 1) {
+                edges.push([corner(x, y), corner(x + 1, y)]);
+            }
+            if !is_solid(x + 1, y) {
+                edges.push([corner(x + 1, y), corner(x + 1, y + 1)]);
+            }
+            if !is_solid(x, y + 1) {
+                edges.push([corner(x + 1, y + 1), corner(x, y + 1)]);
+            }
+            if !is_solid(x - 1, y) {
+                edges.push([corner(x, y + 1), corner(x, y)]);
+            }
+        }
+    }
+    edges
+}
+
+fn cross(a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+// Distance along the ray to the segment, if it's hit
+fn ray_hit(origin: Vector2<f32>, dir: Vector2<f32>, segment: &Segment) -> Option<f32> {
+    let edge = segment[1] - segment[0];
+    let denom = cross(dir, edge);
+    if denom.abs() < 1e-6 {
+        return None;
+    }
+    let to_start = segment[0] - origin;
+    let t = cross(to_start, edge) / denom;
+    let u = cross(to_start, dir) / denom;
+    if t >= 0.0 && u >= 0.0 && u <= 1.0 { Some(t) } else { None }
+}
+
+// Wrap an angle in radians into -PI..PI
+fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI) % (2.0 * PI);
+    if wrapped < 0.0 { wrapped + PI } else { wrapped - PI }
+}
+
+// The outline of what a light reaches, ordered by angle: where rays from
+// the light stop at an occluder or at its radius. Rays go around the
+// circle and just past each occluder corner, so shadows get sharp edges.
+// A cone light's outline runs from one side of the cone to the other,
+// and is meant to be closed through the light's position.
+pub fn light_polygon(light: &Light, occluders: &[Segment]) -> Vec<Vector2<f32>> {
+    let (center, radius) = (light.pos, light.radius);
+    let nearby: Vec<&Segment> = occluders.iter().filter(|s| {
+        let (min_x, max_x) = (s[0].x.min(s[1].x), s[0].x.max(s[1].x));
+        let (min_y, max_y) = (s[0].y.min(s[1].y), s[0].y.max(s[1].y));
+        max_x >= center.x - radius && min_x <= center.x + radius &&
+            max_y >= center.y - radius && min_y <= center.y + radius
+    }).collect();
+
+    // Angles relative to the cone's direction (or +x), all within the cone
+    let (base, half_angle) = match light.cone {
+        Some(cone) => (cone.direction.to_radians(), cone.angle.to_radians().min(PI)),
+        None => (0.0, PI),
+    };
+    let mut angles: Vec<f32> = (0..CIRCLE_RAYS)
+        .map(|i| -PI + 2.0 * PI * i as f32 / CIRCLE_RAYS as f32)
+        .filter(|a| a.abs() <= half_angle)
+        .collect();
+    if light.cone.is_some() {
+        angles.push(-half_angle);
+        angles.push(half_angle);
+    }
+    for segment in &nearby {
+        for &point in segment.iter() {
+            let to_point = point - center;
+            if to_point.magnitude2() > radius * radius {
+                continue;
+            }
+            let angle = wrap_angle(to_point.y.atan2(to_point.x) - base);
+            for &a in &[angle - 1e-4, angle, angle + 1e-4] {
+                if a.abs() <= half_angle {
+                    angles.push(a);
+                }
+            }
+        }
+    }
+    angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    angles.dedup();
+
+    angles.iter().map(|&angle| {
+        let dir = Vector2::new((angle + base).cos(), (angle + base).sin());
+        let distance = nearby.iter()
+            .filter_map(|s| ray_hit(center, dir, s))
+            .fold(radius, f32::min);
+        center + dir * distance
+    }).collect()
+}
+
+// Triangles fanning out from the light to its outline
+fn light_triangles(light: &Light, outline: &[Vector2<f32>], out: &mut Vec<SpriteVertex>) {
+    let vertex = |p: Vector2<f32>| SpriteVertex { pos: [p.x, p.y], uv: [0.0, 0.0], color: [1.0; 4] };
+    let count = outline.len();
+    let segments = if light.cone.is_some() { count.saturating_sub(1) } else { count };
+    for i in 0..segments {
+        out.push(vertex(light.pos));
+        out.push(vertex(outline[i]));
+        out.push(vertex(outline[(i + 1) % count]));
+    }
+}
+
+// Ambient light plus point and cone lights, accumulated into a light buffer
+// that gets multiplied over the scene. Sprites can bring normal maps, which
+// get rendered into a normal buffer first (see render_normals). Everything
+// goes through the backend passed to new(), which has to be passed to the
+// other methods too.
+pub struct Lighting {
+    pub ambient: Vector3<f32>,
+    pub enabled: bool,
+    lights: Vec<Option<Light>>,
+    occluders: Vec<Segment>,
+
+    light_shader: Shader,
+    composite_shader: Shader,
+    normal_target: RenderTarget,
+    light_target: RenderTarget,
+    vertex_buffer: BufferId,
+    width: u32,
+    height: u32,
+}
+
+// lighting/light.shader on the SoftwareBackend
+fn software_light(fragment: &Fragment) -> [f32; 4] {
+    let to_pixel = fragment.pos - fragment.vec2("light_pos", Vector2::new(0.0, 0.0));
+    let dist = to_pixel.magnitude();
+    let radius = fragment.float("radius", 100.0);
+    let mut attenuation = (1.0 - dist / radius).max(0.0).min(1.0).powf(fragment.float("falloff", 1.0));
+
+    let cone_cos = fragment.float("cone_cos", -2.0);
+    if cone_cos >= -1.0 {
+        let c = (to_pixel / dist.max(0.0001)).dot(fragment.vec2("cone_direction", Vector2::new(1.0, 0.0)));
+        attenuation *= smoothstep(cone_cos, cone_cos + fragment.float("cone_edge", 0.0), c);
+    }
+
+    // Normal maps point +y up, the world's y axis points down
+    let resolution = fragment.vec2("resolution", Vector2::new(800.0, 600.0));
+    let texel = fragment.sample(fragment.coord.x / resolution.x, fragment.coord.y / resolution.y);
+    let normal = Vector3::new(texel[0] * 2.0 - 1.0, -(texel[1] * 2.0 - 1.0), texel[2] * 2.0 - 1.0);
+    let to_light = Vector3::new(-to_pixel.x, -to_pixel.y, fragment.float("height", 32.0)).normalize();
+    let diffuse = normal.normalize().dot(to_light).max(0.0);
+
+    let light = fragment.vec3("light_color", Vector3::new(1.0, 1.0, 1.0)) * attenuation * diffuse;
+    [light.x, light.y, light.z, 1.0]
+}
+
+impl Lighting {
+    pub fn new(backend: &mut dyn RenderBackend, textures: &mut Storage<Texture>, width: u32, height: u32) -> Self {
+        let mut compile = |path: &str| {
+            let mut shader = Shader::from_file(path.to_string());
+            if let Err(e) = backend.compile_shader(&mut shader) {
+                panic!("{}", e);
+            }
+            shader
+        };
+        let mut light_shader = compile("lighting/light.shader");
+        light_shader.set_software(software_light);
+        let mut composite_shader = compile("sprite.shader");
+        composite_shader.set_software(software_sprite_shader);
+
+        let normal_target = RenderTargetBuilder::new(width, height).build(backend, textures, "lighting.normals");
+        let light_target = RenderTargetBuilder::new(width, height).build(backend, textures, "lighting.lights");
+        let vertex_buffer = backend.create_buffer(BufferKind::Vertex);
+
+        Lighting {
+            ambient: Vector3::new(1.0, 1.0, 1.0),
+            enabled: true,
+            lights: Vec::new(),
+            occluders: Vec::new(),
+            light_shader,
+            composite_shader,
+            normal_target,
+            light_target,
+            vertex_buffer,
+            width,
+            height,
+        }
+    }
+
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        match self.lights.iter().position(|l| l.is_none()) {
+            Some(index) => {
+                self.lights[index] = Some(light);
+                LightId(index)
+            }
+            None => {
+                self.lights.push(Some(light));
+                LightId(self.lights.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove_light(&mut self, id: LightId) {
+        self.lights[id.0] = None;
+    }
+
+    pub fn light(&self, id: LightId) -> &Light {
+        self.lights[id.0].as_ref().expect("Light was removed")
+    }
+
+    pub fn light_mut(&mut self, id: LightId) -> &mut Light {
+        self.lights[id.0].as_mut().expect("Light was removed")
+    }
+
+    pub fn light_count(&self) -> usize {
+        self.lights.iter().filter(|l| l.is_some()).count()
+    }
+
+    // Lines that lights with cast_shadows can't shine through, e.g.
+    // Canvas::shadow_edges()
+    pub fn set_occluders(&mut self, occluders: Vec<Segment>) {
+        self.occluders = occluders;
+    }
+
+    pub fn resize(&mut self, backend: &mut dyn RenderBackend, textures: &mut Storage<Texture>, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.normal_target.resize(backend, textures, width, height);
+        self.light_target.resize(backend, textures, width, height);
+    }
+
+    // Free the buffers and render targets
+    pub fn release(self, backend: &mut dyn RenderBackend, textures: &mut Storage<Texture>) {
+        backend.delete_buffer(self.vertex_buffer);
+        self.normal_target.release(backend, textures);
+        self.light_target.release(backend, textures);
+    }
+
+    // Render the normal buffer: `submit` should queue everything the lights
+    // fall on, with the same camera as the scene. Leaves the normal buffer
+    // bound.
+    pub fn render_normals<'a, F>(&self, renderer: &mut SpriteRenderer<'a>, submit: F)
+        where F: FnOnce(&mut SpriteRenderer<'a>) {
+        if !self.enabled {
+            return;
+        }
+        renderer.flush();
+        self.normal_target.clear(renderer.backend_mut(), 0.5, 0.5, 1.0, 1.0);
+        renderer.set_normal_pass(true);
+        submit(renderer);
+        renderer.set_normal_pass(false);
+    }
+
+    // Accumulate the ambient light and every light in view into the light
+    // buffer. Leaves the light buffer bound.
+    pub fn render(&mut self, backend: &mut dyn RenderBackend, textures: &Storage<Texture>, camera: &Camera2D) {
+        if !self.enabled {
+            return;
+        }
+        let ambient = self.ambient;
+        self.light_target.clear(backend, ambient.x, ambient.y, ambient.z, 1.0);
+
+        let (view_min, view_max) = camera.visible_bounds();
+        let normals = textures.get(self.normal_target.texture());
+        let mut vertices = Vec::new();
+        for light in self.lights.iter().filter_map(|l| l.as_ref()) {
+            let (pos, radius) = (light.pos, light.radius);
+            if pos.x + radius < view_min.x || pos.x - radius > view_max.x ||
+                pos.y + radius < view_min.y || pos.y - radius > view_max.y {
+                continue;
+            }
+
+            let occluders: &[Segment] = if light.cast_shadows { &self.occluders } else { &[] };
+            vertices.clear();
+            light_triangles(light, &light_polygon(light, occluders), &mut vertices);
+            if vertices.is_empty() {
+                continue;
+            }
+            backend.upload_vertices(self.vertex_buffer, &vertices);
+
+            let shader = &self.light_shader;
+            backend.bind_shader(shader, camera.view_projection());
+            let mut set = |name: &str, value: UniformValue| backend.set_uniform(shader, name, value);
+            set("resolution", UniformValue::Vec2(Vector2::new(self.width as f32, self.height as f32)));
+            set("light_pos", UniformValue::Vec2(pos));
+            set("light_color", UniformValue::Vec3(light.color * light.intensity));
+            set("radius", UniformValue::Float(radius));
+            set("falloff", UniformValue::Float(light.falloff));
+            set("height", UniformValue::Float(light.height));
+            match light.cone {
+                Some(cone) => {
+                    let direction = cone.direction.to_radians();
+                    let cone_cos = cone.angle.to_radians().cos();
+                    let inner_cos = (cone.angle - cone.softness).max(0.0).to_radians().cos();
+                    set("cone_direction", UniformValue::Vec2(Vector2::new(direction.cos(), direction.sin())));
+                    set("cone_cos", UniformValue::Float(cone_cos));
+                    set("cone_edge", UniformValue::Float(inner_cos - cone_cos));
+                }
+                None => set("cone_cos", UniformValue::Float(-2.0)),
+            }
+            backend.draw(self.vertex_buffer, 0, vertices.len(), normals, BlendMode::Additive);
+        }
+    }
+
+    // Multiply the light buffer over `dest`, or the window if None
+    pub fn composite(&mut self, backend: &mut dyn RenderBackend, textures: &Storage<Texture>,
+                     dest: Option<&RenderTarget>) {
+        if !self.enabled {
+            return;
+        }
+        match dest {
+            Some(target) => target.bind(backend),
+            None => RenderTarget::bind_default(backend, self.width, self.height),
+        }
+
+        // Unit quad, both buffers are stored bottom-up so no flip needed
+        let vertex = |x: f32, y: f32| SpriteVertex { pos: [x, y], uv: [x, y], color: [1.0; 4] };
+        let quad = [vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0),
+                    vertex(0.0, 0.0), vertex(1.0, 1.0), vertex(0.0, 1.0)];
+        backend.upload_vertices(self.vertex_buffer, &quad);
+        backend.bind_shader(&self.composite_shader, cgmath::ortho(0.0, 1.0, 0.0, 1.0, -1.0, 1.0));
+        backend.draw(self.vertex_buffer, 0, quad.len(), textures.get(self.light_target.texture()), BlendMode::Multiply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lighting::*;
+    use cgmath::Vector4;
+    use render_backend::SoftwareBackend;
+    use sprite_renderer::test_shaders;
+
+    #[test]
+    fn test_tile_edges() {
+        // Two tiles next to each other and one on its own
+        let solid = |x: i32, y: i32| (y == 0 && x < 2) || (x == 3 && y == 2);
+        let edges = tile_edges(4, 3, 10.0, solid);
+        // The shared side between the first two isn't an edge
+        assert_eq!(edges.len(), 6 + 4);
+        assert!(edges.contains(&[Vector2::new(0.0, 0.0), Vector2::new(10.0, 0.0)]));
+        assert!(!edges.iter().any(|e| e[0].x == 10.0 && e[1].x == 10.0));
+        assert!(edges.contains(&[Vector2::new(40.0, 20.0), Vector2::new(40.0, 30.0)]));
+    }
+
+    #[test]
+    fn test_light_polygon_shadows() {
+        let light = Light::point(Vector2::new(0.0, 0.0), 100.0);
+        let open = light_polygon(&light, &[]);
+        assert_eq!(open.len(), CIRCLE_RAYS);
+        assert!(open.iter().all(|p| (p.magnitude() - 100.0).abs() < 1e-3));
+
+        // A wall 50 to the right, from 30 above to 30 below the light
+        let wall = [Vector2::new(50.0, -30.0), Vector2::new(50.0, 30.0)];
+        let outline = light_polygon(&light, &[wall]);
+        let towards = |angle: f32| {
+            let angle = angle.to_radians();
+            outline.iter().min_by(|a, b| {
+                let da = wrap_angle(a.y.atan2(a.x) - angle).abs();
+                let db = wrap_angle(b.y.atan2(b.x) - angle).abs();
+                da.partial_cmp(&db).unwrap()
+            }).unwrap().magnitude()
+        };
+        assert!((towards(0.0) - 50.0).abs() < 1e-3);
+        assert!((towards(90.0) - 100.0).abs() < 1e-3);
+        // The corners of the wall are part of the outline
+        assert!(outline.iter().any(|p| (p - wall[0]).magnitude() < 0.1));
+
+        // Lights that don't cast shadows ignore it, see Lighting::render
+        assert_eq!(light_polygon(&light.cast_shadows(false), &[]).len(), CIRCLE_RAYS);
+    }
+
+    #[test]
+    fn test_cone_light() {
+        // Pointing down (+y), 45 degrees to either side
+        let light = Light::cone(Vector2::new(10.0, 10.0), 50.0, 90.0, 45.0);
+        let outline = light_polygon(&light, &[]);
+        for p in &outline {
+            let to_p = p - light.pos;
+            let angle = to_p.y.atan2(to_p.x).to_degrees();
+            assert!(angle >= 45.0 - 1e-3 && angle <= 135.0 + 1e-3, "{} is outside the cone", angle);
+        }
+        let first = outline[0] - light.pos;
+        assert!((first.y.atan2(first.x).to_degrees() - 45.0).abs() < 1e-3);
+
+        let mut triangles = Vec::new();
+        light_triangles(&light, &outline, &mut triangles);
+        // Not closed around the back of the light
+        assert_eq!(triangles.len(), 3 * (outline.len() - 1));
+    }
+
+    #[test]
+    fn test_software_lighting() {
+        // Runs software_light, a Rust copy of light.shader; the GLSL itself
+        // isn't covered
+        let mut backend = SoftwareBackend::new(32, 32);
+        let mut textures = Storage::new(4);
+        let mut lighting = Lighting::new(&mut backend, &mut textures, 32, 32);
+        lighting.ambient = Vector3::new(0.25, 0.25, 0.25);
+        lighting.add_light(Light::point(Vector2::new(16.0, 16.0), 10.0).intensity(0.5).cast_shadows(false));
+
+        let sprites = Storage::new(1);
+        let shaders = test_shaders();
+        let camera = Camera2D::new(32.0, 32.0);
+        {
+            let mut renderer = SpriteRenderer::with_backend(Box::new(&mut backend), &shaders, &textures, &sprites);
+            renderer.set_camera(&camera);
+            // Flat normals, nothing drawn over them
+            lighting.render_normals(&mut renderer, |_| {});
+            lighting.render(renderer.backend_mut(), &textures, &camera);
+
+            RenderTarget::bind_default(renderer.backend_mut(), 32, 32);
+            renderer.backend_mut().clear(Vector4::new(0.8, 0.8, 0.8, 1.0));
+            lighting.composite(renderer.backend_mut(), &textures, None);
+        }
+
+        // Out of the light's reach: 0.8 * ambient
+        assert_eq!(backend.pixel(1, 1), [51, 51, 51, 255]);
+        // Half a pixel diagonally from it: 0.8 * (ambient + 0.5 * (1 - 0.71 / 10))
+        let lit = backend.pixel(16, 16);
+        assert!((lit[0] as i32 - 146).abs() <= 2, "{:?}", lit);
+        assert_eq!((lit[0], lit[3]), (lit[2], 255));
+
+        lighting.release(&mut backend, &mut textures);
+        assert_eq!(textures.size(), 0);
+    }
+}
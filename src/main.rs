@@ -12,6 +12,9 @@ extern crate sdl2;
 extern crate cgmath;
 extern crate stb_image;
 extern crate find_folder;
+extern crate rand;
+extern crate png;
+extern crate rusttype;
 
 extern crate arrayvec;
 
@@ -22,12 +25,11 @@ extern crate serde;
 extern crate toml;
 extern crate serde_json;
 
-#[macro_use]
-extern crate wren;
-
 #[macro_use] mod big_array;
 
 mod shader;
+mod shader_preprocessor;
+mod shader_file;
 mod texture;
 mod storage;
 mod sprite_renderer;
@@ -38,259 +40,459 @@ mod input_manager;
 mod asset_manager;
 mod game_data;
 mod path;
+mod render_target;
+mod camera;
+mod render_backend;
+mod capture;
+mod debug_draw;
+mod shape_renderer;
+mod font;
+mod post_process;
+mod lighting;
+mod nine_slice;
+mod window;
+mod app;
+mod time;
+mod scene;
+mod ecs;
+mod transform;
 
 #[cfg(not(use_gl_crate))]
 mod gl;
 
-use shader::Shader;
-use texture::{Texture, TextureBuilder};
-use storage::{Storage, ResourceID};
-use sprite_renderer::SpriteRenderer;
+use storage::ResourceID;
+use sprite_renderer::{SpriteRenderer, SpriteParams, Sprite};
+use ecs::{World, Entity};
+use transform::{Transform2D, Parent};
 use canvas::Canvas;
-use sprite::{SpriteData, SpriteBounds};
-use input_manager::{InputManager, Key};
+use camera::Camera2D;
+use shape_renderer::{ShapeRenderer, ShapeParams};
+use font::{Font, TextParams, TextAlign};
+use post_process::{PostProcess, PostProcessConfig, Effect};
+use lighting::{Lighting, Light, LightId};
+use nine_slice::NineSliceParams;
+use window::{Window, WindowConfig};
+use app::{App, Context};
+use scene::{Scene, SceneChange, SceneManager, Transition};
+use render_target::RenderTarget;
+use render_backend::RenderBackend;
+use sprite::SpriteData;
+use input_manager::Key;
 use game_data::GameData;
-use path::{asset_path, storage_path};
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::video::GLProfile;
-
-use stb_image::image;
-
-use cgmath::{Vector2, Vector3};
 
-use std::time::Duration;
-use std::collections::HashMap;
+use cgmath::{Vector2, Vector3, Vector4};
 
-/*
-struct App<'a> {
-    sdl_context: Sdl,
-    video_subsystem: VideoSubsystem,
-    window: sdl2::Window,
-
-    sprite_renderer: SpriteRenderer<'a>,
-    event_pump: sdl2::EventPump,
-    input_manager: InputManager,
-
-    test_tex_ref: ResourceID<Texture>,
-    spritesheet_tex_ref: ResourceID<Texture>,
-    sprite_id: ResourceID<SpriteData>,
+// Press enter to start
+struct Title {
+    font_id: ResourceID<Font>,
 }
-*/
 
-fn find_sdl_gl_driver() -> Option<u32> {
-    for (index, item) in sdl2::render::drivers().enumerate() {
-        if item.name == "opengl" {
-            return Some(index as u32);
-        }
+impl Title {
+    fn new(data: &GameData) -> Self {
+        let (_, font_id) = data.fonts.get_by_name("dejavu_sans").unwrap();
+        Title { font_id }
     }
-    None
 }
 
+impl Scene for Title {
+    fn event(&mut self, ctx: &mut Context, event: &Event) -> SceneChange {
+        match *event {
+            Event::KeyDown { keycode: Some(Keycode::Return), repeat: false, .. } => {
+                let overworld = Overworld::new(ctx.data, ctx.window.render_size());
+                SceneChange::Replace(Box::new(overworld), Transition::fade(1.0))
+            },
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => SceneChange::Quit,
+            _ => SceneChange::None,
+        }
+    }
 
-lazy_static! {
-    static ref FOREIGN_METHODS: HashMap<&'static str, wren::ForeignMethodFn> = {
-        let mut map = HashMap::new();
-        map
-    };
+    fn render(&mut self, ctx: &mut Context, renderer: &mut SpriteRenderer, _alpha: f32) {
+        let (x, y, width, height) = ctx.window.present_rect();
+        let (drawable_w, drawable_h) = ctx.window.drawable_size();
+        let backend = renderer.backend_mut();
+        RenderTarget::bind_default(backend, drawable_w, drawable_h);
+        backend.clear(Vector4::new(0.0, 0.0, 0.0, 1.0));
+        backend.set_viewport(x, y, width, height);
+
+        let (render_w, render_h) = ctx.window.render_size();
+        let size = Vector2::new(render_w as f32, render_h as f32);
+        renderer.set_camera(&Camera2D::new(size.x, size.y));
+        ShapeRenderer::new(renderer).fill_rect(Vector2::new(0.0, 0.0), size,
+                                               &ShapeParams::new(Vector4::new(0.1, 0.15, 0.3, 1.0)));
+        let font = ctx.data.fonts.get(self.font_id);
+        renderer.submit_text(font, "gengine", &TextParams::new(size * 0.5 - Vector2::new(0.0, 40.0))
+            .align(TextAlign::Center)
+            .scale(2.0)
+            .layer(1));
+        renderer.submit_text(font, "Press enter to start", &TextParams::new(size * 0.5 + Vector2::new(0.0, 30.0))
+            .align(TextAlign::Center)
+            .layer(1));
+    }
 }
 
-lazy_static! {
-    static ref FOREIGN_CLASSES: HashMap<&'static str, wren::ForeignClassMethods> = {
-        let mut map = HashMap::new();
-        /*
-        let mut vec3_class_methods = wren::ForeignClassMethods::new();
-        vec3_class_methods.set_allocate_fn(wren_foreign_method_fn!(vec3_allocate));
-        vec3_class_methods.set_finalize_fn(wren_finalizer_fn!(vec3_finalize));
-
-        map.insert("vectorVec3", vec3_class_methods);
-        */
-        map
-    };
+// Render targets for the overworld, created when it's loaded
+struct Effects {
+    post_process: PostProcess,
+    lighting: Lighting,
+    torch: LightId,
 }
 
+// The test map with a player walking around it
+struct Overworld {
+    panel_id: ResourceID<SpriteData>,
+    font_id: ResourceID<Font>,
+    canvas: Box<Canvas>,
+    effects: Option<Effects>,
+    camera: Camera2D,
+    // Player position after the last and the previous fixed update
+    player: Vector2<f32>,
+    prev_player: Vector2<f32>,
+    // The player's sprite and what it holds in its hand
+    world: World,
+    body: Entity,
+    held_item: Entity,
+    take_screenshot: bool,
+}
 
-fn main() {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-
-    let gl_attr = video_subsystem.gl_attr();
-    gl_attr.set_context_profile(GLProfile::Core);
-    gl_attr.set_context_version(3, 3);
-
-    let window = video_subsystem.window("SDL2 works!", 800, 600)
-        .position_centered()
-        .opengl()
-        .build()
-        .unwrap();
-
-    let ctx = window
-        .gl_create_context()
-        .unwrap();
-
-    window.gl_make_current(&ctx);
-
-    gl::load_with(|name| video_subsystem.gl_get_proc_address(name) as *const _);
-
-    debug_assert_eq!(gl_attr.context_profile(), GLProfile::Core);
-    debug_assert_eq!(gl_attr.context_version(), (3, 3));
-
-    /*
-    let mut game_data = GameData::create_new();
-
-    // Load shaders
-    let mut shader = Shader::new("sprite.vert".to_string(), "sprite.frag".to_string());
-    shader.compile();
-    let shader_id = game_data.shaders.insert("sprite.shader", shader);
-
-    // Load textures
-    let test_tex = TextureBuilder::new()
-        .load_file("awesomeface.png")
-        .image_format(gl::RGBA)
-        .internal_format(gl::RGBA)
-        .build();
-    let test_tex_ref = game_data.textures.insert("awesomeface.texture", test_tex);
-
-    let spritesheet_tex = TextureBuilder::new()
-        .load_file("kenneyrpgpack/Spritesheet/RPGpack_sheet.png")
-        .image_format(gl::RGBA)
-        .internal_format(gl::RGBA)
-        .build();
-    let spritesheet_tex_ref = game_data.textures.insert("rpgpack.texture", spritesheet_tex);
-
-    // Load sprites
-    for i in 0..9 {
-        let name = format!("grass_with_dirt_{}", i+1);
-        game_data.sprites.insert(&name, SpriteData {
-            name: name.clone(),
-            texture: spritesheet_tex_ref,
-            rect: SpriteBounds::new((i % 3) * 64, (i / 3) * 64, 64, 64, 0, 0)
-        });
+impl Overworld {
+    fn new(data: &GameData, render_size: (u32, u32)) -> Self {
+        let (render_w, render_h) = render_size;
+        let (_, shader_id) = data.shaders.get_by_name("sprite.shader").unwrap();
+        let (_, sprite_id) = data.sprites.get_by_name("smiley_face.sprite").unwrap();
+        let (_, panel_id) = data.sprites.get_by_name("panel.sprite").unwrap();
+        let (_, font_id) = data.fonts.get_by_name("dejavu_sans").unwrap();
+
+        let canvas = Box::new(Canvas::from_file(&data.sprites, &data.textures, shader_id, "map_lighting.json"));
+
+        let player = Vector2::new(100.0, 100.0);
+        let mut camera = Camera2D::new(render_w as f32, render_h as f32);
+        camera.follow_lerp = 0.1;
+        camera.deadzone = Some(Vector2::new(100.0, 75.0));
+        camera.bounds = Some((Vector2::new(0.0, 0.0),
+                              Vector2::new(canvas::MAX_WIDTH as f32 * canvas::SCALE,
+                                           canvas::MAX_HEIGHT as f32 * canvas::SCALE)));
+        // Start out looking at the player
+        camera.position = player;
+        camera.update(0.0);
+
+        let mut world = World::new();
+        let body = world.spawn_named("player", (
+            Transform2D::new(player),
+            Sprite::new(sprite_id).params(SpriteParams::new(Vector2::new(0.0, 0.0))
+                .scale(Vector2::new(0.25, 0.25))
+                .color(Vector4::new(0.0, 1.0, 0.0, 1.0))
+                .layer(1)
+                .y_sort(true)),
+        ));
+        // Follows the body around, swinging about the hand
+        let held_item = world.spawn_named("held_item", (
+            Transform2D::new(Vector2::new(96.0, 40.0)),
+            Parent(body),
+            Sprite::new(sprite_id).params(SpriteParams::new(Vector2::new(0.0, -8.0))
+                .scale(Vector2::new(0.08, 0.04))
+                .color(Vector4::new(1.0, 0.9, 0.2, 1.0))
+                .layer(1)),
+        ));
+
+        Overworld {
+            panel_id,
+            font_id,
+            canvas,
+            effects: None,
+            camera,
+            player,
+            prev_player: player,
+            world,
+            body,
+            held_item,
+            take_screenshot: false,
+        }
     }
+}
 
-    let sprite_id = game_data.sprites.insert("smiley_face.sprite", SpriteData {
-        name: "smiley_face".to_string(),
-        texture: test_tex_ref,
-        rect: SpriteBounds::new(64, 64, 384, 384, 0, 0)
-    });
-
-    game_data.save();
-    */
-
-    let mut game_data = GameData::from_file();
-
-    let (shader, shader_id) = game_data.shaders.get_by_name("sprite.shader").unwrap();
-    let projection_mat = cgmath::ortho(0.0, 800.0, 600.0, 0.0, -1.0, 1.0);
-    shader.use_shader();
-    shader.set_int("image", 0);
-    shader.set_mat4("projection", projection_mat);
-
-    let (_, test_tex_ref) = game_data.textures.get_by_name("awesomeface.texture").unwrap();
-    let (_, spritesheet_tex_ref) = game_data.textures.get_by_name("rpgpack.texture").unwrap();
-    let (_, sprite_id) = game_data.sprites.get_by_name("smiley_face.sprite").unwrap();
-
-    // Load Wren VM
-    fn bind_method(_: &mut wren::VM,
-                   module: &str,
-                   class_name: &str,
-                   is_static: bool,
-                   signature: &str) -> wren::ForeignMethodFn {
-        let full_signature = format!("{}{}{}{}",
-                                     module,
-                                     class_name,
-                                     signature,
-                                     if is_static { "s" } else { "" });
-        *FOREIGN_METHODS.get::<str>(&full_signature).unwrap_or(&None)
+impl Scene for Overworld {
+    fn load(&mut self, window: &Window, data: &mut GameData, backend: &mut dyn RenderBackend) {
+        let (render_w, render_h) = window.render_size();
+        let post_process = PostProcess::new(backend, &mut data.textures, render_w, render_h,
+                                            PostProcessConfig::from_file("scenes/overworld.postfx.toml"));
+        let mut lighting = Lighting::new(backend, &mut data.textures, render_w, render_h);
+
+        // Dusk, with a torch carried by the player and a lamp by the walls
+        lighting.ambient = Vector3::new(0.35, 0.35, 0.5);
+        lighting.set_occluders(self.canvas.shadow_edges());
+        let torch = lighting.add_light(Light::point(self.player, 300.0)
+            .color(Vector3::new(1.0, 0.8, 0.5))
+            .intensity(1.2)
+            .falloff(1.5));
+        lighting.add_light(Light::cone(Vector2::new(640.0, 200.0), 450.0, 90.0, 35.0)
+            .color(Vector3::new(0.6, 0.8, 1.0)));
+
+        self.effects = Some(Effects { post_process, lighting, torch });
     }
 
-    fn bind_class(_: &mut wren::VM,
-                  module: &str,
-                  class_name: &str) -> wren::ForeignClassMethods {
-        let full_signature = format!("{}{}", module, class_name);
-        let methods = FOREIGN_CLASSES.get::<str>(&full_signature);
-        if let Some(methods) = methods {
-            return *methods;
+    fn unload(&mut self, data: &mut GameData, backend: &mut dyn RenderBackend) {
+        if let Some(Effects { post_process, lighting, .. }) = self.effects.take() {
+            post_process.release(backend, &mut data.textures);
+            lighting.release(backend, &mut data.textures);
         }
-        panic!("Failed to bind foreign class");
     }
 
-    fn load_module(_: &mut wren::VM, name: &str) -> Option<String> {
-        use std::path::Path;
-        use std::fs::File;
-        use std::io::Read;
-
-        let mut path = Path::new("scripts").join(&name);
-        path.set_extension("wren");
-        let mut buffer = String::new();
-        if File::open(path)
-            .map(|mut f| f.read_to_string(&mut buffer))
-            .is_ok() {
-            Some(buffer)
-        } else {
-            None
+    fn event(&mut self, ctx: &mut Context, event: &Event) -> SceneChange {
+        let effects = self.effects.as_mut().unwrap();
+        match *event {
+            Event::KeyDown { keycode: Some(Keycode::Escape), repeat: false, .. } => {
+                return SceneChange::Push(Box::new(Pause::new(ctx.data)), Transition::cut());
+            },
+            Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                self.take_screenshot = true;
+            },
+            Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => {
+                debug_draw::with(|d| d.toggle());
+            },
+            Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => {
+                effects.post_process.config_mut().toggle("crt");
+            },
+            Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } => {
+                effects.post_process.config_mut().toggle("bloom");
+            },
+            Event::KeyDown { keycode: Some(Keycode::F4), repeat: false, .. } => {
+                effects.lighting.enabled = !effects.lighting.enabled;
+            },
+            Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                ctx.time.time_scale = if ctx.time.time_scale == 1.0 { 0.25 } else { 1.0 };
+            },
+            Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                ctx.time.time_scale = if ctx.time.paused() { 1.0 } else { 0.0 };
+            },
+            Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                ctx.window.toggle_fullscreen();
+            },
+            _ => {}
         }
+        SceneChange::None
     }
 
-    let mut wren_cfg = wren::Configuration::new();
-    wren_cfg.set_bind_foreign_method_fn(wren_bind_foreign_method_fn!(bind_method));
-    wren_cfg.set_bind_foreign_class_fn(wren_bind_foreign_class_fn!(bind_class));
-    wren_cfg.set_load_module_fn(wren_load_module_fn!(load_module));
-    let mut vm = wren::VM::new(wren_cfg);
-    // vm.interpret(source);
-
-    let sprite_renderer = SpriteRenderer::new(&game_data.shaders, &game_data.textures, &game_data.sprites);
-    let canvas = Canvas::from_file(&game_data.sprites, &game_data.textures, &game_data.shaders, shader_id, "map_test.json");
-
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut input_mgr = InputManager::new();
-    let (mut x, mut y) = (100.0f32, 100.0f32);
+    fn fixed_update(&mut self, ctx: &mut Context, dt: f32) -> SceneChange {
+        self.prev_player = self.player;
+        let speed = 600.0 * dt;
+        if ctx.input.is_key_pressed(Key::Left) {
+            self.player.x -= speed;
+        }
+        if ctx.input.is_key_pressed(Key::Right) {
+            self.player.x += speed;
+        }
+        if ctx.input.is_key_pressed(Key::Up) {
+            self.player.y -= speed;
+        }
+        if ctx.input.is_key_pressed(Key::Down) {
+            self.player.y += speed;
+        }
+        SceneChange::None
+    }
 
-    'running: loop {
+    fn update(&mut self, ctx: &mut Context, dt: f32) -> SceneChange {
+        let swing = 40.0 * (6.0 * ctx.time.elapsed() as f32).sin();
+        self.world.get_mut::<Transform2D>(self.held_item).unwrap().set_rotation(swing);
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    break 'running
-                },
-                _ => {}
+        // Fade in from black
+        let effects = self.effects.as_mut().unwrap();
+        if let Some(pass) = effects.post_process.config_mut().get_mut("fade") {
+            if let Effect::Fade(ref mut fade) = pass.effect {
+                fade.amount = (fade.amount - dt).max(0.0);
             }
         }
+        SceneChange::None
+    }
 
-        // update
-        input_mgr.update(&event_pump);
-
-        if input_mgr.is_key_pressed(Key::Left) {
-            x -= 10.0;
-        }
-        if input_mgr.is_key_pressed(Key::Right) {
-            x += 10.0;
+    fn render(&mut self, ctx: &mut Context, renderer: &mut SpriteRenderer, alpha: f32) {
+        let Overworld { panel_id, ref canvas, ref mut camera, ref mut effects, ref mut world, body, .. } = *self;
+        let Effects { ref mut post_process, ref mut lighting, torch } = *effects.as_mut().unwrap();
+        let font = ctx.data.fonts.get(self.font_id);
+        let pos = self.prev_player + (self.player - self.prev_player) * alpha;
+        camera.follow(pos);
+        camera.update(ctx.time.delta());
+
+        post_process.set_output(ctx.window.present_rect());
+        post_process.begin(renderer.backend_mut());
+        renderer.backend_mut().clear(Vector4::new(0.5, 0.5, 0.0, 1.0));
+
+        renderer.set_camera(camera);
+        canvas.submit(renderer, camera);
+
+        world.get_mut::<Transform2D>(body).unwrap().set_position(pos);
+        renderer.submit_world(world);
+        renderer.flush();
+
+        // Light the world, but not the UI drawn after it
+        lighting.light_mut(torch).pos = pos;
+        lighting.render_normals(renderer, |r| {
+            canvas.submit(r, camera);
+            r.submit_world(world);
+        });
+        lighting.render(renderer.backend_mut(), &ctx.data.textures, camera);
+        lighting.composite(renderer.backend_mut(), &ctx.data.textures, Some(post_process.scene_target()));
+
+        {
+            // Health bar above the player
+            let mut shapes = ShapeRenderer::new(renderer);
+            let (min, max) = (pos + Vector2::new(-20.0, -30.0), pos + Vector2::new(20.0, -24.0));
+            shapes.fill_rounded_rect(min, max, 3.0, &ShapeParams::new(Vector4::new(0.2, 0.0, 0.0, 0.8)).layer(2));
+            shapes.fill_rounded_rect(min, Vector2::new(min.x + 30.0, max.y), 3.0,
+                                     &ShapeParams::new(Vector4::new(0.9, 0.1, 0.1, 1.0)).layer(2));
+            shapes.stroke_rounded_rect(min, max, 3.0, &ShapeParams::new(Vector4::new(1.0, 1.0, 1.0, 1.0)).layer(2));
         }
-        if input_mgr.is_key_pressed(Key::Up) {
-            y -= 10.0;
+        let name = TextParams::new(pos - Vector2::new(0.0, 50.0))
+            .align(TextAlign::Center)
+            .scale(0.75)
+            .layer(2);
+        renderer.submit_text(font, "Player", &name);
+
+        // Help panel along the bottom of the screen
+        let (view_min, view_max) = camera.visible_bounds();
+        let panel_pos = Vector2::new(view_min.x + 16.0, view_max.y - 72.0);
+        let panel_size = Vector2::new(view_max.x - view_min.x - 32.0, 56.0);
+        renderer.submit_nine_slice(panel_id, &NineSliceParams::new(panel_pos, panel_size).layer(3));
+        renderer.submit_text(font, "Esc pause  F1 debug  F2 CRT  F3 bloom  F4 lighting\nF5 slow motion  F6 pause  F11 fullscreen  F12 screenshot",
+                             &TextParams::new(panel_pos + panel_size * 0.5 - Vector2::new(0.0, 20.0))
+                                 .align(TextAlign::Center)
+                                 .scale(0.75)
+                                 .layer(4));
+
+        let stats = renderer.stats();
+        let time = &ctx.time;
+        debug_draw::with(|d| {
+            if let Some(half) = camera.deadzone {
+                d.rect(camera.position - half, camera.position + half, debug_draw::YELLOW);
+            }
+            d.circle(pos, 8.0, debug_draw::RED);
+            let (min, _) = camera.visible_bounds();
+            d.text(min + Vector2::new(8.0, 8.0) / camera.zoom,
+                   &format!("draw calls: {}\nsprites: {}", stats.draw_calls, stats.sprites),
+                   debug_draw::WHITE);
+            time.draw_histogram(d, min + Vector2::new(8.0, 32.0) / camera.zoom, Vector2::new(120.0, 40.0) / camera.zoom);
+            d.render(renderer, camera, time.unscaled_delta());
+        });
+        // Everything has to be in the scene target before the passes run
+        renderer.flush();
+        post_process.end(renderer.backend_mut(), &ctx.data.textures);
+
+        if self.take_screenshot {
+            self.take_screenshot = false;
+            let (window_w, window_h) = ctx.window.drawable_size();
+            let frame = capture::capture_screen(renderer.backend_mut(), window_w, window_h);
+            capture::save_png(&frame, "screenshot.png").expect("Couldn't save screenshot");
         }
-        if input_mgr.is_key_pressed(Key::Down) {
-            y += 10.0;
+    }
+
+    fn resize(&mut self, window: &Window, data: &mut GameData, backend: &mut dyn RenderBackend, old_size: (u32, u32)) {
+        let (width, height) = window.render_size();
+        if let Some(ref mut effects) = self.effects {
+            effects.post_process.resize(backend, &mut data.textures, width, height);
+            effects.lighting.resize(backend, &mut data.textures, width, height);
         }
+        self.camera.resize(old_size, (width, height));
+    }
+}
 
-        // render
-        unsafe {
-            gl::ClearColor(0.5, 0.5, 0.0, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+// Drawn over the paused overworld
+struct Pause {
+    font_id: ResourceID<Font>,
+}
+
+impl Pause {
+    fn new(data: &GameData) -> Self {
+        let (_, font_id) = data.fonts.get_by_name("dejavu_sans").unwrap();
+        Pause { font_id }
+    }
+}
+
+impl Scene for Pause {
+    fn event(&mut self, _ctx: &mut Context, event: &Event) -> SceneChange {
+        match *event {
+            Event::KeyDown { keycode: Some(Keycode::Escape), repeat: false, .. } => SceneChange::Pop(Transition::cut()),
+            Event::KeyDown { keycode: Some(Keycode::Q), repeat: false, .. } => SceneChange::Quit,
+            _ => SceneChange::None,
         }
+    }
 
-        canvas.draw();
+    fn render(&mut self, ctx: &mut Context, renderer: &mut SpriteRenderer, _alpha: f32) {
+        let (render_w, render_h) = ctx.window.render_size();
+        let size = Vector2::new(render_w as f32, render_h as f32);
+        renderer.set_camera(&Camera2D::new(size.x, size.y));
+        ShapeRenderer::new(renderer).fill_rect(Vector2::new(0.0, 0.0), size,
+                                               &ShapeParams::new(Vector4::new(0.0, 0.0, 0.0, 0.6)));
+        let font = ctx.data.fonts.get(self.font_id);
+        renderer.submit_text(font, "Paused\nEsc to resume, Q to quit",
+                             &TextParams::new(size * 0.5 - Vector2::new(0.0, 20.0))
+                                 .align(TextAlign::Center)
+                                 .layer(1));
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+}
+
+fn main() {
+    let mut app = App::new(WindowConfig::from_file("window.toml"));
 
-        sprite_renderer.draw_sprite(
-            sprite_id,
-            Vector2::new(x, y),
-            Vector2::new(0.25, 0.25),
-            0.0,
-            Vector3::new(0.0, 1.0, 0.0)
-        );
+    let mut scenes = SceneManager::new(Box::new(Title::new(app.data())));
+    app.run(&mut scenes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::keyboard;
+
+    fn key_down(keycode: Keycode) -> Event {
+        Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None,
+                         keymod: keyboard::NOMOD, repeat: false }
+    }
 
-        window.gl_swap_window();
+    // The real scenes, without a window: any GL call outside the backend
+    // would fail here
+    fn run_scenes() {
+        let mut app = App::headless(WindowConfig::default());
+        let mut scenes = SceneManager::new(Box::new(Title::new(app.data())));
+        assert!(app.run_frames(&mut scenes, 10));
+        assert!(app.data().textures.get_by_name("post_process.0").is_none());
+
+        // The overworld is loaded halfway through the one second fade
+        app.push_event(key_down(Keycode::Return));
+        assert!(app.run_frames(&mut scenes, 40));
+        assert!(app.data().textures.get_by_name("post_process.0").is_some());
+        app.input_mut().set_key(Key::Right, true);
+        assert!(app.run_frames(&mut scenes, 60));
+        assert!(!scenes.is_transitioning());
+        assert_eq!(scenes.len(), 1);
+
+        // Pause, then quit from the pause menu
+        app.push_event(key_down(Keycode::Escape));
+        assert!(app.run_frames(&mut scenes, 2));
+        assert_eq!(scenes.len(), 2);
+        app.push_event(key_down(Keycode::Q));
+        assert!(!app.run_frames(&mut scenes, 2));
+
+        // Unloading the overworld released its render targets
+        let textures = &app.data().textures;
+        for name in &["post_process.0", "post_process.1", "post_process.bloom.0", "post_process.bloom.1",
+                      "lighting.normals", "lighting.lights"] {
+            assert!(textures.get_by_name(name).is_none(), "{} is still loaded", name);
+        }
+    }
 
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    #[test]
+    fn test_headless_scenes() {
+        // Canvas keeps its tile arrays inline, more than a test thread's default stack
+        ::std::thread::Builder::new()
+            .stack_size(32 * 1024 * 1024)
+            .spawn(run_scenes)
+            .unwrap()
+            .join()
+            .unwrap();
     }
 }
@@ -0,0 +1,252 @@
+use cgmath::{Vector2, Vector4};
+
+use storage::ResourceID;
+use shader::Shader;
+use sprite::{SpriteData, NineSlice};
+use render_backend::{SpriteVertex, BlendMode};
+use sprite_renderer::{SpriteRenderer, DrawState};
+
+// How the edges and the centre fill the space between the corners
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SliceMode {
+    Stretch,
+    // Repeat at the source size, cutting off the last repetition
+    Tile,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct NineSliceParams {
+    // Top left corner and size of the whole panel
+    pub pos: Vector2<f32>,
+    pub size: Vector2<f32>,
+    pub mode: SliceMode,
+    pub color: Vector4<f32>,
+    pub shader: Option<ResourceID<Shader>>,
+    pub blend: BlendMode,
+    pub layer: i32,
+}
+
+impl NineSliceParams {
+    pub fn new(pos: Vector2<f32>, size: Vector2<f32>) -> Self {
+        NineSliceParams {
+            pos,
+            size,
+            mode: SliceMode::Stretch,
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            shader: None,
+            blend: BlendMode::Alpha,
+            layer: 0,
+        }
+    }
+
+    pub fn mode(mut self, mode: SliceMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn color(mut self, color: Vector4<f32>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn shader(mut self, shader: ResourceID<Shader>) -> Self {
+        self.shader = Some(shader);
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+// A destination rectangle and the source rectangle (in texture pixels)
+// drawn into it, both as [x0, y0, x1, y1]
+pub type SlicePiece = ([f32; 4], [f32; 4]);
+
+// Split a span into pieces of `tile` length, the last one cut short.
+// Returns (offset, length) pairs.
+fn tile_span(length: f32, tile: f32) -> Vec<(f32, f32)> {
+    if tile <= 0.0 || length <= 0.0 {
+        return Vec::new();
+    }
+    let mut pieces = Vec::new();
+    let mut offset = 0.0;
+    while length - offset > 1e-3 {
+        pieces.push((offset, tile.min(length - offset)));
+        offset += tile;
+    }
+    pieces
+}
+
+// Border sizes along one axis, shrunk proportionally when the panel is
+// smaller than both borders together
+fn fit_borders(size: f32, start: f32, end: f32) -> (f32, f32) {
+    if start + end > size && start + end > 0.0 {
+        let scale = size / (start + end);
+        (start * scale, end * scale)
+    } else {
+        (start, end)
+    }
+}
+
+// The quads a nine-slice sprite with source rect `src` ([x, y, w, h] in
+// texture pixels) is made of when drawn at `pos` with `size`
+pub fn nine_slice_pieces(src: [f32; 4], insets: NineSlice, pos: Vector2<f32>, size: Vector2<f32>,
+                         mode: SliceMode) -> Vec<SlicePiece> {
+    let (src_x, src_y, src_w, src_h) = (src[0], src[1], src[2], src[3]);
+    let (left, right) = fit_borders(size.x, insets.left as f32, insets.right as f32);
+    let (top, bottom) = fit_borders(size.y, insets.top as f32, insets.bottom as f32);
+
+    // Column and row boundaries, on screen and in the texture
+    let dst_xs = [pos.x, pos.x + left, pos.x + size.x - right, pos.x + size.x];
+    let dst_ys = [pos.y, pos.y + top, pos.y + size.y - bottom, pos.y + size.y];
+    let src_xs = [src_x, src_x + insets.left as f32, src_x + src_w - insets.right as f32, src_x + src_w];
+    let src_ys = [src_y, src_y + insets.top as f32, src_y + src_h - insets.bottom as f32, src_y + src_h];
+
+    let mut pieces = Vec::new();
+    for row in 0..3 {
+        for col in 0..3 {
+            let dst = [dst_xs[col], dst_ys[row], dst_xs[col + 1], dst_ys[row + 1]];
+            let src = [src_xs[col], src_ys[row], src_xs[col + 1], src_ys[row + 1]];
+            if dst[2] - dst[0] <= 0.0 || dst[3] - dst[1] <= 0.0 || src[2] - src[0] <= 0.0 || src[3] - src[1] <= 0.0 {
+                continue;
+            }
+            // Corners always keep their size, edges tile along their length
+            let tile_x = mode == SliceMode::Tile && col == 1;
+            let tile_y = mode == SliceMode::Tile && row == 1;
+            let xs = if tile_x { tile_span(dst[2] - dst[0], src[2] - src[0]) } else { vec![(0.0, dst[2] - dst[0])] };
+            let ys = if tile_y { tile_span(dst[3] - dst[1], src[3] - src[1]) } else { vec![(0.0, dst[3] - dst[1])] };
+
+            for &(oy, h) in &ys {
+                for &(ox, w) in &xs {
+                    let src_x1 = if tile_x { src[0] + w } else { src[2] };
+                    let src_y1 = if tile_y { src[1] + h } else { src[3] };
+                    pieces.push(([dst[0] + ox, dst[1] + oy, dst[0] + ox + w, dst[1] + oy + h],
+                                 [src[0], src[1], src_x1, src_y1]));
+                }
+            }
+        }
+    }
+    pieces
+}
+
+impl<'a> SpriteRenderer<'a> {
+    fn nine_slice_vertices(&self, sprite_id: ResourceID<SpriteData>, params: &NineSliceParams) -> Vec<SpriteVertex> {
+        let sprite = self.sprites().get(sprite_id);
+        let texture = self.textures().get(sprite.texture);
+        let (tex_w, tex_h) = (texture.width as f32, texture.height as f32);
+        let (pos, size) = (sprite.rect.position(), sprite.rect.size());
+        let insets = sprite.nine_slice.unwrap_or_else(|| NineSlice::uniform(0));
+
+        let color = [params.color.x, params.color.y, params.color.z, params.color.w];
+        let mut vertices = Vec::new();
+        for (dst, src) in nine_slice_pieces([pos.x, pos.y, size.x, size.y], insets, params.pos, params.size, params.mode) {
+            let corners = [(dst[0], dst[1], src[0], src[1]), (dst[2], dst[1], src[2], src[1]),
+                           (dst[2], dst[3], src[2], src[3]), (dst[0], dst[3], src[0], src[3])];
+            for &i in &[0, 1, 2, 0, 2, 3] {
+                let (x, y, u, v) = corners[i];
+                vertices.push(SpriteVertex { pos: [x, y], uv: [u / tex_w, v / tex_h], color });
+            }
+        }
+        vertices
+    }
+
+    // Queue a sprite scaled to `params.size` keeping its nine-slice borders
+    // intact. Sprites without insets are simply stretched.
+    pub fn submit_nine_slice(&mut self, sprite_id: ResourceID<SpriteData>, params: &NineSliceParams) {
+        let vertices = self.nine_slice_vertices(sprite_id, params);
+        let state = DrawState {
+            shader: params.shader.unwrap_or(self.sprite_shader()),
+            blend: params.blend,
+            texture: self.sprites().get(sprite_id).texture,
+            layer: params.layer,
+            depth: ::std::f32::NEG_INFINITY,
+        };
+        self.submit_triangles(state, &vertices);
+    }
+
+    // Draws immediately with the sprite shader, prefer submit_nine_slice
+    pub fn draw_nine_slice(&mut self, sprite_id: ResourceID<SpriteData>, params: &NineSliceParams) {
+        let vertices = self.nine_slice_vertices(sprite_id, params);
+        let texture = self.textures().get(self.sprites().get(sprite_id).texture);
+        self.draw_triangles(Some(texture), params.blend, &vertices);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nine_slice::*;
+    use sprite_renderer::{test_renderer, test_shaders};
+    use gl;
+    use stb_image::image::Image;
+    use storage::Storage;
+    use texture::TextureBuilder;
+    use sprite::SpriteBounds;
+    use render_backend::SoftwareBackend;
+
+    #[test]
+    fn test_stretch_pieces() {
+        let insets = NineSlice::new(2, 3, 4, 5);
+        let pieces = nine_slice_pieces([10.0, 20.0, 16.0, 16.0], insets, Vector2::new(0.0, 0.0),
+                                       Vector2::new(100.0, 50.0), SliceMode::Stretch);
+        assert_eq!(pieces.len(), 9);
+        // Top left corner keeps its size
+        assert_eq!(pieces[0], ([0.0, 0.0, 2.0, 3.0], [10.0, 20.0, 12.0, 23.0]));
+        // The centre takes what's left
+        assert_eq!(pieces[4], ([2.0, 3.0, 96.0, 45.0], [12.0, 23.0, 22.0, 31.0]));
+        assert_eq!(pieces[8], ([96.0, 45.0, 100.0, 50.0], [22.0, 31.0, 26.0, 36.0]));
+
+        // Too small for the borders, which shrink to fit
+        let pieces = nine_slice_pieces([0.0, 0.0, 16.0, 16.0], NineSlice::uniform(4), Vector2::new(0.0, 0.0),
+                                       Vector2::new(4.0, 16.0), SliceMode::Stretch);
+        assert_eq!(pieces.len(), 6);
+        assert_eq!(pieces[0].0, [0.0, 0.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn test_tile_pieces() {
+        // Centre and edges are 8 pixels, repeated over 20
+        let pieces = nine_slice_pieces([0.0, 0.0, 16.0, 16.0], NineSlice::uniform(4), Vector2::new(0.0, 0.0),
+                                       Vector2::new(28.0, 16.0), SliceMode::Tile);
+        let top: Vec<&SlicePiece> = pieces.iter().filter(|p| p.0[1] == 0.0 && p.0[0] >= 4.0 && p.0[2] <= 24.0).collect();
+        assert_eq!(top.len(), 3);
+        assert_eq!(*top[2], ([20.0, 0.0, 24.0, 4.0], [4.0, 0.0, 8.0, 4.0]));
+        // 4 corners, 3 + 3 pieces along the top and bottom, 1 + 1 on the sides, 3 in the middle
+        assert_eq!(pieces.len(), 4 + 6 + 2 + 3);
+    }
+
+    #[test]
+    fn test_draw_nine_slice() {
+        // 3x3 texture with a distinct colour per cell, one pixel insets
+        let mut data = Vec::new();
+        for i in 0..9u8 {
+            data.extend_from_slice(&[i * 20, 255 - i * 20, 0, 255]);
+        }
+        let texture = TextureBuilder::new()
+            .image(Image { width: 3, height: 3, depth: 4, data })
+            .image_format(gl::RGBA)
+            .build_with(&mut SoftwareBackend::new(1, 1));
+        let mut textures = Storage::new(1);
+        let texture = textures.insert("panel", texture);
+        let mut sprites = Storage::new(1);
+        let panel = sprites.insert("panel", SpriteData::new("panel".to_string(), texture, SpriteBounds::new(0, 0, 3, 3, 0, 0))
+            .nine_slice(NineSlice::uniform(1)));
+
+        let shaders = test_shaders();
+
+        let mut renderer = test_renderer(8, 6, &shaders, &textures, &sprites);
+        renderer.submit_nine_slice(panel, &NineSliceParams::new(Vector2::new(0.0, 0.0), Vector2::new(8.0, 6.0)));
+        renderer.end_frame();
+
+        let image = renderer.backend_mut().read_pixels(0, 0, 8, 6);
+        let cell = |x: usize, y: usize| image.data[4 * (y * 8 + x)] / 20;
+        assert_eq!((cell(0, 0), cell(7, 0), cell(0, 5), cell(7, 5)), (0, 2, 6, 8));
+        assert_eq!((cell(3, 0), cell(0, 3), cell(4, 2), cell(7, 3), cell(5, 5)), (1, 3, 4, 5, 7));
+    }
+}
@@ -0,0 +1,1044 @@
+use std::mem;
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use gl;
+use gl::types::*;
+use cgmath::{Matrix4, Vector2, Vector3, Vector4, One};
+use stb_image::image::Image;
+
+use shader::{Shader, ShaderError, UniformValue};
+use texture::Texture;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpriteVertex {
+    pub pos: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BlendMode {
+    // Straight (non-premultiplied) alpha, what PNGs usually contain
+    Alpha,
+    Premultiplied,
+    Additive,
+    Multiply,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Alpha
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferKind {
+    Vertex,
+    Index,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferId(u32);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TargetId(u32);
+
+// Everything the renderers need from the graphics API. Vertices are always
+// SpriteVertex and indices u32, drawn as triangle lists.
+pub trait RenderBackend {
+    fn create_buffer(&mut self, kind: BufferKind) -> BufferId;
+    fn upload_vertices(&mut self, buffer: BufferId, vertices: &[SpriteVertex]);
+    fn upload_indices(&mut self, buffer: BufferId, indices: &[u32]);
+    fn delete_buffer(&mut self, buffer: BufferId);
+
+    // Create the GPU side of a texture from its CPU pixel data
+    fn upload_texture(&mut self, texture: &mut Texture);
+    fn delete_texture(&mut self, texture: &mut Texture);
+    // Texture for another sampler of the bound shader. What's drawn with
+    // always goes to unit 0.
+    fn bind_texture(&mut self, unit: u32, texture: &Texture);
+
+    fn compile_shader(&mut self, shader: &mut Shader) -> Result<(), ShaderError>;
+    // Use the shader for following draws, setting the common sprite uniforms
+    fn bind_shader(&mut self, shader: &Shader, view_projection: Matrix4<f32>);
+    // Set a uniform of the shader last passed to bind_shader
+    fn set_uniform(&mut self, shader: &Shader, name: &str, value: UniformValue);
+
+    // Offscreen framebuffer drawing into `texture`, which has to have been
+    // uploaded by this backend
+    fn create_target(&mut self, texture: &Texture, depth_stencil: bool) -> TargetId;
+    // Reallocate the target's texture and depth buffer, discarding what they held
+    fn resize_target(&mut self, target: TargetId, texture: &mut Texture, width: u32, height: u32);
+    fn delete_target(&mut self, target: TargetId);
+    // Draw into the target from now on, or into the window with None
+    fn bind_target(&mut self, target: Option<TargetId>);
+    // Copy a target (with its texture) into a rectangle of the window,
+    // scaling with gl::NEAREST or gl::LINEAR filtering. Leaves the window bound.
+    fn blit_to_screen(&mut self, target: TargetId, texture: &Texture, rect: (i32, i32, u32, u32), filter: GLuint);
+
+    // GL conventions: pixels, origin at the bottom left
+    fn set_viewport(&mut self, x: i32, y: i32, width: u32, height: u32);
+    fn clear(&mut self, color: Vector4<f32>);
+    // Every three vertices from `first_vertex` on make a triangle
+    fn draw(&mut self, vertices: BufferId, first_vertex: usize, vertex_count: usize,
+            texture: &Texture, blend: BlendMode);
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId,
+                    first_index: usize, index_count: usize,
+                    texture: &Texture, blend: BlendMode);
+
+    // Read back a rectangle of the current framebuffer as RGBA, top row first
+    fn read_pixels(&mut self, x: i32, y: i32, width: u32, height: u32) -> Image<u8>;
+}
+
+// So a renderer can borrow a backend owned by someone else, e.g. the App's
+impl<'b, B: RenderBackend + ?Sized> RenderBackend for &'b mut B {
+    fn create_buffer(&mut self, kind: BufferKind) -> BufferId {
+        (**self).create_buffer(kind)
+    }
+
+    fn upload_vertices(&mut self, buffer: BufferId, vertices: &[SpriteVertex]) {
+        (**self).upload_vertices(buffer, vertices)
+    }
+
+    fn upload_indices(&mut self, buffer: BufferId, indices: &[u32]) {
+        (**self).upload_indices(buffer, indices)
+    }
+
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        (**self).delete_buffer(buffer)
+    }
+
+    fn upload_texture(&mut self, texture: &mut Texture) {
+        (**self).upload_texture(texture)
+    }
+
+    fn delete_texture(&mut self, texture: &mut Texture) {
+        (**self).delete_texture(texture)
+    }
+
+    fn bind_texture(&mut self, unit: u32, texture: &Texture) {
+        (**self).bind_texture(unit, texture)
+    }
+
+    fn compile_shader(&mut self, shader: &mut Shader) -> Result<(), ShaderError> {
+        (**self).compile_shader(shader)
+    }
+
+    fn bind_shader(&mut self, shader: &Shader, view_projection: Matrix4<f32>) {
+        (**self).bind_shader(shader, view_projection)
+    }
+
+    fn set_uniform(&mut self, shader: &Shader, name: &str, value: UniformValue) {
+        (**self).set_uniform(shader, name, value)
+    }
+
+    fn create_target(&mut self, texture: &Texture, depth_stencil: bool) -> TargetId {
+        (**self).create_target(texture, depth_stencil)
+    }
+
+    fn resize_target(&mut self, target: TargetId, texture: &mut Texture, width: u32, height: u32) {
+        (**self).resize_target(target, texture, width, height)
+    }
+
+    fn delete_target(&mut self, target: TargetId) {
+        (**self).delete_target(target)
+    }
+
+    fn bind_target(&mut self, target: Option<TargetId>) {
+        (**self).bind_target(target)
+    }
+
+    fn blit_to_screen(&mut self, target: TargetId, texture: &Texture, rect: (i32, i32, u32, u32), filter: GLuint) {
+        (**self).blit_to_screen(target, texture, rect, filter)
+    }
+
+    fn set_viewport(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        (**self).set_viewport(x, y, width, height)
+    }
+
+    fn clear(&mut self, color: Vector4<f32>) {
+        (**self).clear(color)
+    }
+
+    fn draw(&mut self, vertices: BufferId, first_vertex: usize, vertex_count: usize,
+            texture: &Texture, blend: BlendMode) {
+        (**self).draw(vertices, first_vertex, vertex_count, texture, blend)
+    }
+
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId,
+                    first_index: usize, index_count: usize,
+                    texture: &Texture, blend: BlendMode) {
+        (**self).draw_indexed(vertices, indices, first_index, index_count, texture, blend)
+    }
+
+    fn read_pixels(&mut self, x: i32, y: i32, width: u32, height: u32) -> Image<u8> {
+        (**self).read_pixels(x, y, width, height)
+    }
+}
+
+pub struct GlBackend {
+    // A VAO with the SpriteVertex layout for every vertex buffer
+    vaos: HashMap<BufferId, GLuint>,
+    // Depth/stencil renderbuffer of every framebuffer that has one
+    renderbuffers: HashMap<TargetId, GLuint>,
+}
+
+impl GlBackend {
+    pub fn new() -> Self {
+        GlBackend { vaos: HashMap::new(), renderbuffers: HashMap::new() }
+    }
+
+    fn apply_blend(blend: BlendMode) {
+        unsafe {
+            gl::Enable(gl::BLEND);
+            match blend {
+                BlendMode::Alpha =>
+                    gl::BlendFuncSeparate(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+                BlendMode::Premultiplied =>
+                    gl::BlendFunc(gl::ONE, gl::ONE_MINUS_SRC_ALPHA),
+                BlendMode::Additive =>
+                    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE),
+                BlendMode::Multiply =>
+                    gl::BlendFunc(gl::DST_COLOR, gl::ZERO),
+            }
+        }
+    }
+}
+
+impl RenderBackend for GlBackend {
+    fn create_buffer(&mut self, kind: BufferKind) -> BufferId {
+        let mut buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+            if kind == BufferKind::Vertex {
+                let stride = mem::size_of::<SpriteVertex>() as GLint;
+                let mut vao = 0;
+                gl::GenVertexArrays(1, &mut vao);
+                gl::BindVertexArray(vao);
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+                gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, 0 as *const c_void);
+                gl::EnableVertexAttribArray(0);
+                gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (2 * mem::size_of::<f32>()) as *const c_void);
+                gl::EnableVertexAttribArray(1);
+                gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, stride, (4 * mem::size_of::<f32>()) as *const c_void);
+                gl::EnableVertexAttribArray(2);
+                gl::BindVertexArray(0);
+                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+                self.vaos.insert(BufferId(buffer), vao);
+            }
+        }
+        BufferId(buffer)
+    }
+
+    fn upload_vertices(&mut self, buffer: BufferId, vertices: &[SpriteVertex]) {
+        unsafe {
+            // Respecifying the whole buffer orphans the old storage, so we
+            // don't stall on draws still using it
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer.0);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           (vertices.len() * mem::size_of::<SpriteVertex>()) as GLsizeiptr,
+                           vertices.as_ptr() as *const c_void,
+                           gl::STREAM_DRAW);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    fn upload_indices(&mut self, buffer: BufferId, indices: &[u32]) {
+        unsafe {
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer.0);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
+                           (indices.len() * mem::size_of::<u32>()) as GLsizeiptr,
+                           indices.as_ptr() as *const c_void,
+                           gl::STREAM_DRAW);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        }
+    }
+
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        unsafe {
+            if let Some(vao) = self.vaos.remove(&buffer) {
+                gl::DeleteVertexArrays(1, &vao);
+            }
+            gl::DeleteBuffers(1, &buffer.0);
+        }
+    }
+
+    fn upload_texture(&mut self, texture: &mut Texture) {
+        texture.upload();
+    }
+
+    fn delete_texture(&mut self, texture: &mut Texture) {
+        unsafe {
+            gl::DeleteTextures(1, &texture.id());
+        }
+        texture.set_id(0);
+    }
+
+    fn bind_texture(&mut self, unit: u32, texture: &Texture) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            texture.bind();
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    fn compile_shader(&mut self, shader: &mut Shader) -> Result<(), ShaderError> {
+        shader.compile()
+    }
+
+    fn bind_shader(&mut self, shader: &Shader, view_projection: Matrix4<f32>) {
+        shader.use_shader();
+        shader.set_mat4("projection", view_projection);
+        shader.set_mat4("model", Matrix4::<f32>::one());
+        shader.set_vec4("spriteColor", Vector4::new(1.0, 1.0, 1.0, 1.0));
+    }
+
+    fn set_uniform(&mut self, shader: &Shader, name: &str, value: UniformValue) {
+        shader.set_uniform(name, &value);
+    }
+
+    fn create_target(&mut self, texture: &Texture, depth_stencil: bool) -> TargetId {
+        let mut fbo = 0;
+        unsafe {
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, texture.id(), 0);
+
+            if depth_stencil {
+                let mut rbo = 0;
+                gl::GenRenderbuffers(1, &mut rbo);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, rbo);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, texture.width, texture.height);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, rbo);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+                self.renderbuffers.insert(TargetId(fbo), rbo);
+            }
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                panic!("Framebuffer is incomplete (status {:#x})", status);
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        TargetId(fbo)
+    }
+
+    fn resize_target(&mut self, target: TargetId, texture: &mut Texture, width: u32, height: u32) {
+        texture.resize(width as GLint, height as GLint);
+        if let Some(&rbo) = self.renderbuffers.get(&target) {
+            unsafe {
+                gl::BindRenderbuffer(gl::RENDERBUFFER, rbo);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width as GLsizei, height as GLsizei);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            }
+        }
+    }
+
+    fn delete_target(&mut self, target: TargetId) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &target.0);
+            if let Some(rbo) = self.renderbuffers.remove(&target) {
+                gl::DeleteRenderbuffers(1, &rbo);
+            }
+        }
+    }
+
+    fn bind_target(&mut self, target: Option<TargetId>) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target.map_or(0, |t| t.0));
+        }
+    }
+
+    fn blit_to_screen(&mut self, target: TargetId, texture: &Texture, rect: (i32, i32, u32, u32), filter: GLuint) {
+        let (x, y, width, height) = rect;
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, target.0);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::BlitFramebuffer(0, 0, texture.width, texture.height,
+                                x, y, x + width as GLint, y + height as GLint,
+                                gl::COLOR_BUFFER_BIT, filter);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    fn set_viewport(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        unsafe {
+            gl::Viewport(x, y, width as GLsizei, height as GLsizei);
+        }
+    }
+
+    fn clear(&mut self, color: Vector4<f32>) {
+        unsafe {
+            gl::ClearColor(color.x, color.y, color.z, color.w);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        }
+    }
+
+    fn draw(&mut self, vertices: BufferId, first_vertex: usize, vertex_count: usize,
+            texture: &Texture, blend: BlendMode) {
+        let vao = *self.vaos.get(&vertices).expect("Not a vertex buffer");
+        GlBackend::apply_blend(blend);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            texture.bind();
+            gl::BindVertexArray(vao);
+            gl::DrawArrays(gl::TRIANGLES, first_vertex as GLint, vertex_count as GLsizei);
+            gl::BindVertexArray(0);
+        }
+    }
+
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId,
+                    first_index: usize, index_count: usize,
+                    texture: &Texture, blend: BlendMode) {
+        let vao = *self.vaos.get(&vertices).expect("Not a vertex buffer");
+        GlBackend::apply_blend(blend);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            texture.bind();
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, indices.0);
+            gl::DrawElements(gl::TRIANGLES, index_count as GLsizei, gl::UNSIGNED_INT,
+                             (first_index * mem::size_of::<u32>()) as *const c_void);
+            gl::BindVertexArray(0);
+        }
+    }
+
+    fn read_pixels(&mut self, x: i32, y: i32, width: u32, height: u32) -> Image<u8> {
+        let row = 4 * width as usize;
+        let mut data = vec![0u8; row * height as usize];
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(x, y, width as GLsizei, height as GLsizei, gl::RGBA, gl::UNSIGNED_BYTE,
+                           data.as_mut_ptr() as *mut c_void);
+        }
+        // GL returns the bottom row first
+        let mut flipped = Vec::with_capacity(data.len());
+        for chunk in data.chunks(row).rev() {
+            flipped.extend_from_slice(chunk);
+        }
+        Image { width: width as usize, height: height as usize, depth: 4, data: flipped }
+    }
+}
+
+// CPU rasteriser with nearest sampling, rendering into RGBA images. Shaders
+// run through the SoftwareShader registered on them, binding one without
+// panics. Slow, but deterministic and usable without a GL context, e.g. for
+// snapshot tests.
+pub struct SoftwareBackend {
+    // The window's back buffer
+    screen: Surface,
+    targets: HashMap<TargetId, Surface>,
+    // Texture id to the target drawing into it, so drawing with a target's
+    // texture samples what was rendered
+    target_textures: HashMap<GLuint, TargetId>,
+    bound: Option<TargetId>,
+    viewport: (i32, i32, u32, u32),
+    view_projection: Matrix4<f32>,
+    program: Option<SoftwareShader>,
+    uniforms: HashMap<String, UniformValue>,
+
+    vertex_buffers: HashMap<BufferId, Vec<SpriteVertex>>,
+    index_buffers: HashMap<BufferId, Vec<u32>>,
+    next_buffer: u32,
+    next_target: u32,
+}
+
+// Texture ids are shared between all SoftwareBackends, as textures are
+// often uploaded by one and drawn by another
+static NEXT_TEXTURE_ID: AtomicUsize = AtomicUsize::new(1);
+
+// RGBA pixels, top row first
+struct Surface {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Surface {
+    fn new(width: u32, height: u32) -> Self {
+        Surface { width, height, pixels: vec![0; 4 * (width * height) as usize] }
+    }
+
+    // Nearest texel, clamped to the edges. Like a GL framebuffer texture,
+    // v = 0 is the bottom row.
+    fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        let clamp = |coord: f32, size: u32| ((coord * size as f32).floor() as i64).max(0).min(size as i64 - 1) as u32;
+        let x = clamp(u, self.width);
+        let y = self.height - 1 - clamp(v, self.height);
+        let i = 4 * (y * self.width + x) as usize;
+        let c = |k: usize| self.pixels[i + k] as f32 / 255.0;
+        [c(0), c(1), c(2), c(3)]
+    }
+}
+
+// Stand-in for a shader's fragment stage on the SoftwareBackend, set by
+// whoever owns the shader with Shader::set_software. Returns the colour
+// before blending.
+pub type SoftwareShader = fn(&Fragment) -> [f32; 4];
+
+// What a SoftwareShader gets
+pub struct Fragment<'a> {
+    // Interpolated vertex position, e.g. world space for lights
+    pub pos: Vector2<f32>,
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+    // gl_FragCoord: pixel centre, origin at the bottom left
+    pub coord: Vector2<f32>,
+    backend: &'a SoftwareBackend,
+    texture: &'a Texture,
+}
+
+impl<'a> Fragment<'a> {
+    // The texture drawn with, or what was rendered into it for a target's
+    pub fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        self.backend.sample(self.texture, u, v)
+    }
+
+    // Uniforms set since the shader was bound, `default` if there is none
+    // of that type; pass the default from the .shader file
+    pub fn float(&self, name: &str, default: f32) -> f32 {
+        match self.backend.uniforms.get(name) {
+            Some(&UniformValue::Float(value)) => value,
+            _ => default,
+        }
+    }
+
+    pub fn vec2(&self, name: &str, default: Vector2<f32>) -> Vector2<f32> {
+        match self.backend.uniforms.get(name) {
+            Some(&UniformValue::Vec2(value)) => value,
+            _ => default,
+        }
+    }
+
+    pub fn vec3(&self, name: &str, default: Vector3<f32>) -> Vector3<f32> {
+        match self.backend.uniforms.get(name) {
+            Some(&UniformValue::Vec3(value)) => value,
+            _ => default,
+        }
+    }
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge0 == edge1 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).max(0.0).min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    // Always evaluate from the same endpoint, so the two triangles sharing
+    // an edge get exactly opposite values and no pixel falls in between
+    if (b[0], b[1]) < (a[0], a[1]) {
+        return -edge(b, a, p);
+    }
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+// Tie-breaking rule for pixel centres exactly on an edge: of two triangles
+// sharing an edge (walked in opposite directions), exactly one owns it
+fn owns_edge(a: [f32; 2], b: [f32; 2]) -> bool {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    dy > 0.0 || (dy == 0.0 && dx > 0.0)
+}
+
+fn blend_pixel(src: [f32; 4], dst: [f32; 4], blend: BlendMode) -> [f32; 4] {
+    let mut out = [0.0; 4];
+    for i in 0..4 {
+        out[i] = match blend {
+            BlendMode::Alpha if i < 3 => src[i] * src[3] + dst[i] * (1.0 - src[3]),
+            BlendMode::Alpha | BlendMode::Premultiplied => src[i] + dst[i] * (1.0 - src[3]),
+            BlendMode::Additive => src[i] * src[3] + dst[i],
+            BlendMode::Multiply => src[i] * dst[i],
+        };
+        out[i] = out[i].max(0.0).min(1.0);
+    }
+    out
+}
+
+impl SoftwareBackend {
+    pub fn new(width: u32, height: u32) -> Self {
+        SoftwareBackend {
+            screen: Surface::new(width, height),
+            targets: HashMap::new(),
+            target_textures: HashMap::new(),
+            bound: None,
+            viewport: (0, 0, width, height),
+            view_projection: Matrix4::one(),
+            program: None,
+            uniforms: HashMap::new(),
+            vertex_buffers: HashMap::new(),
+            index_buffers: HashMap::new(),
+            next_buffer: 1,
+            next_target: 1,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.screen.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.screen.height
+    }
+
+    // A pixel of the back buffer, top row first
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = 4 * (y * self.screen.width + x) as usize;
+        let pixels = &self.screen.pixels;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    }
+
+    pub fn image(&self) -> Image<u8> {
+        Image { width: self.screen.width as usize, height: self.screen.height as usize, depth: 4,
+                data: self.screen.pixels.clone() }
+    }
+
+    fn bound_surface(&self) -> &Surface {
+        match self.bound {
+            Some(target) => &self.targets[&target],
+            None => &self.screen,
+        }
+    }
+
+    fn bound_surface_mut(&mut self) -> &mut Surface {
+        match self.bound {
+            Some(target) => self.targets.get_mut(&target).unwrap(),
+            None => &mut self.screen,
+        }
+    }
+
+    fn sample(&self, texture: &Texture, u: f32, v: f32) -> [f32; 4] {
+        let surface = self.target_textures.get(&texture.id()).and_then(|target| self.targets.get(target));
+        match surface {
+            // Empty while it's being drawn into
+            Some(surface) if !surface.pixels.is_empty() => surface.sample(u, v),
+            _ => texture.sample(u, v),
+        }
+    }
+
+    // Clip space to pixels of a surface (top row first)
+    fn to_screen(&self, surface_height: u32, v: &SpriteVertex) -> [f32; 2] {
+        let clip = self.view_projection * Vector4::new(v.pos[0], v.pos[1], 0.0, 1.0);
+        let (vx, vy, vw, vh) = self.viewport;
+        let top = surface_height as f32 - (vy as f32 + vh as f32);
+        // Snap to 1/256 pixel like GPUs do, so rounding errors can't move
+        // an edge to either side of a pixel centre
+        let snap = |x: f32| (x * 256.0).round() / 256.0;
+        [snap(vx as f32 + (clip.x / clip.w + 1.0) * 0.5 * vw as f32),
+         snap(top + (1.0 - clip.y / clip.w) * 0.5 * vh as f32)]
+    }
+
+    fn draw_triangle(&self, surface: &mut Surface, v: [&SpriteVertex; 3], texture: &Texture, blend: BlendMode) {
+        let program = self.program.expect("No shader bound");
+        let mut p = [self.to_screen(surface.height, v[0]), self.to_screen(surface.height, v[1]),
+                     self.to_screen(surface.height, v[2])];
+        let mut v = v;
+        let mut area = edge(p[0], p[1], p[2]);
+        if area == 0.0 {
+            return;
+        }
+        // Normalise the winding so inside means positive edge functions
+        if area < 0.0 {
+            p.swap(1, 2);
+            v.swap(1, 2);
+            area = -area;
+        }
+
+        // Scissor to both the viewport and the framebuffer
+        let (vx, vy, vw, vh) = self.viewport;
+        let top = surface.height as i32 - (vy + vh as i32);
+        let clip_x0 = vx.max(0);
+        let clip_y0 = top.max(0);
+        let clip_x1 = (vx + vw as i32).min(surface.width as i32);
+        let clip_y1 = (top + vh as i32).min(surface.height as i32);
+
+        let min_x = (p.iter().fold(::std::f32::MAX, |m, q| m.min(q[0])).floor() as i32).max(clip_x0);
+        let min_y = (p.iter().fold(::std::f32::MAX, |m, q| m.min(q[1])).floor() as i32).max(clip_y0);
+        let max_x = (p.iter().fold(::std::f32::MIN, |m, q| m.max(q[0])).ceil() as i32).min(clip_x1);
+        let max_y = (p.iter().fold(::std::f32::MIN, |m, q| m.max(q[1])).ceil() as i32).min(clip_y1);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let center = [x as f32 + 0.5, y as f32 + 0.5];
+                let w = [edge(p[1], p[2], center), edge(p[2], p[0], center), edge(p[0], p[1], center)];
+                let inside = (w[0] > 0.0 || (w[0] == 0.0 && owns_edge(p[1], p[2])))
+                    && (w[1] > 0.0 || (w[1] == 0.0 && owns_edge(p[2], p[0])))
+                    && (w[2] > 0.0 || (w[2] == 0.0 && owns_edge(p[0], p[1])));
+                if !inside {
+                    continue;
+                }
+
+                let b = [w[0] / area, w[1] / area, w[2] / area];
+                let lerp = |f: &dyn Fn(&SpriteVertex) -> f32| b[0] * f(v[0]) + b[1] * f(v[1]) + b[2] * f(v[2]);
+                let mut color = [0.0; 4];
+                for i in 0..4 {
+                    color[i] = lerp(&|q| q.color[i]);
+                }
+                let fragment = Fragment {
+                    pos: Vector2::new(lerp(&|q| q.pos[0]), lerp(&|q| q.pos[1])),
+                    uv: [lerp(&|q| q.uv[0]), lerp(&|q| q.uv[1])],
+                    color,
+                    coord: Vector2::new(center[0], surface.height as f32 - center[1]),
+                    backend: self,
+                    texture,
+                };
+                let src = program(&fragment);
+
+                let offset = 4 * (y as usize * surface.width as usize + x as usize);
+                let mut dst = [0.0; 4];
+                for i in 0..4 {
+                    dst[i] = surface.pixels[offset + i] as f32 / 255.0;
+                }
+                let out = blend_pixel(src, dst, blend);
+                for i in 0..4 {
+                    surface.pixels[offset + i] = (out[i] * 255.0).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+impl RenderBackend for SoftwareBackend {
+    fn create_buffer(&mut self, kind: BufferKind) -> BufferId {
+        let id = BufferId(self.next_buffer);
+        self.next_buffer += 1;
+        match kind {
+            BufferKind::Vertex => { self.vertex_buffers.insert(id, Vec::new()); }
+            BufferKind::Index => { self.index_buffers.insert(id, Vec::new()); }
+        }
+        id
+    }
+
+    fn upload_vertices(&mut self, buffer: BufferId, vertices: &[SpriteVertex]) {
+        let data = self.vertex_buffers.get_mut(&buffer).expect("Not a vertex buffer");
+        data.clear();
+        data.extend_from_slice(vertices);
+    }
+
+    fn upload_indices(&mut self, buffer: BufferId, indices: &[u32]) {
+        let data = self.index_buffers.get_mut(&buffer).expect("Not an index buffer");
+        data.clear();
+        data.extend_from_slice(indices);
+    }
+
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        self.vertex_buffers.remove(&buffer);
+        self.index_buffers.remove(&buffer);
+    }
+
+    // Sampling reads the texture's CPU data directly, the id is only used
+    // to find render targets
+    fn upload_texture(&mut self, texture: &mut Texture) {
+        texture.set_id(NEXT_TEXTURE_ID.fetch_add(1, Ordering::Relaxed) as GLuint);
+    }
+
+    fn delete_texture(&mut self, texture: &mut Texture) {
+        self.target_textures.remove(&texture.id());
+        texture.set_id(0);
+    }
+
+    // Fragment only samples the texture drawn with
+    fn bind_texture(&mut self, _unit: u32, _texture: &Texture) {}
+
+    // Shaders run as their SoftwareShader, so there is nothing to compile
+    fn compile_shader(&mut self, _shader: &mut Shader) -> Result<(), ShaderError> {
+        Ok(())
+    }
+
+    fn bind_shader(&mut self, shader: &Shader, view_projection: Matrix4<f32>) {
+        self.view_projection = view_projection;
+        match shader.software() {
+            Some(program) => self.program = Some(program),
+            None => panic!("Shader {} can't run on the SoftwareBackend, see Shader::set_software",
+                           shader.describe()),
+        }
+        self.uniforms.clear();
+    }
+
+    fn set_uniform(&mut self, _shader: &Shader, name: &str, value: UniformValue) {
+        self.uniforms.insert(name.to_string(), value);
+    }
+
+    fn create_target(&mut self, texture: &Texture, _depth_stencil: bool) -> TargetId {
+        let target = TargetId(self.next_target);
+        self.next_target += 1;
+        self.targets.insert(target, Surface::new(texture.width as u32, texture.height as u32));
+        self.target_textures.insert(texture.id(), target);
+        target
+    }
+
+    fn resize_target(&mut self, target: TargetId, texture: &mut Texture, width: u32, height: u32) {
+        texture.set_size(width as GLint, height as GLint);
+        self.targets.insert(target, Surface::new(width, height));
+    }
+
+    fn delete_target(&mut self, target: TargetId) {
+        self.targets.remove(&target);
+        self.target_textures.retain(|_, t| *t != target);
+        if self.bound == Some(target) {
+            self.bound = None;
+        }
+    }
+
+    fn bind_target(&mut self, target: Option<TargetId>) {
+        if let Some(target) = target {
+            assert!(self.targets.contains_key(&target), "Not a render target of this backend");
+        }
+        self.bound = target;
+    }
+
+    // Always nearest, whatever the filter
+    fn blit_to_screen(&mut self, target: TargetId, _texture: &Texture, rect: (i32, i32, u32, u32), _filter: GLuint) {
+        let (x, y, width, height) = rect;
+        let source = &self.targets[&target];
+        let screen = &mut self.screen;
+        let top = screen.height as i32 - (y + height as i32);
+        for dy in 0..height as i32 {
+            for dx in 0..width as i32 {
+                let (sx, sy) = (x + dx, top + dy);
+                if sx < 0 || sy < 0 || sx >= screen.width as i32 || sy >= screen.height as i32 {
+                    continue;
+                }
+                let u = (dx as f32 + 0.5) / width as f32;
+                let v = 1.0 - (dy as f32 + 0.5) / height as f32;
+                let texel = source.sample(u, v);
+                let offset = 4 * (sy as usize * screen.width as usize + sx as usize);
+                for i in 0..4 {
+                    screen.pixels[offset + i] = (texel[i] * 255.0).round() as u8;
+                }
+            }
+        }
+        self.bound = None;
+    }
+
+    fn set_viewport(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        self.viewport = (x, y, width, height);
+    }
+
+    fn clear(&mut self, color: Vector4<f32>) {
+        let c = [color.x, color.y, color.z, color.w];
+        for pixel in self.bound_surface_mut().pixels.chunks_mut(4) {
+            for i in 0..4 {
+                pixel[i] = (c[i].max(0.0).min(1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+
+    fn draw(&mut self, vertices: BufferId, first_vertex: usize, vertex_count: usize,
+            texture: &Texture, blend: BlendMode) {
+        // Taken out temporarily so we can rasterise into it while sampling
+        // other targets
+        let mut surface = mem::replace(self.bound_surface_mut(), Surface::new(0, 0));
+        let vertex_data = &self.vertex_buffers[&vertices];
+        for tri in vertex_data[first_vertex..first_vertex + vertex_count].chunks(3) {
+            if tri.len() == 3 {
+                self.draw_triangle(&mut surface, [&tri[0], &tri[1], &tri[2]], texture, blend);
+            }
+        }
+        *self.bound_surface_mut() = surface;
+    }
+
+    fn draw_indexed(&mut self, vertices: BufferId, indices: BufferId,
+                    first_index: usize, index_count: usize,
+                    texture: &Texture, blend: BlendMode) {
+        let mut surface = mem::replace(self.bound_surface_mut(), Surface::new(0, 0));
+        let vertex_data = &self.vertex_buffers[&vertices];
+        let index_data = &self.index_buffers[&indices];
+        for tri in index_data[first_index..first_index + index_count].chunks(3) {
+            if tri.len() == 3 {
+                let v = [&vertex_data[tri[0] as usize], &vertex_data[tri[1] as usize], &vertex_data[tri[2] as usize]];
+                self.draw_triangle(&mut surface, v, texture, blend);
+            }
+        }
+        *self.bound_surface_mut() = surface;
+    }
+
+    fn read_pixels(&mut self, x: i32, y: i32, width: u32, height: u32) -> Image<u8> {
+        // Same bottom-left origin as glReadPixels, returned top row first
+        let surface = self.bound_surface();
+        let mut data = Vec::with_capacity(4 * (width * height) as usize);
+        let top = surface.height as i32 - (y + height as i32);
+        for row in top..top + height as i32 {
+            let start = 4 * (row as usize * surface.width as usize + x as usize);
+            data.extend_from_slice(&surface.pixels[start..start + 4 * width as usize]);
+        }
+        Image { width: width as usize, height: height as usize, depth: 4, data }
+    }
+}
+
+// Accepts everything and draws nothing, for running without a window
+pub struct NullBackend {
+    next_buffer: u32,
+    next_target: u32,
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        NullBackend { next_buffer: 1, next_target: 1 }
+    }
+}
+
+impl RenderBackend for NullBackend {
+    fn create_buffer(&mut self, _kind: BufferKind) -> BufferId {
+        let id = BufferId(self.next_buffer);
+        self.next_buffer += 1;
+        id
+    }
+
+    fn upload_vertices(&mut self, _buffer: BufferId, _vertices: &[SpriteVertex]) {}
+    fn upload_indices(&mut self, _buffer: BufferId, _indices: &[u32]) {}
+    fn delete_buffer(&mut self, _buffer: BufferId) {}
+    fn upload_texture(&mut self, _texture: &mut Texture) {}
+    fn delete_texture(&mut self, _texture: &mut Texture) {}
+    fn bind_texture(&mut self, _unit: u32, _texture: &Texture) {}
+
+    fn compile_shader(&mut self, _shader: &mut Shader) -> Result<(), ShaderError> {
+        Ok(())
+    }
+
+    fn bind_shader(&mut self, _shader: &Shader, _view_projection: Matrix4<f32>) {}
+    fn set_uniform(&mut self, _shader: &Shader, _name: &str, _value: UniformValue) {}
+
+    fn create_target(&mut self, _texture: &Texture, _depth_stencil: bool) -> TargetId {
+        let id = TargetId(self.next_target);
+        self.next_target += 1;
+        id
+    }
+
+    fn resize_target(&mut self, _target: TargetId, texture: &mut Texture, width: u32, height: u32) {
+        texture.set_size(width as GLint, height as GLint);
+    }
+
+    fn delete_target(&mut self, _target: TargetId) {}
+    fn bind_target(&mut self, _target: Option<TargetId>) {}
+    fn blit_to_screen(&mut self, _target: TargetId, _texture: &Texture, _rect: (i32, i32, u32, u32), _filter: GLuint) {}
+    fn set_viewport(&mut self, _x: i32, _y: i32, _width: u32, _height: u32) {}
+    fn clear(&mut self, _color: Vector4<f32>) {}
+    fn draw(&mut self, _vertices: BufferId, _first_vertex: usize, _vertex_count: usize,
+            _texture: &Texture, _blend: BlendMode) {}
+    fn draw_indexed(&mut self, _vertices: BufferId, _indices: BufferId,
+                    _first_index: usize, _index_count: usize,
+                    _texture: &Texture, _blend: BlendMode) {}
+
+    // Transparent black
+    fn read_pixels(&mut self, _x: i32, _y: i32, width: u32, height: u32) -> Image<u8> {
+        Image { width: width as usize, height: height as usize, depth: 4, data: vec![0; 4 * (width * height) as usize] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use render_backend::*;
+    use texture::TextureBuilder;
+    use sprite_renderer::software_sprite_shader;
+    use cgmath;
+
+    fn white_texture(backend: &mut SoftwareBackend) -> Texture {
+        TextureBuilder::new()
+            .image(Image { width: 1, height: 1, depth: 4, data: vec![255, 255, 255, 255] })
+            .image_format(gl::RGBA)
+            .build_with(backend)
+    }
+
+    fn sprite_shader() -> Shader {
+        let mut shader = Shader::new(String::new(), String::new());
+        shader.set_software(software_sprite_shader);
+        shader
+    }
+
+    fn quad(x0: f32, y0: f32, x1: f32, y1: f32, color: [f32; 4]) -> Vec<SpriteVertex> {
+        [(x0, y0), (x1, y0), (x1, y1), (x0, y1)].iter()
+            .map(|&(x, y)| SpriteVertex { pos: [x, y], uv: [0.0, 0.0], color })
+            .collect()
+    }
+
+    fn draw_quad(backend: &mut SoftwareBackend, texture: &Texture, vertices: &[SpriteVertex], blend: BlendMode) {
+        let vb = backend.create_buffer(BufferKind::Vertex);
+        let ib = backend.create_buffer(BufferKind::Index);
+        backend.upload_vertices(vb, vertices);
+        backend.upload_indices(ib, &[0, 1, 2, 0, 2, 3]);
+        backend.draw_indexed(vb, ib, 0, 6, texture, blend);
+    }
+
+    #[test]
+    fn test_software_backend_fill() {
+        let mut backend = SoftwareBackend::new(8, 8);
+        let texture = white_texture(&mut backend);
+        let shader = sprite_shader();
+        backend.bind_shader(&shader, cgmath::ortho(0.0, 8.0, 8.0, 0.0, -1.0, 1.0));
+        backend.clear(Vector4::new(0.0, 0.0, 0.0, 1.0));
+
+        draw_quad(&mut backend, &texture, &quad(2.0, 2.0, 6.0, 4.0, [1.0, 0.0, 0.0, 1.0]), BlendMode::Alpha);
+
+        let mut covered = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = x >= 2 && x < 6 && y >= 2 && y < 4;
+                let expected = if inside { [255, 0, 0, 255] } else { [0, 0, 0, 255] };
+                assert_eq!(backend.pixel(x, y), expected, "pixel ({}, {})", x, y);
+                if inside { covered += 1; }
+            }
+        }
+        assert_eq!(covered, 8);
+
+        // Read back uses GL's bottom-left origin
+        let image = backend.read_pixels(2, 4, 4, 2);
+        assert_eq!(image.data.len(), 4 * 4 * 2);
+        assert!(image.data.chunks(4).all(|p| p == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_software_backend_blend_no_double_coverage() {
+        let mut backend = SoftwareBackend::new(4, 4);
+        let texture = white_texture(&mut backend);
+        let shader = sprite_shader();
+        backend.bind_shader(&shader, cgmath::ortho(0.0, 4.0, 4.0, 0.0, -1.0, 1.0));
+        backend.clear(Vector4::new(0.0, 0.0, 0.0, 0.0));
+
+        // The shared diagonal passes through pixel centres; each pixel must be blended once
+        draw_quad(&mut backend, &texture, &quad(0.0, 0.0, 4.0, 4.0, [1.0, 1.0, 1.0, 0.5]), BlendMode::Alpha);
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(backend.pixel(x, y), [128, 128, 128, 128]);
+            }
+        }
+
+        draw_quad(&mut backend, &texture, &quad(0.0, 0.0, 4.0, 4.0, [0.5, 0.0, 0.0, 1.0]), BlendMode::Additive);
+        assert_eq!(backend.pixel(1, 1), [255, 128, 128, 255]);
+    }
+
+    #[test]
+    fn test_software_backend_draw_range() {
+        let mut backend = SoftwareBackend::new(4, 4);
+        let texture = white_texture(&mut backend);
+        let shader = sprite_shader();
+        backend.bind_shader(&shader, cgmath::ortho(0.0, 4.0, 4.0, 0.0, -1.0, 1.0));
+        backend.clear(Vector4::new(0.0, 0.0, 0.0, 1.0));
+
+        // Two quads as a plain triangle list, only the second one drawn
+        let mut vertices = Vec::new();
+        for &(x0, x1) in &[(0.0, 2.0), (2.0, 4.0)] {
+            let q = quad(x0, 0.0, x1, 4.0, [0.0, 1.0, 0.0, 1.0]);
+            vertices.extend_from_slice(&[q[0], q[1], q[2], q[0], q[2], q[3]]);
+        }
+        let vb = backend.create_buffer(BufferKind::Vertex);
+        backend.upload_vertices(vb, &vertices);
+        backend.draw(vb, 6, 6, &texture, BlendMode::Alpha);
+
+        assert_eq!(backend.pixel(1, 1), [0, 0, 0, 255]);
+        assert_eq!(backend.pixel(2, 1), [0, 255, 0, 255]);
+        assert_eq!(backend.pixel(3, 3), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't run on the SoftwareBackend")]
+    fn test_software_backend_unknown_shader() {
+        let mut backend = SoftwareBackend::new(4, 4);
+        backend.bind_shader(&Shader::from_file("postfx/crt.shader".to_string()), Matrix4::one());
+    }
+}
@@ -0,0 +1,143 @@
+use std::thread;
+
+use gl;
+use gl::types::*;
+
+use cgmath::Vector4;
+
+use storage::{Storage, ResourceID};
+use texture::{Texture, TextureBuilder};
+use render_backend::{RenderBackend, TargetId};
+
+// An offscreen framebuffer whose colour attachment lives in Storage<Texture>,
+// so it can be drawn like any other texture once rendering into it is done.
+// Note that the resulting texture is stored bottom-up like any GL framebuffer.
+// The backend that built it owns the framebuffer, see release().
+pub struct RenderTarget {
+    target: TargetId,
+    texture: ResourceID<Texture>,
+    width: u32,
+    height: u32,
+    released: bool,
+}
+
+pub struct RenderTargetBuilder {
+    width: u32,
+    height: u32,
+    depth_stencil: bool,
+    internal_format: GLuint,
+    filter: GLuint,
+}
+
+impl RenderTargetBuilder {
+    pub fn new(width: u32, height: u32) -> Self {
+        RenderTargetBuilder {
+            width, height,
+            depth_stencil: false,
+            internal_format: gl::RGBA8,
+            filter: gl::LINEAR,
+        }
+    }
+
+    pub fn depth_stencil(mut self, depth_stencil: bool) -> Self {
+        self.depth_stencil = depth_stencil;
+        self
+    }
+
+    pub fn internal_format(mut self, internal_format: GLuint) -> Self {
+        self.internal_format = internal_format;
+        self
+    }
+
+    // Use gl::NEAREST for pixel-perfect low-resolution rendering
+    pub fn filter(mut self, filter: GLuint) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    // The texture is uploaded by `backend`, which has to be the one the
+    // target is drawn with
+    pub fn build(self, backend: &mut dyn RenderBackend, textures: &mut Storage<Texture>, name: &str) -> RenderTarget {
+        let texture = TextureBuilder::new()
+            .size(self.width as GLint, self.height as GLint)
+            .internal_format(self.internal_format)
+            .image_format(gl::RGBA)
+            .wrap(gl::CLAMP_TO_EDGE, gl::CLAMP_TO_EDGE)
+            .filter(self.filter, self.filter)
+            .build_with(backend);
+        let target = backend.create_target(&texture, self.depth_stencil);
+
+        RenderTarget {
+            target,
+            texture: textures.insert(name, texture),
+            width: self.width,
+            height: self.height,
+            released: false,
+        }
+    }
+}
+
+impl RenderTarget {
+    pub fn texture(&self) -> ResourceID<Texture> {
+        self.texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Redirect all following draw calls into this target
+    pub fn bind(&self, backend: &mut dyn RenderBackend) {
+        backend.bind_target(Some(self.target));
+        backend.set_viewport(0, 0, self.width, self.height);
+    }
+
+    // Go back to rendering into the window's back buffer
+    pub fn bind_default(backend: &mut dyn RenderBackend, viewport_width: u32, viewport_height: u32) {
+        backend.bind_target(None);
+        backend.set_viewport(0, 0, viewport_width, viewport_height);
+    }
+
+    pub fn clear(&self, backend: &mut dyn RenderBackend, r: f32, g: f32, b: f32, a: f32) {
+        self.bind(backend);
+        backend.clear(Vector4::new(r, g, b, a));
+    }
+
+    pub fn resize(&mut self, backend: &mut dyn RenderBackend, textures: &mut Storage<Texture>, width: u32, height: u32) {
+        if self.width == width && self.height == height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        backend.resize_target(self.target, textures.get_mut(self.texture), width, height);
+    }
+
+    // Copy the colour attachment into a rectangle of the default framebuffer
+    // (in window pixels, origin at the bottom left), e.g. to upscale a
+    // low-resolution target to the window.
+    pub fn blit_to_screen(&self, backend: &mut dyn RenderBackend, textures: &Storage<Texture>,
+                          x: i32, y: i32, width: u32, height: u32, filter: GLuint) {
+        backend.blit_to_screen(self.target, textures.get(self.texture), (x, y, width, height), filter);
+    }
+
+    // Free the framebuffer and its texture, which is removed from `textures`
+    pub fn release(mut self, backend: &mut dyn RenderBackend, textures: &mut Storage<Texture>) {
+        backend.delete_target(self.target);
+        backend.delete_texture(textures.get_mut(self.texture));
+        textures.release(self.texture);
+        self.released = true;
+    }
+}
+
+// Without the backend we can't free the framebuffer here, so a target
+// dropped without release() leaks it
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        debug_assert!(self.released || thread::panicking(),
+                      "RenderTarget dropped without calling release()");
+    }
+}
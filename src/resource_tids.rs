@@ -2,6 +2,8 @@ use super::storage::Resource;
 use super::shader::Shader;
 use super::texture::Texture;
 use super::sprite::SpriteData;
+use super::font::Font;
+use super::ecs::EntityData;
 
 impl Resource for Shader {
     fn tid() -> u16 { 1 }
@@ -14,3 +16,11 @@ impl Resource for Texture {
 impl Resource for SpriteData {
     fn tid() -> u16 { 3 }
 }
+
+impl Resource for Font {
+    fn tid() -> u16 { 4 }
+}
+
+impl Resource for EntityData {
+    fn tid() -> u16 { 5 }
+}
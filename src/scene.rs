@@ -0,0 +1,446 @@
+use std::mem;
+
+use cgmath::{Vector2, Vector4};
+use sdl2::event::Event;
+
+use app::{Game, Context};
+use window::Window;
+use game_data::GameData;
+use camera::Camera2D;
+use sprite_renderer::SpriteRenderer;
+use render_backend::RenderBackend;
+use shape_renderer::{ShapeRenderer, ShapeParams};
+
+// A title screen, level, pause menu... Each scene owns whatever it shows
+// (canvas, sprites, scripts) and only the top of the stack is updated.
+pub trait Scene {
+    // Before the scene is first shown, with write access to GameData for
+    // creating render targets and such
+    fn load(&mut self, _window: &Window, _data: &mut GameData, _backend: &mut dyn RenderBackend) {}
+
+    // After the scene has been removed, to release what load created
+    fn unload(&mut self, _data: &mut GameData, _backend: &mut dyn RenderBackend) {}
+
+    // Another scene was pushed on top of this one
+    fn pause(&mut self) {}
+
+    // The scene on top of this one was popped
+    fn resume(&mut self) {}
+
+    fn event(&mut self, _ctx: &mut Context, _event: &Event) -> SceneChange {
+        SceneChange::None
+    }
+
+    fn fixed_update(&mut self, _ctx: &mut Context, _dt: f32) -> SceneChange {
+        SceneChange::None
+    }
+
+    fn update(&mut self, _ctx: &mut Context, _dt: f32) -> SceneChange {
+        SceneChange::None
+    }
+
+    fn render(&mut self, ctx: &mut Context, renderer: &mut SpriteRenderer, alpha: f32);
+
+    fn resize(&mut self, _window: &Window, _data: &mut GameData, _backend: &mut dyn RenderBackend,
+              _old_size: (u32, u32)) {}
+
+    // Overlays (pause menus, dialogs) are drawn over the scenes below them,
+    // which keep rendering but don't update
+    fn is_overlay(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TransitionKind {
+    // Switch immediately
+    Cut,
+    // Fade to the colour and back
+    Fade(Vector4<f32>),
+    // A bar of the colour sweeps across the screen from left to right
+    Wipe(Vector4<f32>),
+}
+
+// How to get from one scene to the next. The scene changes halfway through,
+// when the screen is fully covered.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    // In real seconds, transitions ignore Time::time_scale
+    pub duration: f32,
+}
+
+impl Transition {
+    pub fn cut() -> Self {
+        Transition { kind: TransitionKind::Cut, duration: 0.0 }
+    }
+
+    pub fn fade(duration: f32) -> Self {
+        Transition { kind: TransitionKind::Fade(Vector4::new(0.0, 0.0, 0.0, 1.0)), duration }
+    }
+
+    pub fn wipe(duration: f32) -> Self {
+        Transition { kind: TransitionKind::Wipe(Vector4::new(0.0, 0.0, 0.0, 1.0)), duration }
+    }
+
+    // Black by default
+    pub fn color(mut self, color: Vector4<f32>) -> Self {
+        self.kind = match self.kind {
+            TransitionKind::Cut => TransitionKind::Cut,
+            TransitionKind::Fade(_) => TransitionKind::Fade(color),
+            TransitionKind::Wipe(_) => TransitionKind::Wipe(color),
+        };
+        self
+    }
+}
+
+pub enum SceneChange {
+    None,
+    Push(Box<dyn Scene>, Transition),
+    Pop(Transition),
+    Replace(Box<dyn Scene>, Transition),
+    Quit,
+}
+
+struct ActiveTransition {
+    transition: Transition,
+    elapsed: f32,
+    // The change to apply once the screen is covered, None after it was
+    change: Option<SceneChange>,
+    load_requested: bool,
+}
+
+impl ActiveTransition {
+    fn half(&self) -> f32 {
+        0.5 * self.transition.duration
+    }
+
+    // How much of the screen is covered, from 0 to 1 and back
+    fn coverage(&self) -> f32 {
+        if self.half() <= 0.0 {
+            return if self.change.is_some() { 1.0 } else { 0.0 };
+        }
+        let t = (self.elapsed / self.half()).min(2.0);
+        if self.change.is_some() { t.min(1.0) } else { (2.0 - t).min(1.0).max(0.0) }
+    }
+}
+
+// Runs a stack of scenes, as the Game given to App::run
+pub struct SceneManager {
+    stack: Vec<Box<dyn Scene>>,
+    transition: Option<ActiveTransition>,
+    // Layer the transition overlay is drawn on
+    pub overlay_layer: i32,
+}
+
+impl SceneManager {
+    // Starts with `scene`, which is loaded in Game::init
+    pub fn new(scene: Box<dyn Scene>) -> Self {
+        SceneManager {
+            stack: vec![scene],
+            transition: None,
+            overlay_layer: 1000,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    // How much of the screen the current transition covers, from 0 to 1
+    pub fn transition_coverage(&self) -> f32 {
+        self.transition.as_ref().map(|t| t.coverage()).unwrap_or(0.0)
+    }
+
+    // Requests from scenes during a transition are dropped
+    fn request(&mut self, ctx: &mut Context, change: SceneChange) {
+        let transition = match change {
+            SceneChange::None => return,
+            SceneChange::Quit => {
+                ctx.quit();
+                return;
+            }
+            SceneChange::Push(_, transition) | SceneChange::Pop(transition) |
+            SceneChange::Replace(_, transition) => transition,
+        };
+        if self.transition.is_some() {
+            return;
+        }
+        self.transition = Some(ActiveTransition { transition, elapsed: 0.0, change: Some(change), load_requested: false });
+        self.advance_transition(ctx, 0.0);
+    }
+
+    fn advance_transition(&mut self, ctx: &mut Context, dt: f32) {
+        let done = match self.transition {
+            Some(ref mut active) => {
+                active.elapsed += dt;
+                if active.change.is_some() && active.elapsed >= active.half() && !active.load_requested {
+                    // The stack changes between frames, in load()
+                    active.load_requested = true;
+                    ctx.request_load();
+                }
+                active.change.is_none() && active.elapsed >= active.transition.duration
+            }
+            None => false,
+        };
+        if done {
+            self.transition = None;
+        }
+    }
+
+    fn apply(&mut self, window: &Window, data: &mut GameData, backend: &mut dyn RenderBackend, change: SceneChange) {
+        match change {
+            SceneChange::Push(mut scene, _) => {
+                if let Some(top) = self.stack.last_mut() {
+                    top.pause();
+                }
+                scene.load(window, data, backend);
+                self.stack.push(scene);
+            }
+            SceneChange::Pop(_) => {
+                if let Some(mut scene) = self.stack.pop() {
+                    scene.unload(data, backend);
+                }
+                if let Some(top) = self.stack.last_mut() {
+                    top.resume();
+                }
+            }
+            SceneChange::Replace(mut scene, _) => {
+                if let Some(mut old) = self.stack.pop() {
+                    old.unload(data, backend);
+                }
+                scene.load(window, data, backend);
+                self.stack.push(scene);
+            }
+            SceneChange::None | SceneChange::Quit => {}
+        }
+    }
+
+    // Lowest scene that is visible: the top one and everything under overlays
+    fn first_visible(&self) -> usize {
+        let mut first = self.stack.len().saturating_sub(1);
+        while first > 0 && self.stack[first].is_overlay() {
+            first -= 1;
+        }
+        first
+    }
+
+    fn draw_transition(&self, ctx: &Context, renderer: &mut SpriteRenderer) {
+        let active = match self.transition {
+            Some(ref active) => active,
+            None => return,
+        };
+        let coverage = active.coverage();
+        let (width, height) = ctx.window.render_size();
+        let (width, height) = (width as f32, height as f32);
+        let (min_x, max_x, color) = match active.transition.kind {
+            TransitionKind::Cut => return,
+            TransitionKind::Fade(color) => (0.0, width, Vector4::new(color.x, color.y, color.z, color.w * coverage)),
+            // Sweeps in from the left while covering, out to the right after
+            TransitionKind::Wipe(color) if active.change.is_some() => (0.0, width * coverage, color),
+            TransitionKind::Wipe(color) => (width * (1.0 - coverage), width, color),
+        };
+        if coverage <= 0.0 || max_x <= min_x {
+            return;
+        }
+
+        renderer.set_camera(&Camera2D::new(width, height));
+        ShapeRenderer::new(renderer).fill_rect(Vector2::new(min_x, 0.0), Vector2::new(max_x, height),
+                                               &ShapeParams::new(color).layer(self.overlay_layer));
+        renderer.flush();
+    }
+}
+
+impl Game for SceneManager {
+    fn init(&mut self, window: &mut Window, data: &mut GameData, backend: &mut dyn RenderBackend) {
+        for scene in &mut self.stack {
+            scene.load(window, data, backend);
+        }
+    }
+
+    fn load(&mut self, window: &Window, data: &mut GameData, backend: &mut dyn RenderBackend) {
+        let change = match self.transition {
+            Some(ref mut active) => mem::replace(&mut active.change, None),
+            None => None,
+        };
+        if let Some(change) = change {
+            self.apply(window, data, backend, change);
+        }
+    }
+
+    fn event(&mut self, ctx: &mut Context, event: &Event) {
+        // Input is ignored during transitions
+        if self.transition.is_some() {
+            return;
+        }
+        let change = match self.stack.last_mut() {
+            Some(scene) => scene.event(ctx, event),
+            None => SceneChange::None,
+        };
+        self.request(ctx, change);
+    }
+
+    fn fixed_update(&mut self, ctx: &mut Context, dt: f32) {
+        let change = match self.stack.last_mut() {
+            Some(scene) => scene.fixed_update(ctx, dt),
+            None => SceneChange::None,
+        };
+        self.request(ctx, change);
+    }
+
+    fn update(&mut self, ctx: &mut Context, dt: f32) {
+        let unscaled = ctx.time.unscaled_delta();
+        self.advance_transition(ctx, unscaled);
+
+        let change = match self.stack.last_mut() {
+            Some(scene) => scene.update(ctx, dt),
+            None => SceneChange::None,
+        };
+        self.request(ctx, change);
+
+        if self.stack.is_empty() && self.transition.is_none() {
+            ctx.quit();
+        }
+    }
+
+    fn render(&mut self, ctx: &mut Context, renderer: &mut SpriteRenderer, alpha: f32) {
+        let first = self.first_visible();
+        for scene in &mut self.stack[first..] {
+            scene.render(ctx, renderer, alpha);
+            // Each scene is drawn over the ones below, whatever its layers
+            renderer.flush();
+        }
+        self.draw_transition(ctx, renderer);
+    }
+
+    fn resize(&mut self, window: &Window, data: &mut GameData, backend: &mut dyn RenderBackend, old_size: (u32, u32)) {
+        for scene in &mut self.stack {
+            scene.resize(window, data, backend, old_size);
+        }
+    }
+
+    fn shutdown(&mut self, data: &mut GameData, backend: &mut dyn RenderBackend) {
+        while let Some(mut scene) = self.stack.pop() {
+            scene.unload(data, backend);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scene::*;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use app::App;
+    use window::WindowConfig;
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    // Logs what happens to it, and asks for a change on some updates
+    struct Logger {
+        name: &'static str,
+        log: Log,
+        overlay: bool,
+        updates: u32,
+        changes: Vec<(u32, SceneChange)>,
+    }
+
+    impl Logger {
+        fn new(name: &'static str, log: &Log) -> Self {
+            Logger { name, log: log.clone(), overlay: false, updates: 0, changes: Vec::new() }
+        }
+
+        fn on_update(mut self, update: u32, change: SceneChange) -> Self {
+            self.changes.push((update, change));
+            self
+        }
+
+        fn push(&self, what: &str) {
+            self.log.borrow_mut().push(format!("{} {}", self.name, what));
+        }
+    }
+
+    impl Scene for Logger {
+        fn load(&mut self, _window: &Window, _data: &mut GameData, _backend: &mut dyn RenderBackend) { self.push("load"); }
+        fn unload(&mut self, _data: &mut GameData, _backend: &mut dyn RenderBackend) { self.push("unload"); }
+        fn pause(&mut self) { self.push("pause"); }
+        fn resume(&mut self) { self.push("resume"); }
+
+        fn update(&mut self, _ctx: &mut Context, _dt: f32) -> SceneChange {
+            self.updates += 1;
+            match self.changes.iter().position(|&(update, _)| update == self.updates) {
+                Some(i) => self.changes.remove(i).1,
+                None => SceneChange::None,
+            }
+        }
+
+        fn render(&mut self, _ctx: &mut Context, _renderer: &mut SpriteRenderer, _alpha: f32) {
+            self.push("render");
+        }
+
+        fn is_overlay(&self) -> bool {
+            self.overlay
+        }
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        mem::replace(&mut *log.borrow_mut(), Vec::new())
+    }
+
+    #[test]
+    fn test_scene_stack() {
+        let log: Log = Rc::new(RefCell::new(Vec::new()));
+        let mut menu = Logger::new("menu", &log).on_update(1, SceneChange::Pop(Transition::cut()));
+        menu.overlay = true;
+        let level = Logger::new("level", &log)
+            .on_update(2, SceneChange::Push(Box::new(menu), Transition::cut()))
+            .on_update(4, SceneChange::Pop(Transition::cut()));
+        let mut manager = SceneManager::new(Box::new(level));
+
+        // The push happens between frames
+        let mut app = App::headless(WindowConfig::default());
+        assert!(app.run_frames(&mut manager, 2));
+        assert_eq!(take(&log), ["level load", "level render", "level render", "level pause", "menu load"]);
+        assert_eq!(manager.len(), 2);
+
+        // The overlay is drawn over the paused level, then pops itself
+        assert!(app.run_frames(&mut manager, 2));
+        assert_eq!(take(&log), ["level render", "menu render", "menu unload", "level resume", "level render"]);
+        assert_eq!(manager.len(), 1);
+
+        // Popping the last scene quits
+        assert!(!app.run_frames(&mut manager, 10));
+        assert_eq!(take(&log), ["level render", "level unload"]);
+        assert_eq!(app.time().frame_count(), 6);
+    }
+
+    #[test]
+    fn test_scene_transition() {
+        let log: Log = Rc::new(RefCell::new(Vec::new()));
+        let level = Logger::new("level", &log);
+        let title = Logger::new("title", &log)
+            .on_update(1, SceneChange::Replace(Box::new(level), Transition::fade(0.09)));
+        let mut manager = SceneManager::new(Box::new(title));
+
+        // 1/60s frames, so the screen is covered on the 4th frame and the
+        // fade finishes on the 7th
+        let mut app = App::headless(WindowConfig::default());
+        app.run_frames(&mut manager, 2);
+        assert!(manager.is_transitioning());
+        assert!((manager.transition_coverage() - (1.0 / 60.0) / 0.045).abs() < 1e-3);
+
+        app.run_frames(&mut manager, 3);
+        assert_eq!(take(&log), ["title load", "title render", "title render", "title render", "title render",
+                                "title unload", "level load", "level render"]);
+        assert!((manager.transition_coverage() - (2.0 - (4.0 / 60.0) / 0.045)).abs() < 1e-3);
+
+        app.run_frames(&mut manager, 1);
+        assert!(manager.is_transitioning());
+        app.run_frames(&mut manager, 1);
+        assert!(!manager.is_transitioning());
+    }
+}
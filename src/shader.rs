@@ -1,32 +1,148 @@
 use std::ffi::CString;
 use std::ptr;
-use std::str;
-use std::fs::File;
-use std::io::prelude::*;
+use std::fmt;
+use std::error::Error;
+use std::io;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 
 use gl;
 use gl::types::*;
 
-use cgmath::{Matrix4, Vector3};
+use cgmath::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
 use cgmath::prelude::*;
 
 use serde::{Serialize, Deserialize};
 
-use path::*;
+use storage::{Storage, ResourceID};
+use shader_preprocessor::{Preprocessor, PreprocessedSource, QuotedLine};
+use shader_file::{ShaderFile, ShaderStage, UniformDecl};
+use render_backend::SoftwareShader;
+
+// Uniform as reported by glGetActiveUniform. Arrays are stored without
+// the trailing "[0]", with `size` holding the number of elements.
+#[derive(Copy, Clone, Debug)]
+pub struct UniformInfo {
+    pub location: GLint,
+    pub ty: GLenum,
+    pub size: GLint,
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io { path: String, error: io::Error },
+    Parse { path: String, line: usize, message: String },
+    // One entry per stage that failed to compile
+    Compile(Vec<ShaderCompileError>),
+    Link { log: String },
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderCompileError {
+    pub stage: ShaderStage,
+    pub path: String,
+    // Info log with line numbers mapped back to the original files
+    pub log: String,
+    // Source lines the log refers to
+    pub lines: Vec<QuotedLine>,
+}
+
+impl fmt::Display for ShaderCompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Failed to compile {} shader {}:", self.stage.name(), self.path)?;
+        writeln!(f, "{}", self.log.trim_end())?;
+        for line in &self.lines {
+            writeln!(f, "    {}:{} | {}", line.file, line.line, line.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShaderError::Io { ref path, ref error } =>
+                write!(f, "Failed to read shader {}: {}", path, error),
+            ShaderError::Parse { ref path, line, ref message } =>
+                write!(f, "{}:{}: {}", path, line, message),
+            ShaderError::Compile(ref errors) => {
+                for error in errors {
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            }
+            ShaderError::Link { ref log } =>
+                write!(f, "Failed to link shader program:\n{}", log),
+        }
+    }
+}
+
+impl Error for ShaderError {}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UniformValue {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Vec2(Vector2<f32>),
+    Vec3(Vector3<f32>),
+    Vec4(Vector4<f32>),
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Shader {
+    // Single-file .shader source; when set the two paths below are unused
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
     pub vertex_path: String,
+    #[serde(default)]
     pub fragment_path: String,
+    // Injected as #define lines, either "NAME" or "NAME=VALUE"
+    #[serde(default)]
+    pub defines: Vec<String>,
     program: GLuint,
-    loaded: bool
+    loaded: bool,
+
+    #[serde(skip)]
+    uniforms: HashMap<String, UniformInfo>,
+    // Names we already warned about, so a missing uniform doesn't spam every frame
+    #[serde(skip)]
+    missing_uniforms: RefCell<HashSet<String>>,
+    #[serde(skip)]
+    software: Option<SoftwareShader>,
 }
 
 fn to_cstring(source: &str) -> CString {
     CString::new(source.as_bytes()).unwrap()
 }
 
-fn compile_shader(shader_type: GLenum, source: &str) -> GLuint {
+fn shader_info_log(shader: GLuint) -> String {
+    unsafe {
+        let mut len = 0;
+        gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
+        let mut buf = vec![0u8; len.max(1) as usize];
+        let mut written = 0;
+        gl::GetShaderInfoLog(shader, buf.len() as GLsizei, &mut written, buf.as_mut_ptr() as *mut GLchar);
+        buf.truncate(written as usize);
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+fn program_info_log(program: GLuint) -> String {
+    unsafe {
+        let mut len = 0;
+        gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
+        let mut buf = vec![0u8; len.max(1) as usize];
+        let mut written = 0;
+        gl::GetProgramInfoLog(program, buf.len() as GLsizei, &mut written, buf.as_mut_ptr() as *mut GLchar);
+        buf.truncate(written as usize);
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+// Returns the info log on failure
+fn compile_shader(shader_type: GLenum, source: &str) -> Result<GLuint, String> {
     unsafe {
         let shader = gl::CreateShader(shader_type);
         
@@ -37,59 +153,174 @@ fn compile_shader(shader_type: GLenum, source: &str) -> GLuint {
         gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
 
         if status != (gl::TRUE as GLint) {
+            let log = shader_info_log(shader);
+            gl::DeleteShader(shader);
+            return Err(log);
+        }
+        return Ok(shader);
+    }
+}
+
+fn reflect_uniforms(program: GLuint) -> HashMap<String, UniformInfo> {
+    let mut uniforms = HashMap::new();
+    unsafe {
+        let mut count = 0;
+        let mut max_len = 0;
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+        gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_len);
+
+        for i in 0..count {
+            let mut buf = vec![0u8; max_len as usize];
             let mut len = 0;
-            gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-            let mut buf = Vec::new();
-            buf.set_len((len as usize) - 1);
-            gl::GetShaderInfoLog(shader, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-            panic!("{}", str::from_utf8(buf.as_slice()).ok().expect("ShaderInfoLog not valid utf8"));
+            let mut size = 0;
+            let mut ty = 0;
+            gl::GetActiveUniform(program, i as GLuint, max_len, &mut len, &mut size, &mut ty,
+                                 buf.as_mut_ptr() as *mut GLchar);
+            buf.truncate(len as usize);
+
+            let mut name = String::from_utf8(buf).expect("Uniform name not valid utf8");
+            if name.ends_with("[0]") {
+                let new_len = name.len() - 3;
+                name.truncate(new_len);
+            }
+            let location = gl::GetUniformLocation(program, to_cstring(&name).as_ptr());
+            uniforms.insert(name, UniformInfo { location, ty, size });
         }
-        return shader;
     }
+    uniforms
 }
 
 impl Shader {
     pub fn new(vertex_path: String, fragment_path: String) -> Self {
         Shader {
+            path: None,
             vertex_path,
             fragment_path,
+            defines: Vec::new(),
             program: 0,
-            loaded: false
+            loaded: false,
+            uniforms: HashMap::new(),
+            missing_uniforms: RefCell::new(HashSet::new()),
+            software: None,
         }
     }
-    pub fn compile(&mut self) {
-        let program = unsafe { gl::CreateProgram() };
 
-        let mut vertex_file = File::open(&asset_path(&self.vertex_path))
-            .expect(&format!("Vertex shader {} not found", &self.vertex_path));
-        let mut vertex_code = String::new();
-        vertex_file.read_to_string(&mut vertex_code)
-            .expect(&format!("Something went wrong reading the vertex shader {}", &self.vertex_path));
-        let mut fragment_file = File::open(&asset_path(&self.fragment_path))
-            .expect(&format!("Fragment shader {} not found", &self.fragment_path));
-        let mut fragment_code = String::new();
-        fragment_file.read_to_string(&mut fragment_code)
-            .expect(&format!("Something went wrong reading the fragment shader {}", &self.fragment_path));
+    pub fn from_file(path: String) -> Self {
+        let mut shader = Shader::new(String::new(), String::new());
+        shader.path = Some(path);
+        shader
+    }
+
+    // Copy of this shader with extra defines, not compiled yet
+    pub fn variant<S: AsRef<str>>(&self, defines: &[S]) -> Shader {
+        let mut variant = Shader::new(self.vertex_path.clone(), self.fragment_path.clone());
+        variant.path = self.path.clone();
+        variant.defines = self.defines.clone();
+        for define in defines {
+            let define = define.as_ref().to_string();
+            if !variant.defines.contains(&define) {
+                variant.defines.push(define);
+            }
+        }
+        variant
+    }
+
+    // How the SoftwareBackend runs this shader. Variants don't inherit it,
+    // their defines may change what the shader does.
+    pub fn set_software(&mut self, software: SoftwareShader) {
+        self.software = Some(software);
+    }
+
+    pub fn software(&self) -> Option<SoftwareShader> {
+        self.software
+    }
+
+    pub fn describe(&self) -> String {
+        match self.path {
+            Some(ref path) => path.clone(),
+            None => format!("{}, {}", self.vertex_path, self.fragment_path),
+        }
+    }
+
+    // Preprocessed sources of every stage, along with the uniform
+    // declarations of a .shader file
+    fn load_stages(&self) -> Result<(Vec<(ShaderStage, String, PreprocessedSource)>, Vec<UniformDecl>), ShaderError> {
+        let preprocessor = Preprocessor::new().defines(&self.defines);
+        let mut stages = Vec::new();
+        match self.path {
+            Some(ref path) => {
+                let shader_file = ShaderFile::load(path)?;
+                for section in &shader_file.stages {
+                    let source = preprocessor.process_section(path, &section.source, section.first_line - 1)?;
+                    stages.push((section.stage, path.clone(), source));
+                }
+                Ok((stages, shader_file.uniforms))
+            }
+            None => {
+                stages.push((ShaderStage::Vertex, self.vertex_path.clone(), preprocessor.process(&self.vertex_path)?));
+                stages.push((ShaderStage::Fragment, self.fragment_path.clone(), preprocessor.process(&self.fragment_path)?));
+                Ok((stages, Vec::new()))
+            }
+        }
+    }
+
+    pub fn compile(&mut self) -> Result<(), ShaderError> {
+        let (stages, uniform_decls) = self.load_stages()?;
+
+        // Compile every stage before bailing out, so errors in both the
+        // vertex and fragment stage get reported at once
+        let mut shader_ids = Vec::new();
+        let mut errors = Vec::new();
+        for &(stage, ref path, ref source) in &stages {
+            match compile_shader(stage.gl_type(), &source.source) {
+                Ok(id) => shader_ids.push(id),
+                Err(log) => errors.push(ShaderCompileError {
+                    stage,
+                    path: path.clone(),
+                    log: source.remap_log(&log),
+                    lines: source.quote_log_lines(&log),
+                }),
+            }
+        }
+        if !errors.is_empty() {
+            for id in shader_ids {
+                unsafe { gl::DeleteShader(id); }
+            }
+            return Err(ShaderError::Compile(errors));
+        }
+
+        let program = unsafe { gl::CreateProgram() };
 
         unsafe {
-            let vertex_shader_id = compile_shader(gl::VERTEX_SHADER, &vertex_code);
-            let fragment_shader_id = compile_shader(gl::FRAGMENT_SHADER, &fragment_code);
-            gl::AttachShader(program, vertex_shader_id);
-            gl::AttachShader(program, fragment_shader_id);
+            for &id in &shader_ids {
+                gl::AttachShader(program, id);
+            }
             gl::LinkProgram(program);
+            for &id in &shader_ids {
+                gl::DeleteShader(id);
+            }
+
             let mut success = 0;
-            let mut info_log = Vec::new();
             gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
             if success == 0 {
-                gl::GetProgramInfoLog(program, 512, ptr::null_mut(), info_log.as_mut_ptr() as *mut GLchar);
-                panic!("{}", str::from_utf8(info_log.as_slice()).ok().expect("PrograminfoLog not valid utf8"));
+                let log = program_info_log(program);
+                gl::DeleteProgram(program);
+                return Err(ShaderError::Link { log });
             }
-            gl::DeleteShader(vertex_shader_id);
-            gl::DeleteShader(fragment_shader_id);
         }
 
         self.program = program;
         self.loaded = true;
+        self.uniforms = reflect_uniforms(program);
+        self.missing_uniforms.borrow_mut().clear();
+
+        self.use_shader();
+        for decl in &uniform_decls {
+            if let Some(ref value) = decl.default {
+                self.set_uniform(&decl.name, value);
+            }
+        }
+        Ok(())
     }
 
     pub fn use_shader(&self) {
@@ -98,35 +329,153 @@ impl Shader {
         }
     }
 
+    pub fn has_uniform(&self, name: &str) -> bool {
+        self.uniforms.contains_key(name)
+    }
+
+    pub fn uniform_info(&self, name: &str) -> Option<&UniformInfo> {
+        self.uniforms.get(name)
+    }
+
+    // Looks up the cached location, warning once per name for uniforms the
+    // linked program doesn't have (misspelled or optimized away)
+    fn location(&self, name: &str) -> Option<GLint> {
+        match self.uniforms.get(name) {
+            Some(info) => Some(info.location),
+            None => {
+                if self.missing_uniforms.borrow_mut().insert(name.to_string()) {
+                    eprintln!("Warning: setting nonexistent uniform {} (shader {})",
+                              name, self.describe());
+                }
+                None
+            }
+        }
+    }
+
+    pub fn set_uniform(&self, name: &str, value: &UniformValue) {
+        match *value {
+            UniformValue::Bool(v) => self.set_bool(name, v),
+            UniformValue::Int(v) => self.set_int(name, v),
+            UniformValue::Float(v) => self.set_float(name, v),
+            UniformValue::Vec2(v) => self.set_vec2(name, v),
+            UniformValue::Vec3(v) => self.set_vec3(name, v),
+            UniformValue::Vec4(v) => self.set_vec4(name, v),
+        }
+    }
+
     pub fn set_bool(&self, name: &str, value: bool) {
-        unsafe {
-            gl::Uniform1i(gl::GetUniformLocation(self.program, to_cstring(name).as_ptr()), value as GLint);
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1i(location, value as GLint); }
         }
     }
-    
 
     pub fn set_int(&self, name: &str, value: i32) {
-        unsafe {
-            gl::Uniform1i(gl::GetUniformLocation(self.program, to_cstring(name).as_ptr()), value as GLint);
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1i(location, value as GLint); }
         }
     }
 
     pub fn set_float(&self, name: &str, value: f32) {
-        unsafe {
-            gl::Uniform1f(gl::GetUniformLocation(self.program, to_cstring(name).as_ptr()), value as GLfloat);
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1f(location, value as GLfloat); }
         }
     }
 
-    pub fn set_mat4(&self, name: &str, value: Matrix4<f32>) {
-        unsafe {
-            gl::UniformMatrix4fv(gl::GetUniformLocation(self.program, to_cstring(name).as_ptr()), 1, gl::FALSE, value.as_ptr());
+    // Bind a sampler uniform to the given texture unit
+    pub fn set_sampler(&self, name: &str, unit: u32) {
+        self.set_int(name, unit as i32);
+    }
+
+    pub fn set_vec2(&self, name: &str, value: Vector2<f32>) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform2fv(location, 1, value.as_ptr()); }
         }
     }
 
     pub fn set_vec3(&self, name: &str, value: Vector3<f32>) {
-        unsafe {
-            gl::Uniform3fv(gl::GetUniformLocation(self.program, to_cstring(name).as_ptr()), 1, value.as_ptr());
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform3fv(location, 1, value.as_ptr()); }
+        }
+    }
+
+    pub fn set_vec4(&self, name: &str, value: Vector4<f32>) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform4fv(location, 1, value.as_ptr()); }
+        }
+    }
+
+    pub fn set_mat3(&self, name: &str, value: Matrix3<f32>) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::UniformMatrix3fv(location, 1, gl::FALSE, value.as_ptr()); }
+        }
+    }
+
+    pub fn set_mat4(&self, name: &str, value: Matrix4<f32>) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::UniformMatrix4fv(location, 1, gl::FALSE, value.as_ptr()); }
+        }
+    }
+
+    pub fn set_int_array(&self, name: &str, values: &[i32]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1iv(location, values.len() as GLsizei, values.as_ptr()); }
+        }
+    }
+
+    pub fn set_float_array(&self, name: &str, values: &[f32]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1fv(location, values.len() as GLsizei, values.as_ptr()); }
+        }
+    }
+
+    pub fn set_vec2_array(&self, name: &str, values: &[Vector2<f32>]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform2fv(location, values.len() as GLsizei, values.as_ptr() as *const GLfloat); }
         }
     }
 
+    pub fn set_vec3_array(&self, name: &str, values: &[Vector3<f32>]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform3fv(location, values.len() as GLsizei, values.as_ptr() as *const GLfloat); }
+        }
+    }
+
+    pub fn set_vec4_array(&self, name: &str, values: &[Vector4<f32>]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform4fv(location, values.len() as GLsizei, values.as_ptr() as *const GLfloat); }
+        }
+    }
+
+    pub fn set_mat4_array(&self, name: &str, values: &[Matrix4<f32>]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::UniformMatrix4fv(location, values.len() as GLsizei, gl::FALSE, values.as_ptr() as *const GLfloat); }
+        }
+    }
+}
+
+// Name under which a variant is cached, e.g. "sprite.shader[ALPHA_TEST]".
+// Defines are sorted so the same set always maps to the same entry.
+pub fn variant_name<S: AsRef<str>>(base_name: &str, defines: &[S]) -> String {
+    let mut defines: Vec<&str> = defines.iter().map(|d| d.as_ref()).collect();
+    defines.sort();
+    defines.dedup();
+    format!("{}[{}]", base_name, defines.join(","))
+}
+
+impl Storage<Shader> {
+    // Returns the variant of `base` compiled with the extra defines,
+    // compiling and caching it on first use
+    pub fn get_or_compile_variant<S: AsRef<str>>(&mut self, base: ResourceID<Shader>, defines: &[S])
+        -> Result<ResourceID<Shader>, ShaderError> {
+        if defines.is_empty() {
+            return Ok(base);
+        }
+        let name = variant_name(self.get_name(base), defines);
+        if let Some((_, id)) = self.get_by_name(&name) {
+            return Ok(id);
+        }
+        let mut variant = self.get(base).variant(defines);
+        variant.compile()?;
+        Ok(self.insert(&name, variant))
+    }
 }
@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io::prelude::*;
+
+use gl;
+use gl::types::*;
+use cgmath::{Vector2, Vector3, Vector4};
+
+use shader::{ShaderError, UniformValue};
+use path::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Geometry,
+}
+
+impl ShaderStage {
+    pub fn from_name(name: &str) -> Option<ShaderStage> {
+        match name {
+            "vertex" => Some(ShaderStage::Vertex),
+            "fragment" => Some(ShaderStage::Fragment),
+            "geometry" => Some(ShaderStage::Geometry),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ShaderStage::Vertex => "vertex",
+            ShaderStage::Fragment => "fragment",
+            ShaderStage::Geometry => "geometry",
+        }
+    }
+
+    pub fn gl_type(&self) -> GLenum {
+        match *self {
+            ShaderStage::Vertex => gl::VERTEX_SHADER,
+            ShaderStage::Fragment => gl::FRAGMENT_SHADER,
+            ShaderStage::Geometry => gl::GEOMETRY_SHADER,
+        }
+    }
+}
+
+// A `#uniform <type> <name> [= <default>]` line from the metadata header
+#[derive(Clone, Debug, PartialEq)]
+pub struct UniformDecl {
+    pub ty: String,
+    pub name: String,
+    pub default: Option<UniformValue>,
+}
+
+pub struct StageSource {
+    pub stage: ShaderStage,
+    pub source: String,
+    // Line of the file the section body starts at (1-based)
+    pub first_line: usize,
+}
+
+// A .shader file: a metadata header followed by `#stage vertex`,
+// `#stage fragment` and optionally `#stage geometry` sections.
+//
+//     #uniform sampler2D image = 0
+//     #uniform vec4 spriteColor = 1.0 1.0 1.0 1.0
+//     #stage vertex
+//     #version 330 core
+//     ...
+//     #stage fragment
+//     ...
+pub struct ShaderFile {
+    pub uniforms: Vec<UniformDecl>,
+    pub stages: Vec<StageSource>,
+}
+
+impl ShaderFile {
+    pub fn load(path: &str) -> Result<ShaderFile, ShaderError> {
+        let mut contents = String::new();
+        File::open(asset_path(path))
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|error| ShaderError::Io { path: path.to_string(), error })?;
+        ShaderFile::parse(path, &contents)
+    }
+
+    pub fn parse(path: &str, contents: &str) -> Result<ShaderFile, ShaderError> {
+        let error = |line: usize, message: String| ShaderError::Parse {
+            path: path.to_string(), line, message
+        };
+
+        let mut uniforms = Vec::new();
+        let mut stages: Vec<StageSource> = Vec::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.starts_with("#stage") {
+                let name = trimmed["#stage".len()..].trim();
+                let stage = ShaderStage::from_name(name)
+                    .ok_or_else(|| error(i + 1, format!("unknown shader stage {}", name)))?;
+                if stages.iter().any(|s| s.stage == stage) {
+                    return Err(error(i + 1, format!("duplicate {} stage", name)));
+                }
+                stages.push(StageSource { stage, source: String::new(), first_line: i + 2 });
+                continue;
+            }
+
+            match stages.last_mut() {
+                Some(section) => {
+                    section.source.push_str(line);
+                    section.source.push('\n');
+                }
+                None => {
+                    if trimmed.starts_with("#uniform") {
+                        let decl = parse_uniform_decl(&trimmed["#uniform".len()..])
+                            .map_err(|message| error(i + 1, message))?;
+                        uniforms.push(decl);
+                    } else if !trimmed.is_empty() && !trimmed.starts_with("//") {
+                        return Err(error(i + 1, format!("unexpected line before first #stage: {}", trimmed)));
+                    }
+                }
+            }
+        }
+
+        for required in &[ShaderStage::Vertex, ShaderStage::Fragment] {
+            if !stages.iter().any(|s| s.stage == *required) {
+                return Err(error(1, format!("missing {} stage", required.name())));
+            }
+        }
+
+        Ok(ShaderFile { uniforms, stages })
+    }
+
+    pub fn stage(&self, stage: ShaderStage) -> Option<&StageSource> {
+        self.stages.iter().find(|s| s.stage == stage)
+    }
+}
+
+fn parse_uniform_decl(decl: &str) -> Result<UniformDecl, String> {
+    let mut split = decl.splitn(2, '=');
+    let mut signature = split.next().unwrap().split_whitespace();
+    let (ty, name) = match (signature.next(), signature.next(), signature.next()) {
+        (Some(ty), Some(name), None) => (ty.to_string(), name.to_string()),
+        _ => return Err(format!("expected #uniform <type> <name>, got #uniform{}", decl)),
+    };
+    let default = match split.next() {
+        Some(value) => Some(parse_uniform_value(&ty, value)?),
+        None => None,
+    };
+    Ok(UniformDecl { ty, name, default })
+}
+
+fn parse_uniform_value(ty: &str, value: &str) -> Result<UniformValue, String> {
+    let parts: Vec<&str> = value.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .collect();
+    let floats = || -> Result<Vec<f32>, String> {
+        parts.iter()
+            .map(|p| p.parse::<f32>().map_err(|_| format!("invalid number {} for {}", p, ty)))
+            .collect()
+    };
+    let expect_len = |values: Vec<f32>, len: usize| -> Result<Vec<f32>, String> {
+        if values.len() == len { Ok(values) } else { Err(format!("{} expects {} components", ty, len)) }
+    };
+
+    match ty {
+        "bool" => match parts.as_slice() {
+            ["true"] => Ok(UniformValue::Bool(true)),
+            ["false"] => Ok(UniformValue::Bool(false)),
+            _ => Err(format!("invalid bool {}", value.trim())),
+        },
+        "int" | "sampler2D" => match parts.as_slice() {
+            [v] => v.parse().map(UniformValue::Int).map_err(|_| format!("invalid int {}", v)),
+            _ => Err(format!("{} expects 1 component", ty)),
+        },
+        "float" => expect_len(floats()?, 1).map(|v| UniformValue::Float(v[0])),
+        "vec2" => expect_len(floats()?, 2).map(|v| UniformValue::Vec2(Vector2::new(v[0], v[1]))),
+        "vec3" => expect_len(floats()?, 3).map(|v| UniformValue::Vec3(Vector3::new(v[0], v[1], v[2]))),
+        "vec4" => expect_len(floats()?, 4).map(|v| UniformValue::Vec4(Vector4::new(v[0], v[1], v[2], v[3]))),
+        _ => Err(format!("default values are not supported for {} uniforms", ty)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shader_file::*;
+
+    #[test]
+    fn test_parse_shader_file() {
+        let contents = "// header\n\
+                        #uniform sampler2D image = 0\n\
+                        #uniform vec3 spriteColor = 1.0, 0.5, 0.0\n\
+                        #uniform mat4 projection\n\
+                        #stage vertex\n\
+                        void main() {}\n\
+                        #stage fragment\n\
+                        out vec4 color;\n\
+                        void main() {}\n";
+        let file = ShaderFile::parse("test.shader", contents).unwrap();
+
+        assert_eq!(file.uniforms.len(), 3);
+        assert_eq!(file.uniforms[0].default, Some(UniformValue::Int(0)));
+        assert_eq!(file.uniforms[1].default, Some(UniformValue::Vec3(Vector3::new(1.0, 0.5, 0.0))));
+        assert_eq!(file.uniforms[2].default, None);
+
+        let vertex = file.stage(ShaderStage::Vertex).unwrap();
+        assert_eq!(vertex.source, "void main() {}\n");
+        assert_eq!(vertex.first_line, 6);
+        let fragment = file.stage(ShaderStage::Fragment).unwrap();
+        assert_eq!(fragment.source, "out vec4 color;\nvoid main() {}\n");
+        assert_eq!(fragment.first_line, 8);
+        assert!(file.stage(ShaderStage::Geometry).is_none());
+    }
+
+    #[test]
+    fn test_parse_shader_file_errors() {
+        match ShaderFile::parse("a.shader", "#stage vertex\nvoid main() {}\n") {
+            Err(ShaderError::Parse { message, .. }) => assert_eq!(message, "missing fragment stage"),
+            _ => panic!("expected missing stage error"),
+        }
+        match ShaderFile::parse("a.shader", "#uniform vec2 offset = 1.0\n#stage vertex\n#stage fragment\n") {
+            Err(ShaderError::Parse { line, message, .. }) => {
+                assert_eq!(line, 1);
+                assert_eq!(message, "vec2 expects 2 components");
+            }
+            _ => panic!("expected invalid default error"),
+        }
+    }
+}
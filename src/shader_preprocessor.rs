@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use shader::ShaderError;
+use path::*;
+
+// Where a line of preprocessed source originally came from (1-based line)
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+pub struct PreprocessedSource {
+    pub source: String,
+    // One entry per line of `source`
+    line_map: Vec<SourceLocation>,
+}
+
+impl PreprocessedSource {
+    // Maps a 1-based line of the preprocessed source back to its origin
+    pub fn location(&self, line: usize) -> Option<&SourceLocation> {
+        if line == 0 { None } else { self.line_map.get(line - 1) }
+    }
+
+    // Rewrites the line references in a GL info log ("0:12(5)", "0(12)",
+    // "ERROR: 0:12:") so they point at the original file and line
+    pub fn remap_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|line| {
+                match find_log_line_number(line) {
+                    Some((start, end, number)) => match self.location(number) {
+                        Some(loc) => format!("{}{}:{}{}", &line[..start], loc.file, loc.line, &line[end..]),
+                        None => line.to_string(),
+                    },
+                    None => line.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The source lines a GL info log refers to, quoted with their original
+    // file and line. Every line but the injected defines is copied verbatim,
+    // so the text can be taken straight from the preprocessed source.
+    pub fn quote_log_lines(&self, log: &str) -> Vec<QuotedLine> {
+        let source_lines: Vec<&str> = self.source.lines().collect();
+        let mut quoted: Vec<QuotedLine> = Vec::new();
+        for line in log.lines() {
+            let number = match find_log_line_number(line) {
+                Some((_, _, number)) => number,
+                None => continue,
+            };
+            let (loc, text) = match (self.location(number), source_lines.get(number.wrapping_sub(1))) {
+                (Some(loc), Some(text)) => (loc, text),
+                _ => continue,
+            };
+            if !quoted.iter().any(|q| q.file == loc.file && q.line == loc.line) {
+                quoted.push(QuotedLine { file: loc.file.clone(), line: loc.line, text: text.to_string() });
+            }
+        }
+        quoted
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuotedLine {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+}
+
+// Finds the "<source>:<line>" or "<source>(<line>)" token in a log line,
+// returning its byte range and the line number
+fn find_log_line_number(line: &str) -> Option<(usize, usize, usize)> {
+    let start = if line.starts_with("ERROR: ") {
+        7
+    } else if line.starts_with("WARNING: ") {
+        9
+    } else {
+        0
+    };
+    let bytes = line.as_bytes();
+    let mut i = start;
+    while i < bytes.len() && bytes[i].is_ascii_digit() { i += 1; }
+    if i == start || i >= bytes.len() {
+        return None;
+    }
+    let (open, close) = match bytes[i] {
+        b':' => (i, None),
+        b'(' => (i, Some(b')')),
+        _ => return None,
+    };
+    let mut j = open + 1;
+    while j < bytes.len() && bytes[j].is_ascii_digit() { j += 1; }
+    if j == open + 1 {
+        return None;
+    }
+    let number = line[open + 1..j].parse().ok()?;
+    let end = match close {
+        Some(c) if j < bytes.len() && bytes[j] == c => j + 1,
+        Some(_) => return None,
+        None => j,
+    };
+    Some((start, end, number))
+}
+
+fn load_asset(path: &str) -> io::Result<String> {
+    let mut contents = String::new();
+    File::open(asset_path(path))?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+// Resolves #include "file" directives (relative to the asset root) and
+// injects #defines right after the #version line.
+pub struct Preprocessor<'a> {
+    defines: Vec<(String, String)>,
+    loader: Box<dyn Fn(&str) -> io::Result<String> + 'a>,
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new() -> Self {
+        Preprocessor::with_loader(load_asset)
+    }
+
+    // Use a custom file loader instead of reading from the assets folder
+    pub fn with_loader<F>(loader: F) -> Self where F: Fn(&str) -> io::Result<String> + 'a {
+        Preprocessor {
+            defines: Vec::new(),
+            loader: Box::new(loader),
+        }
+    }
+
+    // Accepts both "NAME" and "NAME=VALUE"
+    pub fn define(mut self, define: &str) -> Self {
+        let mut split = define.splitn(2, '=');
+        let name = split.next().unwrap().trim().to_string();
+        let value = split.next().map(|v| v.trim().to_string()).unwrap_or_default();
+        self.defines.push((name, value));
+        self
+    }
+
+    pub fn defines<S: AsRef<str>>(self, defines: &[S]) -> Self {
+        defines.iter().fold(self, |p, d| p.define(d.as_ref()))
+    }
+
+    pub fn process(&self, path: &str) -> Result<PreprocessedSource, ShaderError> {
+        let source = (self.loader)(path)
+            .map_err(|error| ShaderError::Io { path: path.to_string(), error })?;
+        self.process_source(path, &source)
+    }
+
+    // Preprocess source text that was already loaded, `path` is only used
+    // for error messages and the line map
+    pub fn process_source(&self, path: &str, source: &str) -> Result<PreprocessedSource, ShaderError> {
+        self.process_section(path, source, 0)
+    }
+
+    // Like process_source, for a section starting `line_offset` lines into
+    // the file (used for the stages of a .shader file)
+    pub fn process_section(&self, path: &str, source: &str, line_offset: usize) -> Result<PreprocessedSource, ShaderError> {
+        let mut output = PreprocessedSource {
+            source: String::new(),
+            line_map: Vec::new(),
+        };
+        let mut include_stack = vec![path.to_string()];
+        self.expand(path, source, line_offset, &mut include_stack, &mut output)?;
+
+        // Defines go right after the #version line (which may follow blank
+        // lines or comments), or first if there is none
+        if !self.defines.is_empty() {
+            let at = output.source.lines()
+                .position(|line| line.trim_start().starts_with("#version"))
+                .map_or(0, |i| i + 1);
+            self.inject_defines(at, &mut output);
+        }
+        Ok(output)
+    }
+
+    fn inject_defines(&self, at: usize, output: &mut PreprocessedSource) {
+        let mut lines: Vec<&str> = output.source.lines().collect();
+        let define_lines: Vec<String> = self.defines.iter()
+            .map(|&(ref name, ref value)| format!("#define {} {}", name, value).trim_end().to_string())
+            .collect();
+        for (i, define) in define_lines.iter().enumerate() {
+            lines.insert(at + i, define);
+            output.line_map.insert(at + i, SourceLocation { file: "<defines>".to_string(), line: i + 1 });
+        }
+        let mut source = lines.join("\n");
+        source.push('\n');
+        output.source = source;
+    }
+
+    fn expand(&self, path: &str, source: &str, line_offset: usize,
+              include_stack: &mut Vec<String>, output: &mut PreprocessedSource) -> Result<(), ShaderError> {
+        for (i, line) in source.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.starts_with("#include") {
+                let include = parse_include(&trimmed["#include".len()..])
+                    .ok_or_else(|| ShaderError::Parse {
+                        path: path.to_string(),
+                        line: line_offset + i + 1,
+                        message: format!("malformed include directive: {}", trimmed)
+                    })?;
+                if include_stack.iter().any(|p| p == &include) {
+                    return Err(ShaderError::Parse {
+                        path: path.to_string(),
+                        line: line_offset + i + 1,
+                        message: format!("recursive include of {}", include)
+                    });
+                }
+                let included = (self.loader)(&include)
+                    .map_err(|error| ShaderError::Io { path: include.clone(), error })?;
+                include_stack.push(include.clone());
+                self.expand(&include, &included, 0, include_stack, output)?;
+                include_stack.pop();
+                continue;
+            }
+
+            output.source.push_str(line);
+            output.source.push('\n');
+            output.line_map.push(SourceLocation { file: path.to_string(), line: line_offset + i + 1 });
+        }
+        Ok(())
+    }
+}
+
+fn parse_include(rest: &str) -> Option<String> {
+    let rest = rest.trim();
+    if rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"') {
+        Some(rest[1..rest.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shader_preprocessor::*;
+    use std::collections::HashMap;
+
+    fn loader(files: HashMap<&'static str, &'static str>) -> impl Fn(&str) -> io::Result<String> {
+        move |path: &str| files.get(path)
+            .map(|s| s.to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.to_string()))
+    }
+
+    #[test]
+    fn test_preprocess_include() {
+        let mut files = HashMap::new();
+        files.insert("main.frag", "#version 330 core\n#include \"common.glsl\"\nvoid main() {}\n");
+        files.insert("common.glsl", "float a;\nfloat b;\n");
+        let result = Preprocessor::with_loader(loader(files)).process("main.frag").unwrap();
+
+        assert_eq!(result.source, "#version 330 core\nfloat a;\nfloat b;\nvoid main() {}\n");
+        assert_eq!(result.location(3), Some(&SourceLocation { file: "common.glsl".to_string(), line: 2 }));
+        assert_eq!(result.location(4), Some(&SourceLocation { file: "main.frag".to_string(), line: 3 }));
+    }
+
+    #[test]
+    fn test_preprocess_defines() {
+        let mut files = HashMap::new();
+        files.insert("main.frag", "#version 330 core\nvoid main() {}\n");
+        let result = Preprocessor::with_loader(loader(files))
+            .define("ALPHA_TEST")
+            .define("MAX_LIGHTS=8")
+            .process("main.frag").unwrap();
+
+        assert_eq!(result.source, "#version 330 core\n#define ALPHA_TEST\n#define MAX_LIGHTS 8\nvoid main() {}\n");
+        assert_eq!(result.location(4), Some(&SourceLocation { file: "main.frag".to_string(), line: 2 }));
+    }
+
+    #[test]
+    fn test_preprocess_defines_after_preamble() {
+        let mut files = HashMap::new();
+        files.insert("blank.frag", "\n#version 330 core\nvoid main() {}\n");
+        files.insert("comment.frag", "// Lit sprites\n#version 330 core\nvoid main() {}\n");
+        let preprocessor = Preprocessor::with_loader(loader(files)).define("FOO");
+
+        let result = preprocessor.process("blank.frag").unwrap();
+        assert_eq!(result.source, "\n#version 330 core\n#define FOO\nvoid main() {}\n");
+        assert_eq!(result.location(4), Some(&SourceLocation { file: "blank.frag".to_string(), line: 3 }));
+
+        let result = preprocessor.process("comment.frag").unwrap();
+        assert_eq!(result.source, "// Lit sprites\n#version 330 core\n#define FOO\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn test_preprocess_recursive_include() {
+        let mut files = HashMap::new();
+        files.insert("a.glsl", "#include \"b.glsl\"\n");
+        files.insert("b.glsl", "#include \"a.glsl\"\n");
+        match Preprocessor::with_loader(loader(files)).process("a.glsl") {
+            Err(ShaderError::Parse { path, line, .. }) => {
+                assert_eq!(path, "b.glsl");
+                assert_eq!(line, 1);
+            }
+            _ => panic!("expected recursive include error"),
+        }
+    }
+
+    #[test]
+    fn test_remap_log() {
+        let mut files = HashMap::new();
+        files.insert("main.frag", "#version 330 core\n#include \"common.glsl\"\nvoid main() {}\n");
+        files.insert("common.glsl", "float a;\n");
+        let result = Preprocessor::with_loader(loader(files)).process("main.frag").unwrap();
+
+        assert_eq!(result.remap_log("0:3(5): error: syntax error"), "main.frag:3(5): error: syntax error");
+        assert_eq!(result.remap_log("0(2) : error C0000: oops"), "common.glsl:1 : error C0000: oops");
+        assert_eq!(result.remap_log("ERROR: 0:2: 'a' : redefinition"), "ERROR: common.glsl:1: 'a' : redefinition");
+        assert_eq!(result.remap_log("some other line"), "some other line");
+    }
+
+    #[test]
+    fn test_quote_log_lines() {
+        let mut files = HashMap::new();
+        files.insert("main.frag", "#version 330 core\n#include \"common.glsl\"\nvoid main() {}\n");
+        files.insert("common.glsl", "float a\n");
+        let result = Preprocessor::with_loader(loader(files))
+            .define("FOO")
+            .process("main.frag").unwrap();
+
+        let quoted = result.quote_log_lines("0:3(8): error: syntax error\n0:3(8): error: again\n0:4(1): warning");
+        assert_eq!(quoted, vec![
+            QuotedLine { file: "common.glsl".to_string(), line: 1, text: "float a".to_string() },
+            QuotedLine { file: "main.frag".to_string(), line: 3, text: "void main() {}".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,323 @@
+use cgmath::{Vector2, Vector4};
+use cgmath::prelude::*;
+
+use storage::ResourceID;
+use render_backend::{SpriteVertex, BlendMode};
+use sprite_renderer::{SpriteRenderer, DrawState};
+
+// Longest a miter joint may get, relative to half the line thickness
+const MITER_LIMIT: f32 = 4.0;
+// How far (in world units) a curve's segments may stray from the true curve
+const CURVE_TOLERANCE: f32 = 0.25;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ShapeParams {
+    pub color: Vector4<f32>,
+    // Width of outlines and polylines
+    pub thickness: f32,
+    pub blend: BlendMode,
+    // Same ordering as sprites (see SpriteParams)
+    pub layer: i32,
+    pub depth: f32,
+}
+
+impl ShapeParams {
+    pub fn new(color: Vector4<f32>) -> Self {
+        ShapeParams {
+            color,
+            thickness: 1.0,
+            blend: BlendMode::Alpha,
+            layer: 0,
+            depth: ::std::f32::NEG_INFINITY,
+        }
+    }
+
+    pub fn thickness(mut self, thickness: f32) -> Self {
+        self.thickness = thickness;
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn depth(mut self, depth: f32) -> Self {
+        self.depth = depth;
+        self
+    }
+}
+
+fn vertex(p: Vector2<f32>, color: [f32; 4]) -> SpriteVertex {
+    SpriteVertex { pos: [p.x, p.y], uv: [0.0, 0.0], color }
+}
+
+// Segments needed for an arc of `angle` radians to stay within `tolerance`
+// of the true curve, in the same units as the radius
+pub fn curve_segments(radius: f32, angle: f32, tolerance: f32) -> usize {
+    let radius = radius.abs();
+    if radius <= tolerance {
+        return 4;
+    }
+    let step = 2.0 * (1.0 - tolerance / radius).acos();
+    ((angle.abs() / step).ceil() as usize).max(4).min(256)
+}
+
+// Points along an arc, angles in degrees clockwise from +x (y points down)
+pub fn arc_points(center: Vector2<f32>, radius: f32, start: f32, end: f32, tolerance: f32) -> Vec<Vector2<f32>> {
+    let (start, end) = (start.to_radians(), end.to_radians());
+    let segments = curve_segments(radius, end - start, tolerance);
+    (0..segments + 1).map(|i| {
+        let angle = start + (end - start) * i as f32 / segments as f32;
+        center + Vector2::new(angle.cos(), angle.sin()) * radius
+    }).collect()
+}
+
+// Points around a full circle, without repeating the first one
+pub fn circle_points(center: Vector2<f32>, radius: f32, tolerance: f32) -> Vec<Vector2<f32>> {
+    let mut points = arc_points(center, radius, 0.0, 360.0, tolerance);
+    points.pop();
+    points
+}
+
+pub fn rounded_rect_points(min: Vector2<f32>, max: Vector2<f32>, radius: f32) -> Vec<Vector2<f32>> {
+    let radius = radius.max(0.0).min(0.5 * (max.x - min.x)).min(0.5 * (max.y - min.y));
+    if radius == 0.0 {
+        return vec![min, Vector2::new(max.x, min.y), max, Vector2::new(min.x, max.y)];
+    }
+    let mut points = Vec::new();
+    points.extend(arc_points(Vector2::new(max.x - radius, min.y + radius), radius, 270.0, 360.0, CURVE_TOLERANCE));
+    points.extend(arc_points(Vector2::new(max.x - radius, max.y - radius), radius, 0.0, 90.0, CURVE_TOLERANCE));
+    points.extend(arc_points(Vector2::new(min.x + radius, max.y - radius), radius, 90.0, 180.0, CURVE_TOLERANCE));
+    points.extend(arc_points(Vector2::new(min.x + radius, min.y + radius), radius, 180.0, 270.0, CURVE_TOLERANCE));
+    points
+}
+
+// Triangle fan over a convex polygon
+pub fn tessellate_convex(out: &mut Vec<SpriteVertex>, points: &[Vector2<f32>], color: Vector4<f32>) {
+    let color = [color.x, color.y, color.z, color.w];
+    for i in 1..points.len().saturating_sub(1) {
+        out.push(vertex(points[0], color));
+        out.push(vertex(points[i], color));
+        out.push(vertex(points[i + 1], color));
+    }
+}
+
+// A line of the given thickness through the points, with mitered joints
+// (and butt ends when not closed)
+pub fn tessellate_polyline(out: &mut Vec<SpriteVertex>, points: &[Vector2<f32>], closed: bool,
+                           thickness: f32, color: Vector4<f32>) {
+    // Repeated points have no direction to offset along
+    let mut path: Vec<Vector2<f32>> = Vec::with_capacity(points.len());
+    for &p in points {
+        if path.last().map_or(true, |&last: &Vector2<f32>| (p - last).magnitude2() > 1e-12) {
+            path.push(p);
+        }
+    }
+    if closed && path.len() > 2 && (path[0] - path[path.len() - 1]).magnitude2() <= 1e-12 {
+        path.pop();
+    }
+    if path.len() < 2 {
+        return;
+    }
+
+    let n = path.len();
+    let half = 0.5 * thickness;
+    let normal = |a: Vector2<f32>, b: Vector2<f32>| {
+        let d = (b - a).normalize();
+        Vector2::new(-d.y, d.x)
+    };
+    // Offset of the line's edges from each point
+    let offsets: Vec<Vector2<f32>> = (0..n).map(|i| {
+        let prev = if i > 0 { Some(path[i - 1]) } else if closed { Some(path[n - 1]) } else { None };
+        let next = if i + 1 < n { Some(path[i + 1]) } else if closed { Some(path[0]) } else { None };
+        match (prev, next) {
+            (Some(prev), Some(next)) => {
+                let (n0, n1) = (normal(prev, path[i]), normal(path[i], next));
+                let sum = n0 + n1;
+                if sum.magnitude2() < 1e-6 {
+                    // The line doubles back on itself
+                    return n0 * half;
+                }
+                let miter = sum.normalize();
+                let length = (half / miter.dot(n0)).min(MITER_LIMIT * half);
+                miter * length
+            }
+            (None, Some(next)) => normal(path[i], next) * half,
+            (Some(prev), None) => normal(prev, path[i]) * half,
+            (None, None) => Vector2::new(0.0, 0.0),
+        }
+    }).collect();
+
+    let color = [color.x, color.y, color.z, color.w];
+    let segments = if closed { n } else { n - 1 };
+    for i in 0..segments {
+        let j = (i + 1) % n;
+        let quad = [path[i] - offsets[i], path[j] - offsets[j], path[j] + offsets[j], path[i] + offsets[i]];
+        for &k in &[0, 1, 2, 0, 2, 3] {
+            out.push(vertex(quad[k], color));
+        }
+    }
+}
+
+// Untextured shapes drawn through a SpriteRenderer, so they share its
+// camera and are sorted and batched together with sprites
+pub struct ShapeRenderer<'r, 'a: 'r> {
+    renderer: &'r mut SpriteRenderer<'a>,
+    vertices: Vec<SpriteVertex>,
+}
+
+impl<'r, 'a> ShapeRenderer<'r, 'a> {
+    pub fn new(renderer: &'r mut SpriteRenderer<'a>) -> Self {
+        ShapeRenderer { renderer, vertices: Vec::new() }
+    }
+
+    fn submit(&mut self, params: &ShapeParams) {
+        let state = DrawState {
+            shader: self.renderer.sprite_shader(),
+            blend: params.blend,
+            texture: ResourceID::null(),
+            layer: params.layer,
+            depth: params.depth,
+        };
+        self.renderer.submit_triangles(state, &self.vertices);
+        self.vertices.clear();
+    }
+
+    fn fill(&mut self, points: &[Vector2<f32>], params: &ShapeParams) {
+        tessellate_convex(&mut self.vertices, points, params.color);
+        self.submit(params);
+    }
+
+    fn stroke(&mut self, points: &[Vector2<f32>], closed: bool, params: &ShapeParams) {
+        tessellate_polyline(&mut self.vertices, points, closed, params.thickness, params.color);
+        self.submit(params);
+    }
+
+    pub fn fill_rect(&mut self, min: Vector2<f32>, max: Vector2<f32>, params: &ShapeParams) {
+        self.fill(&rounded_rect_points(min, max, 0.0), params);
+    }
+
+    pub fn stroke_rect(&mut self, min: Vector2<f32>, max: Vector2<f32>, params: &ShapeParams) {
+        self.stroke(&rounded_rect_points(min, max, 0.0), true, params);
+    }
+
+    pub fn fill_rounded_rect(&mut self, min: Vector2<f32>, max: Vector2<f32>, radius: f32, params: &ShapeParams) {
+        self.fill(&rounded_rect_points(min, max, radius), params);
+    }
+
+    pub fn stroke_rounded_rect(&mut self, min: Vector2<f32>, max: Vector2<f32>, radius: f32, params: &ShapeParams) {
+        self.stroke(&rounded_rect_points(min, max, radius), true, params);
+    }
+
+    pub fn fill_circle(&mut self, center: Vector2<f32>, radius: f32, params: &ShapeParams) {
+        self.fill(&circle_points(center, radius, CURVE_TOLERANCE), params);
+    }
+
+    pub fn stroke_circle(&mut self, center: Vector2<f32>, radius: f32, params: &ShapeParams) {
+        self.stroke(&circle_points(center, radius, CURVE_TOLERANCE), true, params);
+    }
+
+    // Pie slice, angles in degrees clockwise from +x
+    pub fn fill_arc(&mut self, center: Vector2<f32>, radius: f32, start: f32, end: f32, params: &ShapeParams) {
+        let mut points = vec![center];
+        points.extend(arc_points(center, radius, start, end, CURVE_TOLERANCE));
+        self.fill(&points, params);
+    }
+
+    pub fn stroke_arc(&mut self, center: Vector2<f32>, radius: f32, start: f32, end: f32, params: &ShapeParams) {
+        self.stroke(&arc_points(center, radius, start, end, CURVE_TOLERANCE), false, params);
+    }
+
+    pub fn polyline(&mut self, points: &[Vector2<f32>], closed: bool, params: &ShapeParams) {
+        self.stroke(points, closed, params);
+    }
+
+    // The points must form a convex polygon, in either winding order
+    pub fn fill_convex_polygon(&mut self, points: &[Vector2<f32>], params: &ShapeParams) {
+        self.fill(points, params);
+    }
+
+    pub fn stroke_polygon(&mut self, points: &[Vector2<f32>], params: &ShapeParams) {
+        self.stroke(points, true, params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shape_renderer::*;
+    use sprite_renderer::{test_renderer, test_shaders};
+    use std::f32::consts::PI;
+    use storage::Storage;
+    use capture::{capture_screen, assert_golden};
+
+    #[test]
+    fn test_tessellate_polyline_miter() {
+        let mut out = Vec::new();
+        let points = [Vector2::new(0.0, 0.0), Vector2::new(10.0, 0.0), Vector2::new(10.0, 10.0)];
+        tessellate_polyline(&mut out, &points, false, 2.0, Vector4::new(1.0, 1.0, 1.0, 1.0));
+        assert_eq!(out.len(), 2 * 6);
+
+        // The outer corner of the right angle is mitered out to (11, -1)
+        let has = |x: f32, y: f32| out.iter().any(|v| (v.pos[0] - x).abs() < 1e-4 && (v.pos[1] - y).abs() < 1e-4);
+        assert!(has(11.0, -1.0));
+        assert!(has(9.0, 1.0));
+        // Butt ends
+        assert!(has(0.0, -1.0) && has(0.0, 1.0));
+
+        out.clear();
+        tessellate_polyline(&mut out, &points, true, 2.0, Vector4::new(1.0, 1.0, 1.0, 1.0));
+        assert_eq!(out.len(), 3 * 6);
+    }
+
+    #[test]
+    fn test_curve_segments() {
+        assert!(curve_segments(1000.0, 2.0 * PI, 0.25) > curve_segments(10.0, 2.0 * PI, 0.25));
+        assert!(curve_segments(10.0, 2.0 * PI, 0.1) > curve_segments(10.0, 2.0 * PI, 0.25));
+        assert_eq!(curve_segments(0.0, 2.0 * PI, 0.25), 4);
+        assert_eq!(arc_points(Vector2::new(0.0, 0.0), 10.0, 0.0, 90.0, 0.25).len(), curve_segments(10.0, 0.5 * PI, 0.25) + 1);
+        // Rounded corners collapse to a plain rectangle
+        assert_eq!(rounded_rect_points(Vector2::new(0.0, 0.0), Vector2::new(4.0, 4.0), 0.0).len(), 4);
+    }
+
+    #[test]
+    fn test_golden_shapes() {
+        let (textures, sprites) = (Storage::new(1), Storage::new(1));
+        let shaders = test_shaders();
+        let mut renderer = test_renderer(160, 120, &shaders, &textures, &sprites);
+        renderer.backend_mut().clear(Vector4::new(0.1, 0.1, 0.1, 1.0));
+
+        {
+            let mut shapes = ShapeRenderer::new(&mut renderer);
+            let red = ShapeParams::new(Vector4::new(1.0, 0.2, 0.2, 1.0));
+            let white = ShapeParams::new(Vector4::new(1.0, 1.0, 1.0, 1.0)).thickness(3.0);
+
+            // Drawn over the filled panel despite being submitted first
+            shapes.stroke_rounded_rect(Vector2::new(10.0, 10.0), Vector2::new(70.0, 40.0), 8.0, &white.layer(1));
+            shapes.fill_rounded_rect(Vector2::new(10.0, 10.0), Vector2::new(70.0, 40.0), 8.0,
+                                     &ShapeParams::new(Vector4::new(0.2, 0.3, 0.8, 1.0)));
+            shapes.fill_rect(Vector2::new(15.0, 30.0), Vector2::new(45.0, 35.0), &red.layer(2));
+
+            shapes.fill_circle(Vector2::new(110.0, 25.0), 15.0, &red);
+            shapes.stroke_circle(Vector2::new(110.0, 25.0), 15.0, &white.thickness(1.0));
+            shapes.fill_arc(Vector2::new(30.0, 85.0), 20.0, -90.0, 135.0, &ShapeParams::new(Vector4::new(0.2, 0.8, 0.2, 0.6)));
+            shapes.stroke_arc(Vector2::new(30.0, 85.0), 24.0, -90.0, 135.0, &white.thickness(2.0));
+
+            shapes.polyline(&[Vector2::new(60.0, 110.0), Vector2::new(80.0, 70.0),
+                              Vector2::new(100.0, 100.0), Vector2::new(110.0, 60.0)], false, &white.thickness(4.0));
+            shapes.fill_convex_polygon(&[Vector2::new(130.0, 70.0), Vector2::new(150.0, 90.0),
+                                         Vector2::new(140.0, 110.0), Vector2::new(120.0, 100.0)], &red);
+            shapes.stroke_rect(Vector2::new(120.5, 50.5), Vector2::new(150.5, 60.5), &white.thickness(1.0));
+        }
+        renderer.end_frame();
+        // Shapes all share shader, blend and texture, so the sorted queue is a single batch
+        assert_eq!(renderer.stats().batches, 1);
+
+        assert_golden(&capture_screen(renderer.backend_mut(), 160, 120), "shapes", 2);
+    }
+}
@@ -1,5 +1,6 @@
 use serde::ser::{Serialize, Serializer};
 use serde::de::{Deserialize, Deserializer};
+use cgmath::Vector2;
 use storage::ResourceID;
 use texture::Texture;
 
@@ -13,6 +14,20 @@ impl SpriteBounds {
     pub fn new(x: u32, y: u32, w: u32, h: u32, ox: u32, oy: u32) -> Self {
         SpriteBounds { x, y, w, h, ox, oy }
     }
+
+    // Top left of the rect in the texture, in pixels
+    pub fn position(&self) -> Vector2<f32> {
+        Vector2::new(self.x as f32, self.y as f32)
+    }
+
+    pub fn size(&self) -> Vector2<f32> {
+        Vector2::new(self.w as f32, self.h as f32)
+    }
+
+    // Pivot (ox, oy) in pixels relative to the top left of the rect
+    pub fn origin(&self) -> Vector2<f32> {
+        Vector2::new(self.ox as f32, self.oy as f32)
+    }
 }
 
 impl Serialize for SpriteBounds {
@@ -32,17 +47,65 @@ impl<'de> Deserialize<'de> for SpriteBounds {
     }
 }
 
+// Border widths in pixels (left, top, right, bottom) that keep their size
+// when a sprite is drawn as a nine-slice, see nine_slice.rs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NineSlice {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl NineSlice {
+    pub fn new(left: u32, top: u32, right: u32, bottom: u32) -> Self {
+        NineSlice { left, top, right, bottom }
+    }
+
+    pub fn uniform(inset: u32) -> Self {
+        NineSlice::new(inset, inset, inset, inset)
+    }
+}
+
+impl Serialize for NineSlice {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        (self.left, self.top, self.right, self.bottom).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NineSlice {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        Deserialize::deserialize(deserializer)
+            .map(|(left, top, right, bottom)| NineSlice { left, top, right, bottom })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SpriteData {
     pub name: String,
     pub texture: ResourceID<Texture>,
-    pub rect: SpriteBounds
+    pub rect: SpriteBounds,
+    // Tangent-space normals laid out like `texture`, used for lighting.
+    // Every sprite on the same texture should share it.
+    #[serde(default)]
+    pub normal_map: ResourceID<Texture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nine_slice: Option<NineSlice>,
 }
 
 impl SpriteData {
     pub fn new(name: String, texture: ResourceID<Texture>, rect: SpriteBounds) -> Self {
-        SpriteData { name, texture, rect }
+        SpriteData { name, texture, rect, normal_map: ResourceID::null(), nine_slice: None }
+    }
+    pub fn nine_slice(mut self, insets: NineSlice) -> Self {
+        self.nine_slice = Some(insets);
+        self
     }
+
     pub fn get_uvs(&self, tex_w: u32, tex_h: u32) -> [f32; 4] {
         let x1 = self.rect.x as f32 / tex_w as f32;
         let x2 = (self.rect.x + self.rect.w) as f32 / tex_w as f32;
@@ -1,62 +1,268 @@
 use std::mem;
-use std::os::raw::c_void;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 
 use storage::{Storage, ResourceID};
 use shader::Shader;
-use texture::Texture;
-use sprite::{SpriteBounds, SpriteData};
+use texture::{Texture, TextureBuilder};
+use sprite::SpriteData;
+use camera::Camera2D;
+use ecs::World;
+use transform::{self, Transform2D};
+use render_backend::{RenderBackend, GlBackend, BufferId, BufferKind, Fragment};
+pub use render_backend::{SpriteVertex, BlendMode};
 
 use gl;
-use gl::types::*;
-use cgmath;
-use cgmath::{Vector2, Vector3, Matrix4, One};
+use cgmath::{Vector2, Vector4, Matrix3, Matrix4, One};
+use stb_image::image::Image;
+
+// Number of vertices the streaming vertex buffer holds (a quad takes 6),
+// larger batches are split into several draw calls
+pub const MAX_BATCH_VERTICES: usize = 6 * 8192;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Flip {
+    pub horizontal: bool,
+    pub vertical: bool,
+}
+
+impl Flip {
+    pub const NONE: Flip = Flip { horizontal: false, vertical: false };
+    pub const HORIZONTAL: Flip = Flip { horizontal: true, vertical: false };
+    pub const VERTICAL: Flip = Flip { horizontal: false, vertical: true };
+    pub const BOTH: Flip = Flip { horizontal: true, vertical: true };
+}
+
+// How to place a sprite: `pos` is where the sprite's pivot ends up, and
+// scaling, rotation (in degrees) and flipping all happen about the pivot.
+#[derive(Copy, Clone, Debug)]
+pub struct SpriteParams {
+    pub shader: Option<ResourceID<Shader>>,
+    pub pos: Vector2<f32>,
+    pub scale: Vector2<f32>,
+    pub rotate: f32,
+    // RGBA tint multiplied with the texture
+    pub color: Vector4<f32>,
+    pub flip: Flip,
+    pub blend: BlendMode,
+    // Sprites on higher layers are drawn on top, regardless of submission order
+    pub layer: i32,
+    // Within a layer, draw sprites with a larger pivot y on top
+    // (for top-down games where things lower on screen are closer)
+    pub y_sort: bool,
+    // Applied after everything above, e.g. an entity's world matrix
+    pub transform: Option<Matrix3<f32>>,
+}
+
+impl SpriteParams {
+    pub fn new(pos: Vector2<f32>) -> Self {
+        SpriteParams {
+            shader: None,
+            pos,
+            scale: Vector2::new(1.0, 1.0),
+            rotate: 0.0,
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            flip: Flip::NONE,
+            blend: BlendMode::Alpha,
+            layer: 0,
+            y_sort: false,
+            transform: None,
+        }
+    }
+
+    pub fn shader(mut self, shader: ResourceID<Shader>) -> Self {
+        self.shader = Some(shader);
+        self
+    }
+
+    pub fn scale(mut self, scale: Vector2<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn rotate(mut self, rotate: f32) -> Self {
+        self.rotate = rotate;
+        self
+    }
+
+    pub fn color(mut self, color: Vector4<f32>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn flip(mut self, flip: Flip) -> Self {
+        self.flip = flip;
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn y_sort(mut self, y_sort: bool) -> Self {
+        self.y_sort = y_sort;
+        self
+    }
+
+    pub fn transform(mut self, transform: Matrix3<f32>) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    // Where the pivot ends up after the transform
+    pub fn world_pos(&self) -> Vector2<f32> {
+        match self.transform {
+            Some(ref matrix) => transform::transform_point(matrix, self.pos),
+            None => self.pos,
+        }
+    }
+}
+
+// A sprite component, drawn at the entity's world transform by
+// SpriteRenderer::submit_world. `params.pos` is an offset from the entity.
+#[derive(Copy, Clone, Debug)]
+pub struct Sprite {
+    pub sprite: ResourceID<SpriteData>,
+    pub params: SpriteParams,
+}
+
+impl Sprite {
+    pub fn new(sprite: ResourceID<SpriteData>) -> Self {
+        Sprite { sprite, params: SpriteParams::new(Vector2::new(0.0, 0.0)) }
+    }
+
+    pub fn params(mut self, params: SpriteParams) -> Self {
+        self.params = params;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub batches: u32,
+    pub sprites: u32,
+}
+
+// How queued geometry is drawn. Geometry is drawn in order of `layer`,
+// then `depth` (the y coordinate for y-sorted sprites), then grouped into
+// batches of the same shader, blend mode and texture.
+#[derive(Copy, Clone, Debug)]
+pub struct DrawState {
+    pub shader: ResourceID<Shader>,
+    pub blend: BlendMode,
+    // A null texture draws untextured (white) geometry
+    pub texture: ResourceID<Texture>,
+    pub layer: i32,
+    pub depth: f32,
+}
+
+// A queued quad, corners in clockwise order
+#[derive(Copy, Clone, Debug)]
+pub struct Quad {
+    pub shader: ResourceID<Shader>,
+    pub blend: BlendMode,
+    pub texture: ResourceID<Texture>,
+    pub layer: i32,
+    pub depth: f32,
+    pub vertices: [SpriteVertex; 4],
+}
+
+// Triangles [first, first + count) of the queued vertices
+#[derive(Copy, Clone, Debug)]
+struct DrawItem {
+    state: DrawState,
+    first: usize,
+    count: usize,
+}
+
+fn quad_triangles(v: &[SpriteVertex; 4]) -> [SpriteVertex; 6] {
+    [v[0], v[1], v[2], v[0], v[2], v[3]]
+}
+
+// sprite.shader on the SoftwareBackend: texture * vertex colour
+pub fn software_sprite_shader(fragment: &Fragment) -> [f32; 4] {
+    let [u, v] = fragment.uv;
+    let texel = fragment.sample(u, v);
+    let mut color = [0.0; 4];
+    for i in 0..4 {
+        color[i] = texel[i] * fragment.color[i];
+    }
+    color
+}
+
+// Upload a triangle list into the streaming buffer and draw it, splitting
+// into several draw calls if it doesn't fit
+fn draw_chunks(backend: &mut dyn RenderBackend, vertex_buffer: BufferId,
+               texture: &Texture, blend: BlendMode, vertices: &[SpriteVertex], stats: &mut RenderStats) {
+    for chunk in vertices.chunks(MAX_BATCH_VERTICES) {
+        backend.upload_vertices(vertex_buffer, chunk);
+        backend.draw(vertex_buffer, 0, chunk.len(), texture, blend);
+        stats.draw_calls += 1;
+    }
+}
 
 pub struct SpriteRenderer<'a> {
     shaders: &'a Storage<Shader>,
     textures: &'a Storage<Texture>,
     sprites: &'a Storage<SpriteData>,
     sprite_shader: ResourceID<Shader>,
+    view_projection: Matrix4<f32>,
+
+    backend: Box<dyn RenderBackend + 'a>,
+    // Everything is drawn as plain triangle lists
+    vertex_buffer: BufferId,
+    // For untextured geometry (shapes, debug drawing)
+    white_texture: Texture,
+    // Normal pass: draw normal maps instead of colours, see set_normal_pass
+    normal_pass: bool,
+    normal_maps: HashMap<ResourceID<Texture>, ResourceID<Texture>>,
+    // Pointing straight out of the screen, for anything without a normal map
+    flat_normal_texture: Texture,
 
-    vao: GLuint,
-    vbo: GLuint,
+    queue: Vec<DrawItem>,
+    queue_vertices: Vec<SpriteVertex>,
+    frame_stats: RenderStats,
+    last_frame_stats: RenderStats,
 }
 
 impl<'a> SpriteRenderer<'a> {
     pub fn new(shaders: &'a Storage<Shader>,
                textures: &'a Storage<Texture>,
                sprites: &'a Storage<SpriteData>) -> Self {
-        let mut vao = 0;
-        let mut vbo = 0;
-
-        let vertices : [f32; 24] = [
-            0.0, 0.0, 0.0, 0.0,
-            1.0, 0.0, 1.0, 0.0,
-            1.0, 1.0, 1.0, 1.0,
-
-            0.0, 0.0, 0.0, 0.0,
-            1.0, 1.0, 1.0, 1.0,
-            0.0, 1.0, 0.0, 1.0
-        ];
-
-        unsafe {
-            gl::GenVertexArrays(1, &mut vao);
-            gl::GenBuffers(1, &mut vbo);
-
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(gl::ARRAY_BUFFER,
-                           mem::size_of::<[f32; 24]>() as GLsizeiptr,
-                           vertices.as_ptr() as *const c_void,
-                           gl::DYNAMIC_DRAW);
-
-            gl::BindVertexArray(vao);
-            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 4 * mem::size_of::<f32>() as GLint, 0 as *mut c_void);
-            gl::EnableVertexAttribArray(0);
-            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, 4 * mem::size_of::<f32>() as GLint, (2 * mem::size_of::<f32>()) as *mut c_void);
-            gl::EnableVertexAttribArray(1);
-
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-            gl::BindVertexArray(0);
-        }
+        SpriteRenderer::with_backend(Box::new(GlBackend::new()), shaders, textures, sprites)
+    }
+
+    pub fn with_backend(mut backend: Box<dyn RenderBackend + 'a>,
+                        shaders: &'a Storage<Shader>,
+                        textures: &'a Storage<Texture>,
+                        sprites: &'a Storage<SpriteData>) -> Self {
+        let vertex_buffer = backend.create_buffer(BufferKind::Vertex);
+
+        let white_texture = TextureBuilder::new()
+            .image(Image { width: 1, height: 1, depth: 4, data: vec![255; 4] })
+            .internal_format(gl::RGBA)
+            .image_format(gl::RGBA)
+            .build_with(&mut *backend);
+
+        let flat_normal_texture = TextureBuilder::new()
+            .image(Image { width: 1, height: 1, depth: 4, data: vec![128, 128, 255, 255] })
+            .internal_format(gl::RGBA)
+            .image_format(gl::RGBA)
+            .build_with(&mut *backend);
+
+        let mut normal_maps = HashMap::new();
+        sprites.iterate(|sprite| {
+            if !sprite.normal_map.is_null() {
+                normal_maps.insert(sprite.texture, sprite.normal_map);
+            }
+        });
 
         let (_, sprite_shader) = shaders.get_by_name("sprite.shader").unwrap();
 
@@ -65,70 +271,442 @@ impl<'a> SpriteRenderer<'a> {
             textures,
             sprites,
             sprite_shader,
-            vao, vbo
+            view_projection: Matrix4::one(),
+            backend,
+            vertex_buffer,
+            white_texture,
+            normal_pass: false,
+            normal_maps,
+            flat_normal_texture,
+            queue: Vec::new(),
+            queue_vertices: Vec::new(),
+            frame_stats: RenderStats::default(),
+            last_frame_stats: RenderStats::default(),
         }
     }
 
-    pub fn draw_sprite_with_shader(&self,
-                                   shader_id: ResourceID<Shader>,
-                                   sprite_id: ResourceID<SpriteData>,
-                                   pos: Vector2<f32>, scale: Vector2<f32>, rotate: f32,
-                                   color: Vector3<f32>) {
-        let shader = self.shaders.get(shader_id);
+    pub fn backend(&self) -> &dyn RenderBackend {
+        &*self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut dyn RenderBackend {
+        &mut *self.backend
+    }
+
+    // Use the camera's view-projection for everything drawn from now on.
+    // Flush first if queued sprites should still use the previous camera.
+    pub fn set_camera(&mut self, camera: &Camera2D) {
+        self.view_projection = camera.view_projection();
+    }
+
+    // Vertices of a sprite quad, transformed on the CPU so quads with
+    // different transforms can share a draw call
+    fn build_quad(&self, sprite_id: ResourceID<SpriteData>, params: &SpriteParams)
+        -> (ResourceID<Texture>, [SpriteVertex; 4]) {
         let sprite = self.sprites.get(sprite_id);
         let texture = self.textures.get(sprite.texture);
-        let size = Vector2::new(scale.x * texture.width as f32, scale.y * texture.height as f32);
-
-        // change uv coordinate buffer before drawing sprite
+        let size = sprite.rect.size();
+        let origin = sprite.rect.origin();
         let uvs = sprite.get_uvs(texture.width as u32, texture.height as u32);
-        let vertices: [f32; 24] = [
-            0.0, 0.0, uvs[0], uvs[2],
-            1.0, 0.0, uvs[1], uvs[2],
-            1.0, 1.0, uvs[1], uvs[3],
-            0.0, 0.0, uvs[0], uvs[2],
-            1.0, 1.0, uvs[1], uvs[3],
-            0.0, 1.0, uvs[0], uvs[3]
-        ];
-
-        let mut model = Matrix4::<f32>::one();
-        model = model * Matrix4::from_translation(Vector3::new(pos.x, pos.y, 0.0));
-
-        model = model * Matrix4::from_translation(Vector3::new(0.5 * size.x, 0.5 * size.y, 0.0));
-        model = model * Matrix4::from_angle_z(cgmath::Deg(rotate));
-        model = model * Matrix4::from_translation(Vector3::new(-0.5 * size.x, -0.5 * size.y, 0.0));
-
-        model = model * Matrix4::from_nonuniform_scale(size.x, size.y, 1.0);
-
-        shader.set_mat4("model", model);
-        shader.set_vec3("spriteColor", color);
-
-        unsafe {
-            gl::ActiveTexture(gl::TEXTURE0);
-            texture.bind();
-
-            gl::BindVertexArray(self.vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-            gl::BufferSubData(gl::ARRAY_BUFFER,
-                              0 as GLintptr,
-                              24 * mem::size_of::<f32>() as isize,
-                              vertices.as_ptr() as *const c_void);
-            gl::DrawArrays(gl::TRIANGLES, 0, 6);
-            gl::BindVertexArray(0);
+
+        let flip_x = if params.flip.horizontal { -1.0 } else { 1.0 };
+        let flip_y = if params.flip.vertical { -1.0 } else { 1.0 };
+        let (sin, cos) = params.rotate.to_radians().sin_cos();
+
+        // Premultiplied textures need a premultiplied tint too
+        let color = params.color;
+        let color = match params.blend {
+            BlendMode::Premultiplied => [color.x * color.w, color.y * color.w, color.z * color.w, color.w],
+            _ => [color.x, color.y, color.z, color.w],
+        };
+        let corners = [(0.0, 0.0, uvs[0], uvs[2]),
+                       (size.x, 0.0, uvs[1], uvs[2]),
+                       (size.x, size.y, uvs[1], uvs[3]),
+                       (0.0, size.y, uvs[0], uvs[3])];
+        let mut vertices = [SpriteVertex { pos: [0.0; 2], uv: [0.0; 2], color }; 4];
+        for (vertex, &(x, y, u, v)) in vertices.iter_mut().zip(corners.iter()) {
+            let lx = (x - origin.x) * params.scale.x * flip_x;
+            let ly = (y - origin.y) * params.scale.y * flip_y;
+            let pos = Vector2::new(params.pos.x + lx * cos - ly * sin,
+                                   params.pos.y + lx * sin + ly * cos);
+            let pos = match params.transform {
+                Some(ref matrix) => transform::transform_point(matrix, pos),
+                None => pos,
+            };
+            vertex.pos = [pos.x, pos.y];
+            vertex.uv = [u, v];
         }
+        (sprite.texture, vertices)
+    }
+
+    fn bind_shader(&mut self, shader_id: ResourceID<Shader>) {
+        let shader = self.shaders.get(shader_id);
+        self.backend.bind_shader(shader, self.view_projection);
+    }
+
+    fn draw_vertices(&mut self, texture_id: ResourceID<Texture>, blend: BlendMode, vertices: &[SpriteVertex]) {
+        let texture = if !texture_id.is_null() {
+            self.textures.get(texture_id)
+        } else if self.normal_pass {
+            &self.flat_normal_texture
+        } else {
+            &self.white_texture
+        };
+        draw_chunks(&mut *self.backend, self.vertex_buffer, texture, blend, vertices, &mut self.frame_stats);
+    }
+
+    // Draw a triangle list right away with the sprite shader, untextured
+    // (white) if no texture is given. Doesn't flush the queue first.
+    pub fn draw_triangles(&mut self, texture: Option<&Texture>, blend: BlendMode, vertices: &[SpriteVertex]) {
+        if vertices.is_empty() {
+            return;
+        }
+        let shader = self.sprite_shader;
+        self.bind_shader(shader);
+
+        let texture = texture.unwrap_or(&self.white_texture);
+        draw_chunks(&mut *self.backend, self.vertex_buffer, texture, blend, vertices, &mut self.frame_stats);
+        self.frame_stats.batches += 1;
+    }
+
+    // Draws immediately, prefer submit_sprite when drawing many sprites
+    pub fn draw_sprite_params(&mut self, sprite_id: ResourceID<SpriteData>, params: &SpriteParams) {
+        let (texture, vertices) = self.build_quad(sprite_id, params);
+        let shader = params.shader.unwrap_or(self.sprite_shader);
+        self.bind_shader(shader);
+        self.draw_vertices(texture, params.blend, &quad_triangles(&vertices));
+        self.frame_stats.sprites += 1;
+    }
+
+    pub fn draw_sprite_with_shader(&mut self,
+                                   shader_id: ResourceID<Shader>,
+                                   sprite_id: ResourceID<SpriteData>,
+                                   pos: Vector2<f32>, scale: Vector2<f32>, rotate: f32,
+                                   color: Vector4<f32>) {
+        let params = SpriteParams::new(pos).shader(shader_id).scale(scale).rotate(rotate).color(color);
+        self.draw_sprite_params(sprite_id, &params);
     }
 
     // Draw sprite with default shader
-    pub fn draw_sprite(&self,
+    pub fn draw_sprite(&mut self,
                        sprite_id: ResourceID<SpriteData>,
                        pos: Vector2<f32>, scale: Vector2<f32>, rotate: f32,
-                       color: Vector3<f32>) {
-
-        self.draw_sprite_with_shader(self.sprite_shader, sprite_id, pos, scale, rotate, color);
+                       color: Vector4<f32>) {
+        let shader = self.sprite_shader;
+        self.draw_sprite_with_shader(shader, sprite_id, pos, scale, rotate, color);
     }
 
-    pub fn draw_sprite_simple(&self,
+    pub fn draw_sprite_simple(&mut self,
                               sprite_id: ResourceID<SpriteData>,
                               pos: Vector2<f32>, scale: Vector2<f32>) {
-        self.draw_sprite(sprite_id, pos, scale, 0.0, Vector3::new(0.0, 0.0, 0.0));
+        self.draw_sprite(sprite_id, pos, scale, 0.0, Vector4::new(1.0, 1.0, 1.0, 1.0));
+    }
+
+    // Queue a sprite for the next flush
+    pub fn submit_sprite_params(&mut self, sprite_id: ResourceID<SpriteData>, params: &SpriteParams) {
+        let (texture, vertices) = self.build_quad(sprite_id, params);
+        self.submit_quad(Quad {
+            shader: params.shader.unwrap_or(self.sprite_shader),
+            blend: params.blend,
+            texture,
+            layer: params.layer,
+            depth: if params.y_sort { params.world_pos().y } else { ::std::f32::NEG_INFINITY },
+            vertices
+        });
+    }
+
+    // Queue every entity with a Sprite and a Transform2D
+    pub fn submit_world(&mut self, world: &mut World) {
+        let entities: Vec<_> = world.query::<(&Sprite, &Transform2D)>().map(|(entity, _)| entity).collect();
+        for entity in entities {
+            let matrix = transform::world_matrix(world, entity);
+            let sprite = *world.get::<Sprite>(entity).unwrap();
+            self.submit_sprite_params(sprite.sprite, &sprite.params.transform(matrix));
+        }
+    }
+
+    // Queue pre-built vertices, e.g. tiles of a Canvas layer
+    pub fn submit_quad(&mut self, quad: Quad) {
+        let state = DrawState {
+            shader: quad.shader,
+            blend: quad.blend,
+            texture: quad.texture,
+            layer: quad.layer,
+            depth: quad.depth,
+        };
+        self.submit_triangles(state, &quad_triangles(&quad.vertices));
+        self.frame_stats.sprites += 1;
+    }
+
+    // Queue a triangle list, sorted and batched together with sprites
+    pub fn submit_triangles(&mut self, mut state: DrawState, vertices: &[SpriteVertex]) {
+        if vertices.is_empty() {
+            return;
+        }
+        let first = self.queue_vertices.len();
+        self.queue_vertices.extend_from_slice(vertices);
+        if self.normal_pass {
+            state.texture = self.normal_maps.get(&state.texture).cloned().unwrap_or_else(ResourceID::null);
+            // Tints don't apply to normals, but keep the alpha
+            for vertex in &mut self.queue_vertices[first..] {
+                vertex.color = [1.0, 1.0, 1.0, vertex.color[3]];
+            }
+        }
+        self.queue.push(DrawItem { state, first, count: vertices.len() });
+    }
+
+    // While enabled, queued sprites and tiles draw their texture's normal
+    // map (see SpriteData::normal_map) untinted, or flat normals if it has
+    // none, e.g. into the normal buffer of Lighting. Flushes first.
+    pub fn set_normal_pass(&mut self, enabled: bool) {
+        self.flush();
+        self.normal_pass = enabled;
+    }
+
+    pub fn normal_pass(&self) -> bool {
+        self.normal_pass
+    }
+
+    pub fn sprite_shader(&self) -> ResourceID<Shader> {
+        self.sprite_shader
+    }
+
+    pub fn textures(&self) -> &'a Storage<Texture> {
+        self.textures
+    }
+
+    pub fn sprites(&self) -> &'a Storage<SpriteData> {
+        self.sprites
+    }
+
+    pub fn submit_sprite_with_shader(&mut self,
+                                     shader_id: ResourceID<Shader>,
+                                     sprite_id: ResourceID<SpriteData>,
+                                     pos: Vector2<f32>, scale: Vector2<f32>, rotate: f32,
+                                     color: Vector4<f32>) {
+        let params = SpriteParams::new(pos).shader(shader_id).scale(scale).rotate(rotate).color(color);
+        self.submit_sprite_params(sprite_id, &params);
+    }
+
+    pub fn submit_sprite(&mut self,
+                         sprite_id: ResourceID<SpriteData>,
+                         pos: Vector2<f32>, scale: Vector2<f32>, rotate: f32,
+                         color: Vector4<f32>) {
+        let shader = self.sprite_shader;
+        self.submit_sprite_with_shader(shader, sprite_id, pos, scale, rotate, color);
+    }
+
+    // Draw everything queued sorted by layer and depth, merging consecutive
+    // items with the same shader/blend/texture into one batch. The sort is
+    // stable, so items with equal keys keep their submission order.
+    pub fn flush(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+        let mut queue = mem::replace(&mut self.queue, Vec::new());
+        let mut queue_vertices = mem::replace(&mut self.queue_vertices, Vec::new());
+        queue.sort_by(|a, b| {
+            let (a, b) = (&a.state, &b.state);
+            a.layer.cmp(&b.layer)
+                .then(a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal))
+                .then((a.shader.index(), a.blend, a.texture.index())
+                    .cmp(&(b.shader.index(), b.blend, b.texture.index())))
+        });
+
+        let mut vertices = Vec::with_capacity(queue_vertices.len());
+        let mut start = 0;
+        while start < queue.len() {
+            let DrawState { shader, blend, texture, .. } = queue[start].state;
+            let end = start + queue[start..].iter()
+                .take_while(|item| item.state.shader == shader && item.state.blend == blend && item.state.texture == texture)
+                .count();
+
+            vertices.clear();
+            for item in &queue[start..end] {
+                vertices.extend_from_slice(&queue_vertices[item.first..item.first + item.count]);
+            }
+            self.bind_shader(shader);
+            self.draw_vertices(texture, blend, &vertices);
+            self.frame_stats.batches += 1;
+
+            start = end;
+        }
+
+        // Reuse the allocations for the next frame
+        queue.clear();
+        queue_vertices.clear();
+        self.queue = queue;
+        self.queue_vertices = queue_vertices;
+    }
+
+    // Flush and start collecting statistics for a new frame
+    pub fn end_frame(&mut self) {
+        self.flush();
+        self.last_frame_stats = mem::replace(&mut self.frame_stats, RenderStats::default());
+    }
+
+    // Statistics of the last finished frame
+    pub fn stats(&self) -> RenderStats {
+        self.last_frame_stats
+    }
+}
+
+impl<'a> Drop for SpriteRenderer<'a> {
+    fn drop(&mut self) {
+        self.backend.delete_buffer(self.vertex_buffer);
+    }
+}
+
+// A 1x1 RGBA texture, for tests
+#[cfg(test)]
+pub fn solid_texture(color: [u8; 4]) -> Texture {
+    use render_backend::SoftwareBackend;
+    TextureBuilder::new()
+        .image(Image { width: 1, height: 1, depth: 4, data: color.to_vec() })
+        .image_format(gl::RGBA)
+        .build_with(&mut SoftwareBackend::new(1, 1))
+}
+
+// Just the sprite shader, runnable on the SoftwareBackend, for tests
+#[cfg(test)]
+pub fn test_shaders() -> Storage<Shader> {
+    let mut shader = Shader::new(String::new(), String::new());
+    shader.set_software(software_sprite_shader);
+    let mut shaders = Storage::new(1);
+    shaders.insert("sprite.shader", shader);
+    shaders
+}
+
+// A renderer drawing into a width x height SoftwareBackend with the shaders
+// from test_shaders, and a camera showing exactly those pixels, for tests
+#[cfg(test)]
+pub fn test_renderer<'a>(width: u32, height: u32, shaders: &'a Storage<Shader>, textures: &'a Storage<Texture>,
+                         sprites: &'a Storage<SpriteData>) -> SpriteRenderer<'a> {
+    use render_backend::SoftwareBackend;
+    let mut renderer = SpriteRenderer::with_backend(Box::new(SoftwareBackend::new(width, height)),
+                                                    shaders, textures, sprites);
+    renderer.set_camera(&Camera2D::new(width as f32, height as f32));
+    renderer
+}
+
+#[cfg(test)]
+mod tests {
+    use sprite_renderer::*;
+    use sprite::SpriteBounds;
+
+    #[test]
+    fn test_submit_sorts_by_layer() {
+        let mut textures = Storage::new(4);
+        let red = textures.insert("red", solid_texture([255, 0, 0, 255]));
+        let blue = textures.insert("blue", solid_texture([0, 0, 255, 255]));
+        let mut sprites = Storage::new(4);
+        let red = sprites.insert("red", SpriteData::new("red".to_string(), red, SpriteBounds::new(0, 0, 4, 4, 0, 0)));
+        let blue = sprites.insert("blue", SpriteData::new("blue".to_string(), blue, SpriteBounds::new(0, 0, 4, 4, 0, 0)));
+
+        let shaders = test_shaders();
+
+        let mut renderer = test_renderer(8, 8, &shaders, &textures, &sprites);
+        renderer.backend_mut().clear(Vector4::new(0.0, 0.0, 0.0, 1.0));
+
+        // Submitted first but on the higher layer, so drawn on top
+        renderer.submit_sprite_params(blue, &SpriteParams::new(Vector2::new(2.0, 2.0)).layer(1));
+        renderer.submit_sprite_params(red, &SpriteParams::new(Vector2::new(0.0, 0.0)));
+        renderer.end_frame();
+
+        let image = renderer.backend_mut().read_pixels(0, 0, 8, 8);
+        let pixel = |x: usize, y: usize| &image.data[4 * (y * 8 + x)..4 * (y * 8 + x) + 4];
+        assert_eq!(pixel(1, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(3, 3), [0, 0, 255, 255]);
+        assert_eq!(pixel(7, 7), [0, 0, 0, 255]);
+
+        assert_eq!(renderer.stats(), RenderStats { draw_calls: 2, batches: 2, sprites: 2 });
+    }
+
+    #[test]
+    fn test_normal_pass() {
+        let mut textures = Storage::new(4);
+        let red = textures.insert("red", solid_texture([255, 0, 0, 255]));
+        let bumpy = textures.insert("bumpy", solid_texture([200, 100, 220, 255]));
+        let blue = textures.insert("blue", solid_texture([0, 0, 255, 255]));
+        let mut sprites = Storage::new(4);
+        let mut red_data = SpriteData::new("red".to_string(), red, SpriteBounds::new(0, 0, 4, 4, 0, 0));
+        red_data.normal_map = bumpy;
+        let red = sprites.insert("red", red_data);
+        let blue = sprites.insert("blue", SpriteData::new("blue".to_string(), blue, SpriteBounds::new(0, 0, 4, 4, 0, 0)));
+
+        let shaders = test_shaders();
+
+        let mut renderer = test_renderer(8, 4, &shaders, &textures, &sprites);
+        renderer.set_normal_pass(true);
+        let tint = Vector4::new(0.5, 0.5, 0.5, 1.0);
+        renderer.submit_sprite_params(red, &SpriteParams::new(Vector2::new(0.0, 0.0)).color(tint));
+        renderer.submit_sprite_params(blue, &SpriteParams::new(Vector2::new(4.0, 0.0)));
+        renderer.set_normal_pass(false);
+        assert!(!renderer.normal_pass());
+
+        let image = renderer.backend_mut().read_pixels(0, 0, 8, 4);
+        let pixel = |x: usize, y: usize| &image.data[4 * (y * 8 + x)..4 * (y * 8 + x) + 4];
+        assert_eq!(pixel(1, 1), [200, 100, 220, 255]);
+        assert_eq!(pixel(5, 1), [128, 128, 255, 255]);
+    }
+
+    #[test]
+    fn test_premultiplied_tint() {
+        let mut textures = Storage::new(4);
+        let white = textures.insert("white", solid_texture([255, 255, 255, 255]));
+        let mut sprites = Storage::new(4);
+        let white = sprites.insert("white", SpriteData::new("white".to_string(), white, SpriteBounds::new(0, 0, 4, 4, 0, 0)));
+
+        let shaders = test_shaders();
+
+        let mut renderer = test_renderer(4, 4, &shaders, &textures, &sprites);
+        renderer.backend_mut().clear(Vector4::new(0.0, 0.0, 0.0, 1.0));
+        // Half transparent red over black, the same as with straight alpha
+        let tint = Vector4::new(1.0, 0.0, 0.0, 0.5);
+        renderer.submit_sprite_params(white, &SpriteParams::new(Vector2::new(0.0, 0.0))
+                                      .color(tint).blend(BlendMode::Premultiplied));
+        renderer.end_frame();
+
+        let image = renderer.backend_mut().read_pixels(0, 0, 4, 4);
+        assert_eq!(&image.data[0..4], [128, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_submit_world() {
+        use transform::Parent;
+
+        let mut textures = Storage::new(4);
+        let red = textures.insert("red", solid_texture([255, 0, 0, 255]));
+        let blue = textures.insert("blue", solid_texture([0, 0, 255, 255]));
+        let mut sprites = Storage::new(4);
+        let red = sprites.insert("red", SpriteData::new("red".to_string(), red, SpriteBounds::new(0, 0, 2, 2, 0, 0)));
+        let blue = sprites.insert("blue", SpriteData::new("blue".to_string(), blue, SpriteBounds::new(0, 0, 2, 2, 0, 0)));
+
+        // A body with a hand 4 pixels to its right
+        let mut world = World::new();
+        let body = world.spawn((Transform2D::new(Vector2::new(1.0, 1.0)), Sprite::new(red)));
+        world.spawn((Transform2D::new(Vector2::new(4.0, 0.0)), Parent(body), Sprite::new(blue)));
+        // Moving and turning the body carries the hand along
+        {
+            let transform = world.get_mut::<Transform2D>(body).unwrap();
+            transform.translate(Vector2::new(0.0, 1.0));
+            transform.set_rotation(90.0);
+        }
+
+        let shaders = test_shaders();
+
+        let mut renderer = test_renderer(8, 8, &shaders, &textures, &sprites);
+        renderer.backend_mut().clear(Vector4::new(0.0, 0.0, 0.0, 1.0));
+        renderer.submit_world(&mut world);
+        renderer.end_frame();
+
+        // Rotated 90 degrees about (1, 2), the body covers (-1, 2) - (1, 4)
+        // and the hand (-1, 6) - (1, 8)
+        let image = renderer.backend_mut().read_pixels(0, 0, 8, 8);
+        let pixel = |x: usize, y: usize| &image.data[4 * (y * 8 + x)..4 * (y * 8 + x) + 4];
+        assert_eq!(pixel(0, 3), [255, 0, 0, 255]);
+        assert_eq!(pixel(0, 7), [0, 0, 255, 255]);
+        assert_eq!(pixel(5, 3), [0, 0, 0, 255]);
+        assert_eq!(renderer.stats().sprites, 2);
     }
 }
@@ -11,8 +11,8 @@ pub trait Resource {
     fn tid() -> u16;
 }
 
-#[derive(Derivative, PartialEq, Debug)]
-#[derivative(Copy(bound=""), Clone(bound=""))]
+#[derive(Derivative)]
+#[derivative(Copy(bound=""), Clone(bound=""), PartialEq(bound=""), Eq(bound=""), Hash(bound=""), Debug(bound=""))]
 #[repr(C)]
 pub struct ResourceID<T: Resource> {
     index: u32,
@@ -35,6 +35,10 @@ impl<T> ResourceID<T> where T: Resource {
     pub fn is_null(&self) -> bool {
         self.index == u32::max_value()
     }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
 }
 
 impl<T> Default for ResourceID<T> where T: Resource {
@@ -165,6 +169,18 @@ impl<T> Storage<T> where T: Resource {
         node.item.is_some() && node.generation == item_ref.generation
     }
     
+    // Id of the item currently at `index`, if there is one
+    pub fn id_at(&self, index: u32) -> Option<ResourceID<T>> {
+        self.nodes.get(index as usize).and_then(|node| {
+            node.item.as_ref().map(|_| ResourceID {
+                index,
+                generation: node.generation,
+                tid: T::tid(),
+                phantom: PhantomData
+            })
+        })
+    }
+
     pub fn get(&self, item_ref: ResourceID<T>) -> &T {
         let node = &self.nodes[item_ref.index as usize];
         assert!(node.item.is_some());
@@ -179,6 +195,13 @@ impl<T> Storage<T> where T: Resource {
         node.item.as_mut().unwrap()
     }
 
+    pub fn get_name(&self, item_ref: ResourceID<T>) -> &str {
+        let node = &self.nodes[item_ref.index as usize];
+        assert!(node.item.is_some());
+        assert_eq!(node.generation, item_ref.generation);
+        &node.name
+    }
+
     pub fn get_by_name(&self, name: &str) -> Option<(&T, ResourceID<T>)> {
         self.name_mappings.get(name).map(|index| {
             let node = &self.nodes[*index as usize];
@@ -235,7 +258,7 @@ impl<T> Storage<T> where T: Resource {
         self.name_mappings.remove(name);
     }
 
-    pub fn iterate<F>(&self, fun: F) where F : Fn(&T) -> () {
+    pub fn iterate<F>(&self, mut fun: F) where F : FnMut(&T) -> () {
         for i in 0..self.capacity() {
             let node = &self.nodes[i as usize];
             if node.item.is_some() {
@@ -244,7 +267,7 @@ impl<T> Storage<T> where T: Resource {
         }
     }
 
-    pub fn iterate_mut<F>(&mut self, fun: F) where F : Fn(&mut T) -> () {
+    pub fn iterate_mut<F>(&mut self, mut fun: F) where F : FnMut(&mut T) -> () {
         for i in 0..self.capacity() {
             let node = &mut self.nodes[i as usize];
             if node.item.is_some() {
@@ -316,6 +339,23 @@ mod tests {
         assert!(storage.has(alice_ref));
     }
 
+    #[test]
+    fn test_storage_id_at() {
+        let mut storage = Storage::new(8);
+
+        let alice_ref = storage.insert("alice", (1, 3.0, "Alice".to_string()));
+        assert_eq!(storage.id_at(alice_ref.index()), Some(alice_ref));
+
+        // Reusing the slot gives a new generation
+        storage.release(alice_ref);
+        assert_eq!(storage.id_at(alice_ref.index()), None);
+        let bob_ref = storage.insert("bob", (2, 4.0, "Bob".to_string()));
+        assert_eq!(bob_ref.index(), alice_ref.index());
+        assert!(!storage.has(alice_ref));
+        assert_eq!(storage.id_at(bob_ref.index()), Some(bob_ref));
+        assert_eq!(storage.id_at(100), None);
+    }
+
     #[test]
     fn test_storage_size() {
         let mut storage = Storage::new(8);
@@ -2,11 +2,13 @@ use gl;
 use gl::types::*;
 use stb_image::image::Image;
 use std::os::raw::c_void;
+use std::ptr;
 
 use stb_image::image;
 use serde::{Serialize, Deserialize};
 
 use path::*;
+use render_backend::{RenderBackend, GlBackend};
 
 #[derive(Serialize, Deserialize)]
 pub struct Texture {
@@ -75,6 +77,25 @@ impl TextureBuilder {
         self
     }
 
+    // Allocate an empty texture of the given size (used for render targets)
+    pub fn size(mut self, width: GLint, height: GLint) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn wrap(mut self, wrap_s: GLuint, wrap_t: GLuint) -> Self {
+        self.wrap_s = wrap_s as GLint;
+        self.wrap_t = wrap_t as GLint;
+        self
+    }
+
+    pub fn filter(mut self, filter_min: GLuint, filter_max: GLuint) -> Self {
+        self.filter_min = filter_min as GLint;
+        self.filter_max = filter_max as GLint;
+        self
+    }
+
     pub fn internal_format(mut self, internal_format: GLuint) -> Self {
         self.internal_format = internal_format as GLint;
         self
@@ -85,41 +106,81 @@ impl TextureBuilder {
         self
     }
 
-    pub fn build(mut self) -> Texture {
-        let mut id = 0;
-
-        unsafe {
-            gl::GenTextures(1, &mut id);
-            gl::BindTexture(gl::TEXTURE_2D, id);
-
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, self.wrap_s);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, self.wrap_s);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, self.filter_min);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, self.filter_max);
-
-            gl::TexImage2D(gl::TEXTURE_2D, 0, self.internal_format, self.width, self.height, 0, self.image_format, gl::UNSIGNED_BYTE, self.data.as_mut_ptr() as *mut c_void);
-        }
+    pub fn build(self) -> Texture {
+        self.build_with(&mut GlBackend::new())
+    }
 
-        Texture {
-            id: id,
+    pub fn build_with(self, backend: &mut dyn RenderBackend) -> Texture {
+        let mut texture = Texture {
+            id: 0,
             width: self.width, height: self.height,
             internal_format: self.internal_format, image_format: self.image_format,
             wrap_s: self.wrap_s, wrap_t: self.wrap_t,
             filter_min: self.filter_min, filter_max: self.filter_max,
             path: self.path,
             data: self.data
-        }
+        };
+        backend.upload_texture(&mut texture);
+        texture
     }
 }
 
 impl Texture {
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
     pub fn bind(&self) {
         unsafe {
             gl::BindTexture(gl::TEXTURE_2D, self.id);
         }
     }
 
+    // Backends without GL objects hand out their own ids
+    pub fn set_id(&mut self, id: GLuint) {
+        self.id = id;
+    }
+
+    // Change the size without touching GL, discarding the CPU data
+    pub fn set_size(&mut self, width: GLint, height: GLint) {
+        self.width = width;
+        self.height = height;
+        self.data.clear();
+    }
+
+    // Reallocate the texture storage with a new size, discarding its
+    // contents, used by GlBackend
+    pub fn resize(&mut self, width: GLint, height: GLint) {
+        self.set_size(width, height);
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, self.internal_format, self.width, self.height, 0, self.image_format, gl::UNSIGNED_BYTE, ptr::null());
+        }
+    }
+
+    // Create the GL texture object from `data` (empty data allocates
+    // uninitialized storage), used by GlBackend
+    pub fn upload(&mut self) {
+        unsafe {
+            gl::GenTextures(1, &mut self.id);
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, self.wrap_s);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, self.wrap_t);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, self.filter_min);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, self.filter_max);
+
+            let pixels = if self.data.is_empty() { ptr::null() } else { self.data.as_ptr() as *const c_void };
+            gl::TexImage2D(gl::TEXTURE_2D, 0, self.internal_format, self.width, self.height, 0, self.image_format, gl::UNSIGNED_BYTE, pixels);
+        }
+    }
+
     pub fn load(&mut self) {
+        self.load_with(&mut GlBackend::new());
+    }
+
+    pub fn load_with(&mut self, backend: &mut dyn RenderBackend) {
         let image = match image::load(&asset_path(&self.path)) {
             image::LoadResult::ImageU8(image) => image,
             image::LoadResult::ImageF32(_) => { panic!("Image loaded as f32"); }
@@ -130,16 +191,44 @@ impl Texture {
         self.width = image.width as GLint;
         self.height = image.height as GLint;
 
-        unsafe {
-            gl::GenTextures(1, &mut self.id);
-            gl::BindTexture(gl::TEXTURE_2D, self.id);
+        backend.upload_texture(self);
+    }
 
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, self.wrap_s);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, self.wrap_s);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, self.filter_min);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, self.filter_max);
+    pub fn channels(&self) -> usize {
+        match self.image_format {
+            gl::RED => 1,
+            gl::RG => 2,
+            gl::RGB => 3,
+            _ => 4,
+        }
+    }
 
-            gl::TexImage2D(gl::TEXTURE_2D, 0, self.internal_format, self.width, self.height, 0, self.image_format, gl::UNSIGNED_BYTE, self.data.as_mut_ptr() as *mut c_void);
+    // CPU-side nearest-neighbour lookup in the pixel data, as RGBA in 0..1.
+    // Textures without CPU data (e.g. render targets) sample as white.
+    pub fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        if self.data.is_empty() || self.width <= 0 || self.height <= 0 {
+            return [1.0; 4];
+        }
+        let wrap = |coord: f32, size: GLint, mode: GLint| -> usize {
+            let texel = (coord * size as f32).floor() as i64;
+            let texel = if mode == gl::REPEAT as GLint {
+                ((texel % size as i64) + size as i64) % size as i64
+            } else {
+                texel.max(0).min(size as i64 - 1)
+            };
+            texel as usize
+        };
+        let x = wrap(u, self.width, self.wrap_s);
+        let y = wrap(v, self.height, self.wrap_t);
+        let channels = self.channels();
+        let offset = (y * self.width as usize + x) * channels;
+        let texel = &self.data[offset..offset + channels];
+        let c = |i: usize| texel[i] as f32 / 255.0;
+        match channels {
+            1 => [c(0), c(0), c(0), 1.0],
+            2 => [c(0), c(0), c(0), c(1)],
+            3 => [c(0), c(1), c(2), 1.0],
+            _ => [c(0), c(1), c(2), c(3)],
         }
     }
 }
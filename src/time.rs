@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+
+use cgmath::{Vector2, Vector4};
+
+use debug_draw::{self, DebugDraw};
+
+// Frame times kept for the histogram, two seconds at 60fps
+pub const HISTORY_LENGTH: usize = 120;
+
+// Frame timing, advanced once per frame by the App runner
+pub struct Time {
+    // Multiplies delta: 0 pauses, values below 1 give slow motion
+    pub time_scale: f32,
+    // How quickly the FPS follows changes in frame time, between 0 and 1
+    pub fps_smoothing: f32,
+    delta: f32,
+    unscaled_delta: f32,
+    elapsed: f64,
+    unscaled_elapsed: f64,
+    frame_count: u64,
+    average_frame_time: f32,
+    // Unscaled, oldest first
+    history: VecDeque<f32>,
+}
+
+impl Time {
+    pub fn new() -> Self {
+        Time {
+            time_scale: 1.0,
+            fps_smoothing: 0.05,
+            delta: 0.0,
+            unscaled_delta: 0.0,
+            elapsed: 0.0,
+            unscaled_elapsed: 0.0,
+            frame_count: 0,
+            average_frame_time: 0.0,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    // Start a new frame that took `frame_time` real seconds
+    pub fn advance(&mut self, frame_time: f32) {
+        self.unscaled_delta = frame_time;
+        self.delta = frame_time * self.time_scale.max(0.0);
+        self.elapsed += self.delta as f64;
+        self.unscaled_elapsed += frame_time as f64;
+
+        self.average_frame_time = if self.frame_count == 0 {
+            frame_time
+        } else {
+            self.average_frame_time + (frame_time - self.average_frame_time) * self.fps_smoothing
+        };
+        self.frame_count += 1;
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(frame_time);
+    }
+
+    // Seconds since the last frame, scaled by time_scale
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    // Real seconds since the last frame, for things that shouldn't slow down
+    // with the game like UI animations
+    pub fn unscaled_delta(&self) -> f32 {
+        self.unscaled_delta
+    }
+
+    // Scaled seconds since the start
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    pub fn unscaled_elapsed(&self) -> f64 {
+        self.unscaled_elapsed
+    }
+
+    // Frames started so far, including the current one
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn paused(&self) -> bool {
+        self.time_scale <= 0.0
+    }
+
+    // Frames per second over roughly the last 1 / fps_smoothing frames
+    pub fn fps(&self) -> f32 {
+        if self.average_frame_time > 0.0 { 1.0 / self.average_frame_time } else { 0.0 }
+    }
+
+    // Real frame times of the last HISTORY_LENGTH frames, oldest first
+    pub fn frame_times(&self) -> &VecDeque<f32> {
+        &self.history
+    }
+
+    pub fn max_frame_time(&self) -> f32 {
+        self.history.iter().fold(0.0, |max, &t| max.max(t))
+    }
+
+    // Bar graph of the recent frame times in the rectangle starting at `min`
+    // (world units, like everything in DebugDraw). The top of the graph is
+    // 1/30s, with a line at 1/60s.
+    pub fn draw_histogram(&self, draw: &mut DebugDraw, min: Vector2<f32>, size: Vector2<f32>) {
+        let top = 1.0 / 30.0;
+        let bottom = min.y + size.y;
+        let bar_width = size.x / HISTORY_LENGTH as f32;
+
+        draw.rect(min, min + size, debug_draw::WHITE);
+        let target_y = bottom - size.y * (1.0 / 60.0) / top;
+        draw.line(Vector2::new(min.x, target_y), Vector2::new(min.x + size.x, target_y),
+                  Vector4::new(1.0, 1.0, 1.0, 0.5));
+
+        // Newest on the right
+        let offset = HISTORY_LENGTH - self.history.len();
+        for (i, &frame_time) in self.history.iter().enumerate() {
+            let color = if frame_time <= 1.0 / 55.0 {
+                debug_draw::GREEN
+            } else if frame_time <= 1.0 / 28.0 {
+                debug_draw::YELLOW
+            } else {
+                debug_draw::RED
+            };
+            let x = min.x + (offset + i) as f32 * bar_width + 0.5 * bar_width;
+            let height = size.y * (frame_time / top).min(1.0);
+            draw.line(Vector2::new(x, bottom), Vector2::new(x, bottom - height), color);
+        }
+
+        let label = format!("{:.1} fps  {:.1} ms (max {:.1})", self.fps(), 1000.0 * self.average_frame_time,
+                            1000.0 * self.max_frame_time());
+        draw.text(Vector2::new(min.x, bottom + 4.0), &label, debug_draw::WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::*;
+
+    #[test]
+    fn test_time_scale() {
+        let mut time = Time::new();
+        time.advance(0.1);
+        time.time_scale = 0.5;
+        time.advance(0.1);
+        assert!((time.delta() - 0.05).abs() < 1e-6);
+        assert!((time.unscaled_delta() - 0.1).abs() < 1e-6);
+        assert!((time.elapsed() - 0.15).abs() < 1e-6);
+        assert!((time.unscaled_elapsed() - 0.2).abs() < 1e-6);
+
+        time.time_scale = 0.0;
+        time.advance(0.1);
+        assert!(time.paused());
+        assert_eq!(time.delta(), 0.0);
+        assert_eq!(time.frame_count(), 3);
+    }
+
+    #[test]
+    fn test_fps_and_history() {
+        let mut time = Time::new();
+        for _ in 0..HISTORY_LENGTH {
+            time.advance(1.0 / 60.0);
+        }
+        assert!((time.fps() - 60.0).abs() < 1e-2);
+
+        // One slow frame only nudges the smoothed value, but shows up in
+        // the history, which stays the same length
+        time.advance(0.1);
+        assert!(time.fps() > 40.0 && time.fps() < 60.0);
+        assert_eq!(time.frame_times().len(), HISTORY_LENGTH);
+        assert_eq!(*time.frame_times().back().unwrap(), 0.1);
+        assert_eq!(time.max_frame_time(), 0.1);
+
+        let mut draw = DebugDraw::new();
+        time.draw_histogram(&mut draw, Vector2::new(0.0, 0.0), Vector2::new(120.0, 40.0));
+        // Frame, target line, a bar per frame and the label
+        assert_eq!(draw.shape_count(), 2 + HISTORY_LENGTH + 1);
+    }
+}
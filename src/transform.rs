@@ -0,0 +1,234 @@
+use cgmath::{Vector2, Vector3, Matrix3, SquareMatrix};
+
+use ecs::{World, Entity, Commands};
+
+// Deeper hierarchies than this are assumed to be cycles
+const MAX_DEPTH: u32 = 256;
+
+// Position, rotation (in degrees) and scale of an entity relative to its
+// Parent, or to the world if it has none. Scaling and rotation happen about
+// the position, like in SpriteParams.
+#[derive(Clone, Debug)]
+pub struct Transform2D {
+    position: Vector2<f32>,
+    rotation: f32,
+    scale: Vector2<f32>,
+    // Set when the local transform changes
+    dirty: bool,
+    // Cached result of world_matrix()
+    world: Matrix3<f32>,
+    // Bumped whenever `world` is recomputed, so children know to follow
+    version: u32,
+    // The parent and its version that `world` was computed from
+    computed_from: Option<(Entity, u32)>,
+}
+
+impl Transform2D {
+    pub fn new(position: Vector2<f32>) -> Self {
+        Transform2D {
+            position,
+            rotation: 0.0,
+            scale: Vector2::new(1.0, 1.0),
+            dirty: true,
+            world: Matrix3::identity(),
+            version: 0,
+            computed_from: None,
+        }
+    }
+
+    pub fn rotate(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn scale(mut self, scale: Vector2<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn position(&self) -> Vector2<f32> {
+        self.position
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    pub fn scaling(&self) -> Vector2<f32> {
+        self.scale
+    }
+
+    pub fn set_position(&mut self, position: Vector2<f32>) {
+        self.position = position;
+        self.dirty = true;
+    }
+
+    pub fn translate(&mut self, offset: Vector2<f32>) {
+        self.position += offset;
+        self.dirty = true;
+    }
+
+    pub fn set_rotation(&mut self, rotation: f32) {
+        self.rotation = rotation;
+        self.dirty = true;
+    }
+
+    pub fn set_scale(&mut self, scale: Vector2<f32>) {
+        self.scale = scale;
+        self.dirty = true;
+    }
+
+    pub fn local_matrix(&self) -> Matrix3<f32> {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        Matrix3::new(cos * self.scale.x, sin * self.scale.x, 0.0,
+                     -sin * self.scale.y, cos * self.scale.y, 0.0,
+                     self.position.x, self.position.y, 1.0)
+    }
+
+    // The world matrix as of the last world_matrix() or update_transforms()
+    // call, which may be out of date
+    pub fn cached_world_matrix(&self) -> Matrix3<f32> {
+        self.world
+    }
+}
+
+// Makes an entity's Transform2D relative to another entity's. A parent
+// without a Transform2D (or that was despawned) counts as no parent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Parent(pub Entity);
+
+pub fn transform_point(matrix: &Matrix3<f32>, point: Vector2<f32>) -> Vector2<f32> {
+    let p = matrix * Vector3::new(point.x, point.y, 1.0);
+    Vector2::new(p.x, p.y)
+}
+
+// Rotation in degrees of a world matrix
+pub fn matrix_rotation(matrix: &Matrix3<f32>) -> f32 {
+    matrix.x.y.atan2(matrix.x.x).to_degrees()
+}
+
+// Attach `child` to `parent`, or detach it with None. Panics if that would
+// make the child its own ancestor.
+pub fn set_parent(world: &mut World, child: Entity, parent: Option<Entity>) {
+    match parent {
+        Some(parent) => {
+            let mut ancestor = Some(parent);
+            while let Some(entity) = ancestor {
+                assert!(entity != child, "Parenting would create a cycle");
+                ancestor = world.get::<Parent>(entity).map(|p| p.0);
+            }
+            world.insert(child, Parent(parent));
+        }
+        None => {
+            world.remove::<Parent>(child);
+        }
+    }
+}
+
+// The entity's parent, if it's still alive
+pub fn parent(world: &World, entity: Entity) -> Option<Entity> {
+    world.get::<Parent>(entity).map(|p| p.0).filter(|&p| world.is_alive(p))
+}
+
+// Local to world space for the entity, only recomputed if its transform or
+// one of its ancestors' changed since the last call. Identity for entities
+// without a Transform2D.
+pub fn world_matrix(world: &mut World, entity: Entity) -> Matrix3<f32> {
+    resolve(world, entity, 0).map(|(matrix, _)| matrix).unwrap_or_else(Matrix3::identity)
+}
+
+pub fn world_position(world: &mut World, entity: Entity) -> Vector2<f32> {
+    let matrix = world_matrix(world, entity);
+    Vector2::new(matrix.z.x, matrix.z.y)
+}
+
+// Returns the world matrix and its version
+fn resolve(world: &mut World, entity: Entity, depth: u32) -> Option<(Matrix3<f32>, u32)> {
+    assert!(depth < MAX_DEPTH, "Transform hierarchy is too deep or has a cycle");
+    let parent = world.get::<Parent>(entity).map(|p| p.0);
+    let parent_world = parent.and_then(|p| resolve(world, p, depth + 1).map(|(matrix, version)| (p, matrix, version)));
+
+    let transform = world.get_mut::<Transform2D>(entity)?;
+    let computed_from = parent_world.map(|(p, _, version)| (p, version));
+    if transform.dirty || transform.computed_from != computed_from {
+        let local = transform.local_matrix();
+        transform.world = match parent_world {
+            Some((_, parent_matrix, _)) => parent_matrix * local,
+            None => local,
+        };
+        transform.dirty = false;
+        transform.computed_from = computed_from;
+        transform.version = transform.version.wrapping_add(1);
+    }
+    Some((transform.world, transform.version))
+}
+
+// Bring every cached world matrix up to date, as a system to run after
+// everything that moves entities
+pub fn update_transforms(world: &mut World, _commands: &mut Commands, _dt: f32) {
+    let entities: Vec<Entity> = world.query::<&Transform2D>().map(|(entity, _)| entity).collect();
+    for entity in entities {
+        resolve(world, entity, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use transform::*;
+
+    fn assert_near(a: Vector2<f32>, b: Vector2<f32>) {
+        assert!((a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let mut world = World::new();
+        let body = world.spawn((Transform2D::new(Vector2::new(100.0, 50.0)).scale(Vector2::new(2.0, 2.0)),));
+        let arm = world.spawn((Transform2D::new(Vector2::new(10.0, 0.0)).rotate(90.0),));
+        let hand = world.spawn((Transform2D::new(Vector2::new(5.0, 0.0)),));
+        set_parent(&mut world, arm, Some(body));
+        set_parent(&mut world, hand, Some(arm));
+
+        // Scaled by the body, then turned by the arm so +x points down
+        assert_near(world_position(&mut world, arm), Vector2::new(120.0, 50.0));
+        assert_near(world_position(&mut world, hand), Vector2::new(120.0, 60.0));
+        assert!((matrix_rotation(&world_matrix(&mut world, hand)) - 90.0).abs() < 1e-3);
+
+        // Moving the body moves the hand, without touching the hand
+        world.get_mut::<Transform2D>(body).unwrap().translate(Vector2::new(-100.0, 0.0));
+        assert_near(world_position(&mut world, hand), Vector2::new(20.0, 60.0));
+
+        // Nothing changed, so nothing is recomputed
+        let version = world.get::<Transform2D>(hand).unwrap().version;
+        world_matrix(&mut world, hand);
+        assert_eq!(world.get::<Transform2D>(hand).unwrap().version, version);
+
+        // Detaching leaves the local transform as the world transform
+        set_parent(&mut world, arm, None);
+        assert_near(world_position(&mut world, hand), Vector2::new(10.0, 5.0));
+
+        // Children of despawned parents become roots
+        world.despawn(arm);
+        assert_eq!(parent(&world, hand), None);
+        assert_near(world_position(&mut world, hand), Vector2::new(5.0, 0.0));
+    }
+
+    #[test]
+    fn test_update_transforms() {
+        let mut world = World::new();
+        let body = world.spawn((Transform2D::new(Vector2::new(1.0, 2.0)),));
+        let hand = world.spawn((Transform2D::new(Vector2::new(3.0, 0.0)), Parent(body)));
+        update_transforms(&mut world, &mut Commands::new(), 0.0);
+        let matrix = world.get::<Transform2D>(hand).unwrap().cached_world_matrix();
+        assert_near(transform_point(&matrix, Vector2::new(0.0, 0.0)), Vector2::new(4.0, 2.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parent_cycle() {
+        let mut world = World::new();
+        let a = world.spawn((Transform2D::new(Vector2::new(0.0, 0.0)),));
+        let b = world.spawn((Transform2D::new(Vector2::new(0.0, 0.0)), Parent(a)));
+        set_parent(&mut world, a, Some(b));
+    }
+}
@@ -0,0 +1,306 @@
+use std::fs::File;
+use std::io::Read;
+
+use gl;
+use toml;
+use sdl2::VideoSubsystem;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::video::{self, FullscreenType, GLContext, GLProfile};
+
+use camera::Viewport;
+use path::*;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowMode {
+    Windowed,
+    // Exclusive fullscreen, changes the display mode to the window size
+    Fullscreen,
+    // Fullscreen window at the desktop resolution
+    Borderless,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    pub resizable: bool,
+    pub mode: WindowMode,
+    pub vsync: bool,
+    // Use the full pixel resolution of high-DPI displays
+    pub high_dpi: bool,
+    // Fixed size everything is rendered at, scaled to fit the window with
+    // black bars where the aspect ratios differ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_resolution: Option<[u32; 2]>,
+    // Only scale the virtual resolution by whole numbers, for crisp pixel art
+    pub integer_scale: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            title: "gengine".to_string(),
+            width: 800,
+            height: 600,
+            resizable: true,
+            mode: WindowMode::Windowed,
+            vsync: true,
+            high_dpi: true,
+            virtual_resolution: None,
+            integer_scale: false,
+        }
+    }
+}
+
+impl WindowConfig {
+    pub fn from_str(source: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(source)
+    }
+
+    // Load from a .toml file relative to the assets folder
+    pub fn from_file(path: &str) -> Self {
+        let mut source = String::new();
+        File::open(asset_path(path)).and_then(|mut f| f.read_to_string(&mut source))
+            .unwrap_or_else(|e| panic!("Couldn't read window config {}: {}", path, e));
+        WindowConfig::from_str(&source).unwrap_or_else(|e| panic!("{}: {}", path, e))
+    }
+}
+
+// The largest rectangle with the aspect ratio of `virtual_size` that fits in
+// `window_size`, centred. Both sizes and the result are in pixels.
+pub fn letterbox(window_size: (u32, u32), virtual_size: (u32, u32), integer_scale: bool) -> Viewport {
+    let (window_w, window_h) = (window_size.0 as f32, window_size.1 as f32);
+    let (virtual_w, virtual_h) = (virtual_size.0.max(1) as f32, virtual_size.1.max(1) as f32);
+    let mut scale = (window_w / virtual_w).min(window_h / virtual_h);
+    if integer_scale && scale >= 1.0 {
+        scale = scale.floor();
+    }
+    let (width, height) = ((virtual_w * scale).round(), (virtual_h * scale).round());
+    Viewport::new(((window_w - width) * 0.5).floor(), ((window_h - height) * 0.5).floor(), width, height)
+}
+
+// The SDL window and its GL context
+struct GlWindow {
+    // Declared before the window so it's dropped first
+    context: GLContext,
+    window: video::Window,
+    video: VideoSubsystem,
+}
+
+pub struct Window {
+    // None when running headless
+    gl: Option<GlWindow>,
+    config: WindowConfig,
+    drawable_size: (u32, u32),
+}
+
+impl Window {
+    // Open the window and make a GL 3.3 core context current for it
+    pub fn new(video: &VideoSubsystem, config: WindowConfig) -> Self {
+        let gl_attr = video.gl_attr();
+        gl_attr.set_context_profile(GLProfile::Core);
+        gl_attr.set_context_version(3, 3);
+
+        let mut builder = video.window(&config.title, config.width, config.height);
+        builder.position_centered().opengl();
+        if config.resizable {
+            builder.resizable();
+        }
+        if config.high_dpi {
+            builder.allow_highdpi();
+        }
+        match config.mode {
+            WindowMode::Windowed => {}
+            WindowMode::Fullscreen => { builder.fullscreen(); }
+            WindowMode::Borderless => { builder.fullscreen_desktop(); }
+        }
+        let window = builder.build().unwrap_or_else(|e| panic!("Couldn't create window: {}", e));
+
+        let context = window.gl_create_context().unwrap();
+        window.gl_make_current(&context).unwrap();
+        gl::load_with(|name| video.gl_get_proc_address(name) as *const _);
+
+        debug_assert_eq!(gl_attr.context_profile(), GLProfile::Core);
+        debug_assert_eq!(gl_attr.context_version(), (3, 3));
+
+        let drawable_size = window.drawable_size();
+        let mut window = Window {
+            gl: Some(GlWindow { context, window, video: video.clone() }),
+            config,
+            drawable_size,
+        };
+        let vsync = window.config.vsync;
+        window.set_vsync(vsync);
+        window
+    }
+
+    // A stand-in that never opens, keeping the configured size. There is no
+    // GL context, so nothing may call into GL.
+    pub fn headless(config: WindowConfig) -> Self {
+        let drawable_size = (config.width, config.height);
+        Window { gl: None, config, drawable_size }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.gl.is_none()
+    }
+
+    pub fn config(&self) -> &WindowConfig {
+        &self.config
+    }
+
+    pub fn sdl_window(&self) -> Option<&video::Window> {
+        self.gl.as_ref().map(|gl| &gl.window)
+    }
+
+    // In screen coordinates, the same space as mouse positions
+    pub fn size(&self) -> (u32, u32) {
+        match self.gl {
+            Some(ref gl) => gl.window.size(),
+            None => self.drawable_size,
+        }
+    }
+
+    // In pixels, larger than size() on high-DPI displays
+    pub fn drawable_size(&self) -> (u32, u32) {
+        self.drawable_size
+    }
+
+    pub fn dpi_scale(&self) -> f32 {
+        let (width, _) = self.size();
+        self.drawable_size.0 as f32 / width.max(1) as f32
+    }
+
+    // Size of the frame the game renders: the virtual resolution if set,
+    // otherwise the whole window in pixels. Cameras, render targets and
+    // post-processing should use this.
+    pub fn render_size(&self) -> (u32, u32) {
+        match self.config.virtual_resolution {
+            Some([width, height]) => (width, height),
+            None => self.drawable_size,
+        }
+    }
+
+    // Where the rendered frame is shown in the window, in pixels with the
+    // origin at the top left
+    pub fn letterbox(&self) -> Viewport {
+        letterbox(self.drawable_size, self.render_size(), self.config.integer_scale)
+    }
+
+    // letterbox() in GL conventions (origin at the bottom left), as
+    // (x, y, width, height)
+    pub fn present_rect(&self) -> (i32, i32, u32, u32) {
+        let vp = self.letterbox();
+        (vp.x as i32, (self.drawable_size.1 as f32 - vp.y - vp.height) as i32, vp.width as u32, vp.height as u32)
+    }
+
+    // Convert a mouse position to render pixels, e.g. before passing it to
+    // Camera2D::mouse_to_world. Positions on the black bars end up outside
+    // the render size.
+    pub fn to_render_coords(&self, pos: (i32, i32)) -> (i32, i32) {
+        let vp = self.letterbox();
+        let (render_w, render_h) = self.render_size();
+        let dpi = self.dpi_scale();
+        let x = (pos.0 as f32 * dpi - vp.x) * render_w as f32 / vp.width.max(1.0);
+        let y = (pos.1 as f32 * dpi - vp.y) * render_h as f32 / vp.height.max(1.0);
+        (x.floor() as i32, y.floor() as i32)
+    }
+
+    // Keep track of size changes, returns true if the render size changed
+    // and cameras and render targets need resizing
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let gl = match self.gl {
+            Some(ref gl) => gl,
+            None => return false,
+        };
+        match *event {
+            Event::Window { win_event: WindowEvent::SizeChanged(..), window_id, .. }
+                if window_id == gl.window.id() => {
+                let old_render_size = self.render_size();
+                self.drawable_size = gl.window.drawable_size();
+                self.render_size() != old_render_size
+            }
+            _ => false,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: WindowMode) {
+        if let Some(ref mut gl) = self.gl {
+            let fullscreen = match mode {
+                WindowMode::Windowed => FullscreenType::Off,
+                WindowMode::Fullscreen => FullscreenType::True,
+                WindowMode::Borderless => FullscreenType::Desktop,
+            };
+            if let Err(e) = gl.window.set_fullscreen(fullscreen) {
+                eprintln!("Couldn't switch window to {:?}: {}", mode, e);
+                return;
+            }
+        }
+        self.config.mode = mode;
+    }
+
+    // Between windowed and borderless fullscreen
+    pub fn toggle_fullscreen(&mut self) {
+        let mode = match self.config.mode {
+            WindowMode::Windowed => WindowMode::Borderless,
+            _ => WindowMode::Windowed,
+        };
+        self.set_mode(mode);
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) {
+        if let Some(ref gl) = self.gl {
+            if !gl.video.gl_set_swap_interval(if vsync { 1 } else { 0 }) {
+                eprintln!("Couldn't {} vsync", if vsync { "enable" } else { "disable" });
+            }
+        }
+        self.config.vsync = vsync;
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        if let Some(ref mut gl) = self.gl {
+            gl.window.set_title(title).unwrap();
+        }
+        self.config.title = title.to_string();
+    }
+
+    pub fn swap(&self) {
+        if let Some(ref gl) = self.gl {
+            gl.window.gl_swap_window();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use window::*;
+
+    #[test]
+    fn test_window_config() {
+        let config = WindowConfig::from_str(r#"
+            title = "Test"
+            mode = "borderless"
+            virtual_resolution = [320, 180]
+        "#).unwrap();
+        assert_eq!(config.title, "Test");
+        assert_eq!(config.mode, WindowMode::Borderless);
+        assert_eq!(config.virtual_resolution, Some([320, 180]));
+        // Everything else keeps its default
+        assert_eq!((config.width, config.height, config.vsync), (800, 600, true));
+    }
+
+    #[test]
+    fn test_letterbox() {
+        // Wider window, bars on the sides
+        assert_eq!(letterbox((1000, 600), (800, 600), false), Viewport::new(100.0, 0.0, 800.0, 600.0));
+        // Taller window, bars on the top and bottom
+        assert_eq!(letterbox((640, 720), (320, 180), false), Viewport::new(0.0, 180.0, 640.0, 360.0));
+        // 2.5x rounds down to 2x
+        assert_eq!(letterbox((800, 450), (320, 180), true), Viewport::new(80.0, 45.0, 640.0, 360.0));
+        // Smaller than the virtual resolution still shrinks to fit
+        assert_eq!(letterbox((160, 90), (320, 180), true), Viewport::new(0.0, 0.0, 160.0, 90.0));
+    }
+}